@@ -1,12 +1,37 @@
+use crate::error::FlvError;
 use crate::exchange::{AudioCodecConfig, MseDecoderConfig, Packed, PackedContent, PackedContentToCore, RemuxedData, VideoCodecConfig};
-use std::collections::VecDeque;
+use crate::flv::event::ScriptEvent;
+use std::collections::{BTreeMap, VecDeque};
+
+/// The codec strings of the tracks in the initialization segment.
+/// None for a track type which the output does not have.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackCodecs {
+    pub audio: Option<String>,
+    pub video: Option<String>,
+}
+
+impl TrackCodecs {
+    /// The mime type for e.g. `MediaSource.isTypeSupported`, like `video/mp4; codecs="avc1.64001E, mp4a.40.2"`.
+    /// An audio-only output is `audio/mp4`.
+    pub fn mime_type(&self) -> String {
+        let container = if self.video.is_some() { "video/mp4" } else { "audio/mp4" };
+        let codecs = self.video.iter().chain(self.audio.iter()).cloned().collect::<Vec<_>>();
+        format!("{}; codecs=\"{}\"", container, codecs.join(", "))
+    }
+}
 
 pub struct Core {
     pub buffer: VecDeque<RemuxedData>,
     pub pack_buffer: VecDeque<Packed>,
-
-    audio_codec_conf: Option<AudioCodecConfig>,
-    video_codec_conf: Option<VideoCodecConfig>,
+    /// Script tags other than onMetaData, in the order they arrived.
+    pub events: VecDeque<ScriptEvent>,
+
+    // keyed by the flv track id.
+    audio_codec_confs: BTreeMap<u8, AudioCodecConfig>,
+    video_codec_confs: BTreeMap<u8, VideoCodecConfig>,
+    /// The initialization segment has arrived, so every track it has is configured.
+    header_received: bool,
 }
 
 impl Core {
@@ -14,8 +39,10 @@ impl Core {
         Self {
             buffer: VecDeque::new(),
             pack_buffer: VecDeque::new(),
-            audio_codec_conf: None,
-            video_codec_conf: None,
+            events: VecDeque::new(),
+            audio_codec_confs: BTreeMap::new(),
+            video_codec_confs: BTreeMap::new(),
+            header_received: false,
         }
     }
 
@@ -23,64 +50,141 @@ impl Core {
         self.pack_buffer.push_back(pack);
     }
 
-    pub fn process_incoming(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    /// Drop the media fragments and the script events which have not been consumed yet.
+    /// Headers, reinitialisations and codec configurations are kept.
+    pub(crate) fn discard_media(&mut self) {
+        self.pack_buffer.retain(|pack| !matches!(
+            pack.packed_content,
+            PackedContent::ToCore(PackedContentToCore::Data(RemuxedData::Audio(_) | RemuxedData::Video(_)) | PackedContentToCore::ScriptEvent(_))
+        ));
+        self.buffer.retain(|data| matches!(data, RemuxedData::Header(_) | RemuxedData::Reinitialization(_)));
+        self.events.clear();
+    }
+
+    pub fn process_incoming(&mut self) -> Result<(), FlvError> {
         while let Some(data) = self.pack_buffer.pop_front() {
             match data.packed_content {
                 PackedContent::ToCore(PackedContentToCore::Data(data)) => {
+                    if let RemuxedData::Header(_) = data {
+                        self.header_received = true;
+                    }
                     self.buffer.push_back(data);
                 }
                 PackedContent::ToCore(PackedContentToCore::DecoderConfig(conf)) => {
                     match conf {
                         MseDecoderConfig::AudioCodec(audio_codec) => {
-                            self.audio_codec_conf = Some(audio_codec);
+                            self.audio_codec_confs.insert(audio_codec.track_id, audio_codec);
                         }
                         MseDecoderConfig::VideoCodec(video_codec) => {
-                            self.video_codec_conf = Some(video_codec);
+                            self.video_codec_confs.insert(video_codec.track_id, video_codec);
                         }
                     }
                 }
+                PackedContent::ToCore(PackedContentToCore::ScriptEvent(event)) => {
+                    self.events.push_back(event);
+                }
+                PackedContent::ToCore(PackedContentToCore::Reinitialize) => {
+                    // the new codec configuration has been sent in front of this.
+                    let codecs = TrackCodecs {
+                        audio: self.get_audio_codec_conf(),
+                        video: self.get_video_codec_conf(),
+                    };
+                    self.buffer.push_back(RemuxedData::Reinitialization(codecs));
+                }
                 _ => {}
             };
         };
         Ok(())
     }
+
+    /// Take the next script event, e.g. a cue point, a caption or a timecode.
+    /// Events come out separately from the media, place them by their `presentation_time`.
+    pub fn consume_event(&mut self) -> Result<Option<ScriptEvent>, FlvError> {
+        self.process_incoming()?;
+        Ok(self.events.pop_front())
+    }
 }
 
 impl IConsumable for Core {
     type ConsumerData = RemuxedData;
 
-    fn consume(&mut self) -> Result<RemuxedData, Box<dyn std::error::Error>> {
+    fn consume(&mut self) -> Result<RemuxedData, FlvError> {
         self.process_incoming()?;
 
         if let Some(data) = self.buffer.pop_front() {
             Ok(data)
         } else {
-            Err("No data available".into())
+            Err(FlvError::internal("No data available"))
         }
     }
 }
 
 impl Core {
+    /// The codec of the first audio track.
     pub fn get_audio_codec_conf(&mut self) -> Option<String> {
-        match self.audio_codec_conf {
-            Some(ref mut conf) => Some(conf.audio_conf()),
+        match self.audio_codec_confs.values_mut().next() {
+            Some(conf) => conf.audio_conf().ok(),
             None => None
         }
     }
 
+    /// The codec of the first video track.
     pub fn get_video_codec_conf(&mut self) -> Option<String> {
-        match self.video_codec_conf {
-            Some(ref mut conf) => Some(conf.video_conf()),
+        match self.video_codec_confs.values_mut().next() {
+            Some(conf) => Some(conf.video_conf()),
             None => None
         }
     }
 
+    /// The codecs of every audio track, with their flv track id.
+    pub fn get_audio_codec_confs(&mut self) -> Vec<(u8, String)> {
+        self.audio_codec_confs.iter_mut()
+            .filter_map(|(track_id, conf)| Some((*track_id, conf.audio_conf().ok()?)))
+            .collect()
+    }
+
+    /// The codecs of every video track, with their flv track id.
+    pub fn get_video_codec_confs(&mut self) -> Vec<(u8, String)> {
+        self.video_codec_confs.iter_mut()
+            .map(|(track_id, conf)| (*track_id, conf.video_conf()))
+            .collect()
+    }
+
+    /// Whether every track of the output has its codec configuration,
+    /// which is the case once the initialization segment is out, whichever tracks it has.
     pub fn is_codec_configured(&self) -> bool {
-        self.audio_codec_conf.is_some() && self.video_codec_conf.is_some()
+        self.header_received
+    }
+
+    /// Returns the codecs of the output once the initialization segment is out,
+    /// with only the tracks it has, e.g. just the audio of a radio stream.
+    /// This method will not block.
+    pub fn try_get_codecs(&mut self) -> Option<TrackCodecs> {
+        // the codec configurations are sent in front of the header.
+        self.process_incoming().ok()?;
+        if !self.header_received {
+            return None;
+        }
+        Some(TrackCodecs {
+            audio: self.get_audio_codec_conf(),
+            video: self.get_video_codec_conf(),
+        })
+    }
+
+    /// Returns the codecs of the output, with only the tracks it has.
+    /// This method will block until the initialization segment is out.
+    pub fn get_codecs(&mut self) -> Result<TrackCodecs, FlvError> {
+        loop {
+            self.process_incoming()?;
+            if let Some(codecs) = self.try_get_codecs() {
+                return Ok(codecs);
+            }
+        }
     }
 
     /// Returns the codec configuration if it is already set
     /// Returns a tuple of audio and video codec configuration in String.
+    /// Both tracks are required, use `try_get_codecs` for an output with a single track.
     /// If the codec configuration is not set, returns None.
     /// This method will not block.
     pub fn try_get_codec_conf(&mut self) -> Option<(String, String)> {
@@ -91,20 +195,20 @@ impl Core {
     }
 
     /// Returns the codec configuration. This method will block until the codec configuration is ready.
-    pub fn get_codec_conf(&mut self) -> Result<(String, String), Box<dyn std::error::Error>> {
+    /// Fails once it is clear that the output lacks the audio or the video, use `get_codecs` for that.
+    pub fn get_codec_conf(&mut self) -> Result<(String, String), FlvError> {
         // todo: [OPTIMIZATION REQUIRED] this is a blocking call. use try_get_codec_conf instead.
-        self.process_incoming()?;
-
         loop {
-            match self.try_get_codec_conf() {
-                Some(conf) => return Ok(conf),
-                None => {}
+            self.process_incoming()?;
+            if self.is_codec_configured() {
+                return self.try_get_codec_conf()
+                    .ok_or(FlvError::internal("The output does not have both an audio and a video track, use get_codecs instead."));
             }
         }
     }
 
     /// Returns the codec configuration with a timeout.
-    pub fn get_codec_conf_with_timeout(&mut self, timeout: std::time::Duration) -> Result<(String, String), Box<dyn std::error::Error>> {
+    pub fn get_codec_conf_with_timeout(&mut self, timeout: std::time::Duration) -> Result<(String, String), FlvError> {
         let start = std::time::Instant::now();
         while start.elapsed() < timeout {
             match self.try_get_codec_conf() {
@@ -112,7 +216,7 @@ impl Core {
                 None => {}
             }
         };
-        Err("Unable to get codec configuration. Time limit exceeded. ".into())
+        Err(FlvError::internal("Unable to get codec configuration. Time limit exceeded."))
     }
 
     /// Returns the codec configuration with a default value if the codec configuration is not ready.
@@ -130,6 +234,6 @@ impl Core {
 
 pub trait IConsumable {
     type ConsumerData;
-    fn consume(&mut self) -> Result<Self::ConsumerData, Box<dyn std::error::Error>>;
+    fn consume(&mut self) -> Result<Self::ConsumerData, FlvError>;
 }
 
@@ -0,0 +1,163 @@
+use std::fmt::{Display, Formatter};
+
+/// Where in the stream an error happened.
+/// Every part is optional, as not every stage knows all of them:
+/// the decoder knows the byte offset and the tag index, the remuxer only knows the timestamp.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ErrorContext {
+    pub byte_offset: Option<u64>,
+    pub tag_index: Option<u64>,
+    pub timestamp: Option<u32>,
+}
+
+impl ErrorContext {
+    /// Fill in the parts of the context that are still unknown.
+    /// Parts which are already set are kept, as they are usually more precise.
+    fn merge(&mut self, other: ErrorContext) {
+        self.byte_offset = self.byte_offset.or(other.byte_offset);
+        self.tag_index = self.tag_index.or(other.tag_index);
+        self.timestamp = self.timestamp.or(other.timestamp);
+    }
+}
+
+impl Display for ErrorContext {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut parts = vec![];
+        if let Some(byte_offset) = self.byte_offset {
+            parts.push(format!("byte offset {}", byte_offset));
+        }
+        if let Some(tag_index) = self.tag_index {
+            parts.push(format!("tag #{}", tag_index));
+        }
+        if let Some(timestamp) = self.timestamp {
+            parts.push(format!("timestamp {}ms", timestamp));
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+#[derive(Debug)]
+pub enum FlvError {
+    /// Reading from the underlying source failed.
+    Io { source: std::io::Error, context: ErrorContext },
+    /// Not enough bytes are buffered yet.
+    /// This is recoverable: push more data and try again.
+    InsufficientData { required: usize, available: usize, context: ErrorContext },
+    /// The data is not valid flv.
+    MalformedFlv { reason: String, context: ErrorContext },
+    /// The data is valid, but uses a codec or a feature which is not supported.
+    UnsupportedCodec { codec: String, context: ErrorContext },
+    /// Script data can not be decoded as AMF.
+    AmfDecode { reason: String, context: ErrorContext },
+    /// The pipeline is in a state where the operation is not possible.
+    InternalState { reason: String, context: ErrorContext },
+}
+
+impl FlvError {
+    pub fn insufficient_data(required: usize, available: usize) -> Self {
+        FlvError::InsufficientData { required, available, context: ErrorContext::default() }
+    }
+
+    pub fn malformed(reason: impl Into<String>) -> Self {
+        FlvError::MalformedFlv { reason: reason.into(), context: ErrorContext::default() }
+    }
+
+    pub fn unsupported_codec(codec: impl Into<String>) -> Self {
+        FlvError::UnsupportedCodec { codec: codec.into(), context: ErrorContext::default() }
+    }
+
+    pub fn amf(reason: impl Into<String>) -> Self {
+        FlvError::AmfDecode { reason: reason.into(), context: ErrorContext::default() }
+    }
+
+    pub fn internal(reason: impl Into<String>) -> Self {
+        FlvError::InternalState { reason: reason.into(), context: ErrorContext::default() }
+    }
+
+    #[inline]
+    pub fn is_insufficient_data(&self) -> bool {
+        matches!(self, FlvError::InsufficientData { .. })
+    }
+
+    pub fn context(&self) -> &ErrorContext {
+        match self {
+            FlvError::Io { context, .. } => context,
+            FlvError::InsufficientData { context, .. } => context,
+            FlvError::MalformedFlv { context, .. } => context,
+            FlvError::UnsupportedCodec { context, .. } => context,
+            FlvError::AmfDecode { context, .. } => context,
+            FlvError::InternalState { context, .. } => context,
+        }
+    }
+
+    fn context_mut(&mut self) -> &mut ErrorContext {
+        match self {
+            FlvError::Io { context, .. } => context,
+            FlvError::InsufficientData { context, .. } => context,
+            FlvError::MalformedFlv { context, .. } => context,
+            FlvError::UnsupportedCodec { context, .. } => context,
+            FlvError::AmfDecode { context, .. } => context,
+            FlvError::InternalState { context, .. } => context,
+        }
+    }
+
+    /// Attach the byte offset, unless a more precise one is already known.
+    pub fn at_offset(mut self, byte_offset: u64) -> Self {
+        self.context_mut().merge(ErrorContext { byte_offset: Some(byte_offset), ..Default::default() });
+        self
+    }
+
+    /// Attach the index of the tag being processed, unless it is already known.
+    pub fn at_tag(mut self, tag_index: u64) -> Self {
+        self.context_mut().merge(ErrorContext { tag_index: Some(tag_index), ..Default::default() });
+        self
+    }
+
+    /// Attach the timestamp of the tag being processed, unless it is already known.
+    pub fn at_timestamp(mut self, timestamp: u32) -> Self {
+        self.context_mut().merge(ErrorContext { timestamp: Some(timestamp), ..Default::default() });
+        self
+    }
+}
+
+impl Display for FlvError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FlvError::Io { source, .. } => write!(f, "I/O error: {}", source)?,
+            FlvError::InsufficientData { required, available, .. } => {
+                write!(f, "Insufficient data for decoding. Expected minimum buffer size {}, real size {}.", required, available)?
+            }
+            FlvError::MalformedFlv { reason, .. } => write!(f, "Malformed flv: {}", reason)?,
+            FlvError::UnsupportedCodec { codec, .. } => write!(f, "Unsupported codec: {}", codec)?,
+            FlvError::AmfDecode { reason, .. } => write!(f, "AMF decode error: {}", reason)?,
+            FlvError::InternalState { reason, .. } => write!(f, "Internal state error: {}", reason)?,
+        }
+
+        let context = self.context();
+        if *context != ErrorContext::default() {
+            write!(f, " ({})", context)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for FlvError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FlvError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for FlvError {
+    fn from(source: std::io::Error) -> Self {
+        FlvError::Io { source, context: ErrorContext::default() }
+    }
+}
+
+impl From<std::string::FromUtf8Error> for FlvError {
+    fn from(error: std::string::FromUtf8Error) -> Self {
+        FlvError::amf(format!("Invalid utf-8 string: {}", error))
+    }
+}
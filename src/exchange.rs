@@ -1,6 +1,10 @@
+use crate::core::TrackCodecs;
+use crate::error::FlvError;
+use crate::flv::event::ScriptEvent;
 use crate::flv::header::FlvHeader;
 use crate::flv::meta::RawMetaData;
 use crate::flv::tag::Tag;
+use crate::flv::timestamp::TimestampDiscontinuity;
 use crate::fmpeg::remux_context::AudioCodecType;
 use std::collections::{HashMap, VecDeque};
 use std::fmt::Debug;
@@ -88,15 +92,16 @@ impl Exchange {
         self.channels.insert(registry.get_self_as_destination(), registry.get_sender());
     }
 
-    pub fn process_incoming(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn process_incoming(&mut self) -> Result<(), FlvError> {
         if let Ok(received) = self.receiver.recv() {
             let routing = received.packed_routing;
             self.channels
                 .get(&routing)
-                .unwrap()
-                .send(received.packed_content)?;
+                .ok_or(FlvError::internal("[Exchange] No channel registered for the destination."))?
+                .send(received.packed_content)
+                .map_err(|_| FlvError::internal("[Exchange] Receiver closed."))?;
         } else {
-            return Err("[Exchange] Channel closed.".into());
+            return Err(FlvError::internal("[Exchange] Channel closed."));
         }
         Ok(())
     }
@@ -125,6 +130,9 @@ pub enum PackedContent {
 pub enum PackedContentToCore {
     Data(RemuxedData),
     DecoderConfig(MseDecoderConfig),
+    ScriptEvent(ScriptEvent),
+    /// A codec changed, the core announces it with the codecs it has by then.
+    Reinitialize,
     Command,
 }
 
@@ -133,6 +141,13 @@ pub enum RemuxedData {
     Audio(Vec<u8>),
     Video(Vec<u8>),
     EndOfSequence(EndOfSequenceType),
+    /// The timestamps of the following fragments do not continue the ones before.
+    /// Everything buffered before the break has been flushed in front of it.
+    Discontinuity(TimestampDiscontinuity),
+    /// A codec changed mid-stream, e.g. after the publisher restarted its encoder.
+    /// The next `Header` is a new initialization segment for these codecs, so the consumer has to
+    /// reinitialise its decoder first, e.g. with `SourceBuffer.changeType`.
+    Reinitialization(TrackCodecs),
 }
 
 pub enum EndOfSequenceType {
@@ -153,6 +168,8 @@ pub struct AudioCodecConfig {
 
     pub audio_codec_type: AudioCodecType,
     pub audio_object_type: u8,
+    /// The flv track id, 0 unless the stream is multitrack.
+    pub track_id: u8,
 }
 
 impl AudioCodecConfig {
@@ -161,22 +178,40 @@ impl AudioCodecConfig {
             conf_string: "".to_string(),
             audio_codec_type: codec_type,
             audio_object_type: object_type,
+            track_id: 0,
         }
     }
 
-    pub fn audio_conf(&mut self) -> String {
+    pub fn with_track_id(mut self, track_id: u8) -> Self {
+        self.track_id = track_id;
+        self
+    }
+
+    pub fn audio_conf(&mut self) -> Result<String, FlvError> {
         match self.audio_codec_type {
             AudioCodecType::Aac => {
                 if self.conf_string.is_empty() {
                     self.conf_string = format!("mp4a.40.{}", self.audio_object_type);
                 }
-                self.conf_string.clone()
+                Ok(self.conf_string.clone())
             }
             AudioCodecType::Mp3 => {
-                "mp3".to_string()
+                Ok("mp3".to_string())
+            }
+            AudioCodecType::Opus => {
+                Ok("opus".to_string())
+            }
+            AudioCodecType::Flac => {
+                Ok("flac".to_string())
+            }
+            AudioCodecType::Ac3 => {
+                Ok("ac-3".to_string())
+            }
+            AudioCodecType::Eac3 => {
+                Ok("ec-3".to_string())
             }
             AudioCodecType::None => {
-                panic!("No audio codec type specified.")
+                Err(FlvError::internal("No audio codec type specified."))
             }
         }
     }
@@ -188,6 +223,8 @@ pub struct VideoCodecConfig {
     pub avc_profile_indication: u8,
     pub avc_profile_compatibility: u8,
     pub avc_level_indication: u8,
+    /// The flv track id, 0 unless the stream is multitrack.
+    pub track_id: u8,
 }
 
 impl VideoCodecConfig {
@@ -197,11 +234,83 @@ impl VideoCodecConfig {
             avc_profile_indication: profile_indication,
             avc_profile_compatibility: profile_compatibility,
             avc_level_indication: level_indication,
+            track_id: 0,
         }
     }
 
+    pub fn with_track_id(mut self, track_id: u8) -> Self {
+        self.track_id = track_id;
+        self
+    }
+
+    /// For codecs other than avc, whose codec string is built from their configuration record.
+    pub fn from_conf_string(conf_string: String) -> VideoCodecConfig {
+        Self {
+            conf_string,
+            avc_profile_indication: 0,
+            avc_profile_compatibility: 0,
+            avc_level_indication: 0,
+            track_id: 0,
+        }
+    }
+
+    /// `hvc1.<profile>.<compatibility>.<tier><level>.<constraints>`, from an HEVCDecoderConfigurationRecord.
+    pub fn from_hvcc(record: &[u8]) -> Option<VideoCodecConfig> {
+        Self::from_hvcc_as(record, "hvc1")
+    }
+
+    /// Like `from_hvcc`, for a sample entry of type `sample_entry_type`, i.e. `hvc1` or `hev1`.
+    pub fn from_hvcc_as(record: &[u8], sample_entry_type: &str) -> Option<VideoCodecConfig> {
+        if record.len() < 13 {
+            return None;
+        }
+        let profile_space = ["", "A", "B", "C"][(record[1] >> 6) as usize];
+        let tier = if record[1] & 0x20 != 0 { 'H' } else { 'L' };
+        let profile_idc = record[1] & 0x1F;
+        // the compatibility flags are written in reverse bit order.
+        let compatibility = u32::from_be_bytes([record[2], record[3], record[4], record[5]]).reverse_bits();
+        let level_idc = record[12];
+
+        let mut conf = format!("{}.{}{}.{:X}.{}{}", sample_entry_type, profile_space, profile_idc, compatibility, tier, level_idc);
+        // trailing zero bytes of the constraint flags are left out.
+        let constraints = &record[6..12];
+        let used = constraints.iter().rposition(|byte| *byte != 0).map_or(0, |last| last + 1);
+        for byte in &constraints[..used] {
+            conf.push_str(&format!(".{:X}", byte));
+        }
+        Some(Self::from_conf_string(conf))
+    }
+
+    /// `av01.<profile>.<level><tier>.<bit depth>`, from an AV1CodecConfigurationRecord.
+    pub fn from_av1c(record: &[u8]) -> Option<VideoCodecConfig> {
+        if record.len() < 4 {
+            return None;
+        }
+        let profile = record[1] >> 5;
+        let level = record[1] & 0x1F;
+        let tier = if record[2] & 0x80 != 0 { 'H' } else { 'M' };
+        let high_bitdepth = record[2] & 0x40 != 0;
+        let twelve_bit = record[2] & 0x20 != 0;
+        let bit_depth = match (high_bitdepth, profile == 2 && twelve_bit) {
+            (true, true) => 12,
+            (true, false) => 10,
+            _ => 8,
+        };
+        Some(Self::from_conf_string(format!("av01.{}.{:02}{}.{:02}", profile, level, tier, bit_depth)))
+    }
+
+    /// `vp09.<profile>.<level>.<bit depth>`, from a VPCodecConfigurationRecord including its version and flags.
+    pub fn from_vpcc(record: &[u8]) -> Option<VideoCodecConfig> {
+        if record.len() < 7 {
+            return None;
+        }
+        Some(Self::from_conf_string(format!("vp09.{:02}.{:02}.{:02}", record[4], record[5], record[6] >> 4)))
+    }
+
     pub fn video_conf(&mut self) -> String {
-        self.conf_string = format!("avc1.{:02x}{:02x}{:02x}", self.avc_profile_indication, self.avc_profile_compatibility, self.avc_level_indication);
+        if self.conf_string.is_empty() {
+            self.conf_string = format!("avc1.{:02x}{:02x}{:02x}", self.avc_profile_indication, self.avc_profile_compatibility, self.avc_level_indication);
+        }
         self.conf_string.clone()
     }
 }
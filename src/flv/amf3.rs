@@ -0,0 +1,344 @@
+use crate::error::FlvError;
+use crate::flv::decoder::Decoder;
+use crate::flv::script::{ScriptData, ScriptDataDate, ScriptDataEcmaArray, ScriptDataLongString, ScriptDataObject, ScriptDataObjectProp, ScriptDataString, ScriptDataTypedObject, ScriptStrictArray, MAX_RESOLVED_NODES};
+
+/// Externalizable classes which wrap a single AMF3 value, and so can be read without knowing the class.
+const TRANSPARENT_EXTERNALIZABLE_CLASSES: [&str; 2] = ["flex.messaging.io.ArrayCollection", "flex.messaging.io.ObjectProxy"];
+
+#[derive(Debug, Clone)]
+struct Amf3Traits {
+    class_name: String,
+    externalizable: bool,
+    dynamic: bool,
+    members: Vec<String>,
+}
+
+/// The reference tables of an AMF3 value, which start out empty at every AMF0 switch to AMF3.
+///
+/// AMF3 values are mapped onto the AMF0 `ScriptData`:
+/// integers become numbers, arrays become strict arrays, or ecma arrays if they have an associative part,
+/// vectors become strict arrays, and dictionaries become ecma arrays with their keys turned into strings.
+/// Objects, ecma arrays and dictionaries end with an end marker property, just like the AMF0 ones.
+///
+/// Note: a reference is replaced with a copy of the value it points at,
+/// except for one to a value which is still being read, which would never end, and stays `ScriptData::Reference`.
+#[derive(Debug, Clone)]
+pub struct Amf3Context {
+    strings: Vec<String>,
+    // None while the value is still being read.
+    objects: Vec<Option<ScriptData>>,
+    traits: Vec<Amf3Traits>,
+    budget: usize,
+}
+
+impl Default for Amf3Context {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Amf3Context {
+    pub fn new() -> Self {
+        Self {
+            strings: vec![],
+            objects: vec![],
+            traits: vec![],
+            budget: MAX_RESOLVED_NODES,
+        }
+    }
+
+    pub fn parse_value(&mut self, data: &mut Decoder) -> Result<ScriptData, FlvError> {
+        data.enter_script_level()?;
+        let value = self.parse_value_inner(data);
+        data.leave_script_level();
+        value
+    }
+
+    fn parse_value_inner(&mut self, data: &mut Decoder) -> Result<ScriptData, FlvError> {
+        let marker = data.drain_u8()?;
+        let value = match marker {
+            0x00 => ScriptData::Undefined,
+            0x01 => ScriptData::Null,
+            0x02 => ScriptData::Boolean(0),
+            0x03 => ScriptData::Boolean(1),
+            // the 29 bits are signed.
+            0x04 => ScriptData::Number((((Self::parse_u29(data)? << 3) as i32) >> 3) as f64),
+            0x05 => ScriptData::Number(data.drain_f64()?),
+            0x06 => Self::string_value(self.parse_string(data)?),
+            0x07 | 0x0B => self.parse_xml(data)?,
+            0x08 => self.parse_date(data)?,
+            0x09 => self.parse_array(data)?,
+            0x0A => self.parse_object(data)?,
+            0x0C => self.parse_byte_array(data)?,
+            0x0D..=0x10 => self.parse_vector(data, marker)?,
+            0x11 => self.parse_dictionary(data)?,
+            _ => {
+                return Err(FlvError::amf(format!("Unsupported AMF3 type marker {}.", marker)));
+            }
+        };
+        Ok(value)
+    }
+
+    /// A variable length unsigned 29-bit integer:
+    /// 7 bits in each of the first 3 bytes as long as the high bit is set, and 8 in the 4th.
+    pub fn parse_u29(data: &mut Decoder) -> Result<u32, FlvError> {
+        let mut value = 0u32;
+        for _ in 0..3 {
+            let byte = data.drain_u8()?;
+            value = (value << 7) | (byte & 0x7F) as u32;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Ok((value << 8) | data.drain_u8()? as u32)
+    }
+
+    /// Read the U29 in front of a complex value.
+    /// Returns the referenced value for a reference, and the rest of the header otherwise.
+    fn parse_header(&mut self, data: &mut Decoder) -> Result<Result<u32, ScriptData>, FlvError> {
+        let header = Self::parse_u29(data)?;
+        if header & 1 == 0 {
+            return self.object_reference((header >> 1) as usize).map(Err);
+        }
+        Ok(Ok(header >> 1))
+    }
+
+    fn object_reference(&mut self, index: usize) -> Result<ScriptData, FlvError> {
+        match self.objects.get(index) {
+            Some(Some(value)) => {
+                // copies of copies grow exponentially, a few bytes of references could otherwise take all the memory.
+                self.budget = self.budget.checked_sub(value.node_count())
+                    .ok_or(FlvError::amf("Too many values behind AMF3 references."))?;
+                Ok(value.clone())
+            }
+            Some(None) => Ok(ScriptData::Reference(index as u16)),
+            None => Err(FlvError::amf(format!("AMF3 object reference {} is out of range.", index))),
+        }
+    }
+
+    /// Take the next slot in the object table, before anything inside the value is read.
+    #[inline]
+    fn reserve_object(&mut self) -> usize {
+        self.objects.push(None);
+        self.objects.len() - 1
+    }
+
+    #[inline]
+    fn complete_object(&mut self, index: usize, value: ScriptData) -> ScriptData {
+        self.objects[index] = Some(value.clone());
+        value
+    }
+
+    pub fn parse_string(&mut self, data: &mut Decoder) -> Result<String, FlvError> {
+        let header = Self::parse_u29(data)?;
+        if header & 1 == 0 {
+            let index = (header >> 1) as usize;
+            return self.strings.get(index).cloned()
+                .ok_or(FlvError::amf(format!("AMF3 string reference {} is out of range.", index)));
+        }
+        let length = (header >> 1) as usize;
+        let string = String::from_utf8(data.drain_bytes_vec(length)?)?;
+        // the empty string is never referenced.
+        if !string.is_empty() {
+            self.strings.push(string.clone());
+        }
+        Ok(string)
+    }
+
+    fn string_value(string: String) -> ScriptData {
+        match u16::try_from(string.len()) {
+            Ok(length) => ScriptData::String(ScriptDataString { length, data: string }),
+            Err(_) => ScriptData::LongString(ScriptDataLongString { length: string.len() as u32, data: string }),
+        }
+    }
+
+    fn property(name: String, value: ScriptData) -> ScriptDataObjectProp {
+        ScriptDataObjectProp { name: ScriptDataString { length: name.len() as u16, data: name }, value }
+    }
+
+    fn end_marker() -> ScriptDataObjectProp {
+        Self::property(String::new(), ScriptData::ObjectEndMarker)
+    }
+
+    fn parse_xml(&mut self, data: &mut Decoder) -> Result<ScriptData, FlvError> {
+        let length = match self.parse_header(data)? {
+            Ok(length) => length,
+            Err(referenced) => return Ok(referenced),
+        };
+        let index = self.reserve_object();
+        let xml = String::from_utf8(data.drain_bytes_vec(length as usize)?)?;
+        Ok(self.complete_object(index, ScriptData::XmlDocument(ScriptDataLongString { length, data: xml })))
+    }
+
+    fn parse_date(&mut self, data: &mut Decoder) -> Result<ScriptData, FlvError> {
+        if let Err(referenced) = self.parse_header(data)? {
+            return Ok(referenced);
+        }
+        let index = self.reserve_object();
+        // always in utc.
+        let date = data.drain_f64()?;
+        Ok(self.complete_object(index, ScriptData::Date(ScriptDataDate { date, local_time_offset: 0 })))
+    }
+
+    fn parse_array(&mut self, data: &mut Decoder) -> Result<ScriptData, FlvError> {
+        let dense_length = match self.parse_header(data)? {
+            Ok(length) => length,
+            Err(referenced) => return Ok(referenced),
+        };
+        let index = self.reserve_object();
+
+        let mut properties = vec![];
+        loop {
+            let key = self.parse_string(data)?;
+            if key.is_empty() {
+                break;
+            }
+            let value = self.parse_value(data)?;
+            properties.push(Self::property(key, value));
+        }
+        // the length is read from the stream, so do not trust it for pre-allocation.
+        let mut values = vec![];
+        for _ in 0..dense_length {
+            values.push(self.parse_value(data)?);
+        }
+
+        let array = if properties.is_empty() {
+            ScriptData::StrictArray(ScriptStrictArray { length: dense_length, values })
+        } else {
+            // the dense part goes in front, keyed by the index, like an ecma array written by flash.
+            let mut dense: Vec<_> = values.into_iter().enumerate().map(|(i, value)| Self::property(i.to_string(), value)).collect();
+            dense.append(&mut properties);
+            dense.push(Self::end_marker());
+            ScriptData::EcmaArray(ScriptDataEcmaArray { length: dense.len() as u32 - 1, properties: dense })
+        };
+        Ok(self.complete_object(index, array))
+    }
+
+    fn parse_traits(&mut self, data: &mut Decoder, header: u32) -> Result<Amf3Traits, FlvError> {
+        // the low bit of `header` was the object reference flag, which is gone already.
+        if header & 1 == 0 {
+            let index = (header >> 1) as usize;
+            return self.traits.get(index).cloned()
+                .ok_or(FlvError::amf(format!("AMF3 traits reference {} is out of range.", index)));
+        }
+        let externalizable = header & 2 != 0;
+        let dynamic = header & 4 != 0;
+        let member_count = header >> 3;
+        let class_name = self.parse_string(data)?;
+        let mut members = vec![];
+        if !externalizable {
+            for _ in 0..member_count {
+                members.push(self.parse_string(data)?);
+            }
+        }
+        let traits = Amf3Traits { class_name, externalizable, dynamic, members };
+        self.traits.push(traits.clone());
+        Ok(traits)
+    }
+
+    fn parse_object(&mut self, data: &mut Decoder) -> Result<ScriptData, FlvError> {
+        let header = match self.parse_header(data)? {
+            Ok(header) => header,
+            Err(referenced) => return Ok(referenced),
+        };
+        let index = self.reserve_object();
+        let traits = self.parse_traits(data, header)?;
+
+        if traits.externalizable {
+            if !TRANSPARENT_EXTERNALIZABLE_CLASSES.contains(&traits.class_name.as_str()) {
+                return Err(FlvError::amf(format!("Unable to read the externalizable AMF3 class {}.", traits.class_name)));
+            }
+            let value = self.parse_value(data)?;
+            return Ok(self.complete_object(index, value));
+        }
+
+        let mut properties = vec![];
+        for member in traits.members {
+            let value = self.parse_value(data)?;
+            properties.push(Self::property(member, value));
+        }
+        if traits.dynamic {
+            loop {
+                let key = self.parse_string(data)?;
+                if key.is_empty() {
+                    break;
+                }
+                let value = self.parse_value(data)?;
+                properties.push(Self::property(key, value));
+            }
+        }
+        properties.push(Self::end_marker());
+
+        let object = if traits.class_name.is_empty() {
+            ScriptData::Object(ScriptDataObject { properties })
+        } else {
+            let class_name = ScriptDataString { length: traits.class_name.len() as u16, data: traits.class_name };
+            ScriptData::TypedObject(ScriptDataTypedObject { class_name, properties })
+        };
+        Ok(self.complete_object(index, object))
+    }
+
+    fn parse_byte_array(&mut self, data: &mut Decoder) -> Result<ScriptData, FlvError> {
+        let length = match self.parse_header(data)? {
+            Ok(length) => length,
+            Err(referenced) => return Ok(referenced),
+        };
+        let index = self.reserve_object();
+        let bytes = data.drain_bytes_vec(length as usize)?;
+        Ok(self.complete_object(index, ScriptData::ByteArray(bytes)))
+    }
+
+    fn parse_vector(&mut self, data: &mut Decoder, marker: u8) -> Result<ScriptData, FlvError> {
+        let length = match self.parse_header(data)? {
+            Ok(length) => length,
+            Err(referenced) => return Ok(referenced),
+        };
+        let index = self.reserve_object();
+        let _fixed_length = data.drain_u8()?;
+        if marker == 0x10 {
+            let _type_name = self.parse_string(data)?;
+        }
+
+        let mut values = vec![];
+        for _ in 0..length {
+            values.push(match marker {
+                0x0D => ScriptData::Number(data.drain_i32()? as f64),
+                0x0E => ScriptData::Number(data.drain_u32()? as f64),
+                0x0F => ScriptData::Number(data.drain_f64()?),
+                _ => self.parse_value(data)?,
+            });
+        }
+        Ok(self.complete_object(index, ScriptData::StrictArray(ScriptStrictArray { length, values })))
+    }
+
+    fn parse_dictionary(&mut self, data: &mut Decoder) -> Result<ScriptData, FlvError> {
+        let length = match self.parse_header(data)? {
+            Ok(length) => length,
+            Err(referenced) => return Ok(referenced),
+        };
+        let index = self.reserve_object();
+        let _weak_keys = data.drain_u8()?;
+
+        let mut properties = vec![];
+        for _ in 0..length {
+            let key = Self::key_string(&self.parse_value(data)?);
+            let value = self.parse_value(data)?;
+            properties.push(Self::property(key, value));
+        }
+        properties.push(Self::end_marker());
+        Ok(self.complete_object(index, ScriptData::EcmaArray(ScriptDataEcmaArray { length, properties })))
+    }
+
+    /// Turn a dictionary key into a property name, the way actionscript turns it into a string.
+    fn key_string(key: &ScriptData) -> String {
+        match key {
+            ScriptData::String(string) => string.data.clone(),
+            ScriptData::LongString(string) | ScriptData::XmlDocument(string) => string.data.clone(),
+            ScriptData::Number(number) => number.to_string(),
+            ScriptData::Boolean(boolean) => (*boolean != 0).to_string(),
+            ScriptData::Null => "null".to_string(),
+            ScriptData::Undefined => "undefined".to_string(),
+            _ => "[object Object]".to_string(),
+        }
+    }
+}
@@ -0,0 +1,215 @@
+//! AES-128 in CBC mode, as used by the "Encryption" and "SE" filters of encrypted flv.
+//! Keys are supplied by the caller, nothing here deals with key exchange.
+
+use crate::error::FlvError;
+
+pub const AES_BLOCK_SIZE: usize = 16;
+const ROUNDS: usize = 10;
+
+const SBOX: [u8; 256] = [
+    0x63, 0x7c, 0x77, 0x7b, 0xf2, 0x6b, 0x6f, 0xc5, 0x30, 0x01, 0x67, 0x2b, 0xfe, 0xd7, 0xab, 0x76,
+    0xca, 0x82, 0xc9, 0x7d, 0xfa, 0x59, 0x47, 0xf0, 0xad, 0xd4, 0xa2, 0xaf, 0x9c, 0xa4, 0x72, 0xc0,
+    0xb7, 0xfd, 0x93, 0x26, 0x36, 0x3f, 0xf7, 0xcc, 0x34, 0xa5, 0xe5, 0xf1, 0x71, 0xd8, 0x31, 0x15,
+    0x04, 0xc7, 0x23, 0xc3, 0x18, 0x96, 0x05, 0x9a, 0x07, 0x12, 0x80, 0xe2, 0xeb, 0x27, 0xb2, 0x75,
+    0x09, 0x83, 0x2c, 0x1a, 0x1b, 0x6e, 0x5a, 0xa0, 0x52, 0x3b, 0xd6, 0xb3, 0x29, 0xe3, 0x2f, 0x84,
+    0x53, 0xd1, 0x00, 0xed, 0x20, 0xfc, 0xb1, 0x5b, 0x6a, 0xcb, 0xbe, 0x39, 0x4a, 0x4c, 0x58, 0xcf,
+    0xd0, 0xef, 0xaa, 0xfb, 0x43, 0x4d, 0x33, 0x85, 0x45, 0xf9, 0x02, 0x7f, 0x50, 0x3c, 0x9f, 0xa8,
+    0x51, 0xa3, 0x40, 0x8f, 0x92, 0x9d, 0x38, 0xf5, 0xbc, 0xb6, 0xda, 0x21, 0x10, 0xff, 0xf3, 0xd2,
+    0xcd, 0x0c, 0x13, 0xec, 0x5f, 0x97, 0x44, 0x17, 0xc4, 0xa7, 0x7e, 0x3d, 0x64, 0x5d, 0x19, 0x73,
+    0x60, 0x81, 0x4f, 0xdc, 0x22, 0x2a, 0x90, 0x88, 0x46, 0xee, 0xb8, 0x14, 0xde, 0x5e, 0x0b, 0xdb,
+    0xe0, 0x32, 0x3a, 0x0a, 0x49, 0x06, 0x24, 0x5c, 0xc2, 0xd3, 0xac, 0x62, 0x91, 0x95, 0xe4, 0x79,
+    0xe7, 0xc8, 0x37, 0x6d, 0x8d, 0xd5, 0x4e, 0xa9, 0x6c, 0x56, 0xf4, 0xea, 0x65, 0x7a, 0xae, 0x08,
+    0xba, 0x78, 0x25, 0x2e, 0x1c, 0xa6, 0xb4, 0xc6, 0xe8, 0xdd, 0x74, 0x1f, 0x4b, 0xbd, 0x8b, 0x8a,
+    0x70, 0x3e, 0xb5, 0x66, 0x48, 0x03, 0xf6, 0x0e, 0x61, 0x35, 0x57, 0xb9, 0x86, 0xc1, 0x1d, 0x9e,
+    0xe1, 0xf8, 0x98, 0x11, 0x69, 0xd9, 0x8e, 0x94, 0x9b, 0x1e, 0x87, 0xe9, 0xce, 0x55, 0x28, 0xdf,
+    0x8c, 0xa1, 0x89, 0x0d, 0xbf, 0xe6, 0x42, 0x68, 0x41, 0x99, 0x2d, 0x0f, 0xb0, 0x54, 0xbb, 0x16,
+];
+
+const INV_SBOX: [u8; 256] = [
+    0x52, 0x09, 0x6a, 0xd5, 0x30, 0x36, 0xa5, 0x38, 0xbf, 0x40, 0xa3, 0x9e, 0x81, 0xf3, 0xd7, 0xfb,
+    0x7c, 0xe3, 0x39, 0x82, 0x9b, 0x2f, 0xff, 0x87, 0x34, 0x8e, 0x43, 0x44, 0xc4, 0xde, 0xe9, 0xcb,
+    0x54, 0x7b, 0x94, 0x32, 0xa6, 0xc2, 0x23, 0x3d, 0xee, 0x4c, 0x95, 0x0b, 0x42, 0xfa, 0xc3, 0x4e,
+    0x08, 0x2e, 0xa1, 0x66, 0x28, 0xd9, 0x24, 0xb2, 0x76, 0x5b, 0xa2, 0x49, 0x6d, 0x8b, 0xd1, 0x25,
+    0x72, 0xf8, 0xf6, 0x64, 0x86, 0x68, 0x98, 0x16, 0xd4, 0xa4, 0x5c, 0xcc, 0x5d, 0x65, 0xb6, 0x92,
+    0x6c, 0x70, 0x48, 0x50, 0xfd, 0xed, 0xb9, 0xda, 0x5e, 0x15, 0x46, 0x57, 0xa7, 0x8d, 0x9d, 0x84,
+    0x90, 0xd8, 0xab, 0x00, 0x8c, 0xbc, 0xd3, 0x0a, 0xf7, 0xe4, 0x58, 0x05, 0xb8, 0xb3, 0x45, 0x06,
+    0xd0, 0x2c, 0x1e, 0x8f, 0xca, 0x3f, 0x0f, 0x02, 0xc1, 0xaf, 0xbd, 0x03, 0x01, 0x13, 0x8a, 0x6b,
+    0x3a, 0x91, 0x11, 0x41, 0x4f, 0x67, 0xdc, 0xea, 0x97, 0xf2, 0xcf, 0xce, 0xf0, 0xb4, 0xe6, 0x73,
+    0x96, 0xac, 0x74, 0x22, 0xe7, 0xad, 0x35, 0x85, 0xe2, 0xf9, 0x37, 0xe8, 0x1c, 0x75, 0xdf, 0x6e,
+    0x47, 0xf1, 0x1a, 0x71, 0x1d, 0x29, 0xc5, 0x89, 0x6f, 0xb7, 0x62, 0x0e, 0xaa, 0x18, 0xbe, 0x1b,
+    0xfc, 0x56, 0x3e, 0x4b, 0xc6, 0xd2, 0x79, 0x20, 0x9a, 0xdb, 0xc0, 0xfe, 0x78, 0xcd, 0x5a, 0xf4,
+    0x1f, 0xdd, 0xa8, 0x33, 0x88, 0x07, 0xc7, 0x31, 0xb1, 0x12, 0x10, 0x59, 0x27, 0x80, 0xec, 0x5f,
+    0x60, 0x51, 0x7f, 0xa9, 0x19, 0xb5, 0x4a, 0x0d, 0x2d, 0xe5, 0x7a, 0x9f, 0x93, 0xc9, 0x9c, 0xef,
+    0xa0, 0xe0, 0x3b, 0x4d, 0xae, 0x2a, 0xf5, 0xb0, 0xc8, 0xeb, 0xbb, 0x3c, 0x83, 0x53, 0x99, 0x61,
+    0x17, 0x2b, 0x04, 0x7e, 0xba, 0x77, 0xd6, 0x26, 0xe1, 0x69, 0x14, 0x63, 0x55, 0x21, 0x0c, 0x7d,
+];
+
+const RCON: [u8; ROUNDS] = [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1b, 0x36];
+
+/// Multiply by x in GF(2^8).
+#[inline]
+fn xtime(byte: u8) -> u8 {
+    (byte << 1) ^ if byte & 0x80 != 0 { 0x1b } else { 0 }
+}
+
+#[inline]
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut result = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            result ^= a;
+        }
+        a = xtime(a);
+        b >>= 1;
+    }
+    result
+}
+
+/// A single AES-128 block cipher with its expanded key.
+/// The state is kept column by column, as in FIPS-197.
+#[derive(Clone)]
+pub struct Aes128 {
+    round_keys: [[u8; AES_BLOCK_SIZE]; ROUNDS + 1],
+}
+
+impl Aes128 {
+    pub fn new(key: &[u8; AES_BLOCK_SIZE]) -> Self {
+        let mut words = [[0u8; 4]; 4 * (ROUNDS + 1)];
+        for (i, word) in key.chunks_exact(4).enumerate() {
+            words[i].copy_from_slice(word);
+        }
+        for i in 4..words.len() {
+            let mut temp = words[i - 1];
+            if i % 4 == 0 {
+                temp.rotate_left(1);
+                for byte in temp.iter_mut() {
+                    *byte = SBOX[*byte as usize];
+                }
+                temp[0] ^= RCON[i / 4 - 1];
+            }
+            let previous = words[i - 4];
+            for (byte, (a, b)) in words[i].iter_mut().zip(previous.iter().zip(temp.iter())) {
+                *byte = a ^ b;
+            }
+        }
+
+        let mut round_keys = [[0u8; AES_BLOCK_SIZE]; ROUNDS + 1];
+        for (round, round_key) in round_keys.iter_mut().enumerate() {
+            for (column, word) in words[round * 4..round * 4 + 4].iter().enumerate() {
+                round_key[column * 4..column * 4 + 4].copy_from_slice(word);
+            }
+        }
+        Self { round_keys }
+    }
+
+    #[inline]
+    fn add_round_key(&self, state: &mut [u8; AES_BLOCK_SIZE], round: usize) {
+        for (byte, key) in state.iter_mut().zip(self.round_keys[round].iter()) {
+            *byte ^= key;
+        }
+    }
+
+    /// Row `r` is rotated left by `r` for encryption, right by `r` for decryption.
+    #[inline]
+    fn shift_rows(state: &mut [u8; AES_BLOCK_SIZE], inverse: bool) {
+        let old = *state;
+        for row in 1..4 {
+            for column in 0..4 {
+                let from = if inverse { (column + 4 - row) % 4 } else { (column + row) % 4 };
+                state[row + 4 * column] = old[row + 4 * from];
+            }
+        }
+    }
+
+    #[inline]
+    fn mix_columns(state: &mut [u8; AES_BLOCK_SIZE], inverse: bool) {
+        let factors: [u8; 4] = if inverse { [14, 11, 13, 9] } else { [2, 3, 1, 1] };
+        for column in state.chunks_exact_mut(4) {
+            let old = [column[0], column[1], column[2], column[3]];
+            for (row, byte) in column.iter_mut().enumerate() {
+                *byte = (0..4).fold(0, |acc, i| acc ^ gf_mul(old[(row + i) % 4], factors[i]));
+            }
+        }
+    }
+
+    pub fn encrypt_block(&self, block: &mut [u8; AES_BLOCK_SIZE]) {
+        self.add_round_key(block, 0);
+        for round in 1..=ROUNDS {
+            for byte in block.iter_mut() {
+                *byte = SBOX[*byte as usize];
+            }
+            Self::shift_rows(block, false);
+            if round != ROUNDS {
+                Self::mix_columns(block, false);
+            }
+            self.add_round_key(block, round);
+        }
+    }
+
+    pub fn decrypt_block(&self, block: &mut [u8; AES_BLOCK_SIZE]) {
+        self.add_round_key(block, ROUNDS);
+        for round in (0..ROUNDS).rev() {
+            Self::shift_rows(block, true);
+            for byte in block.iter_mut() {
+                *byte = INV_SBOX[*byte as usize];
+            }
+            self.add_round_key(block, round);
+            if round != 0 {
+                Self::mix_columns(block, true);
+            }
+        }
+    }
+}
+
+/// AES-128-CBC with PKCS#7 padding.
+pub struct Cbc;
+
+impl Cbc {
+    pub fn encrypt(key: &[u8; AES_BLOCK_SIZE], iv: &[u8; AES_BLOCK_SIZE], data: &[u8]) -> Vec<u8> {
+        let cipher = Aes128::new(key);
+        let padding = AES_BLOCK_SIZE - data.len() % AES_BLOCK_SIZE;
+        let mut result = Vec::with_capacity(data.len() + padding);
+        result.extend_from_slice(data);
+        result.resize(data.len() + padding, padding as u8);
+
+        let mut previous = *iv;
+        for chunk in result.chunks_exact_mut(AES_BLOCK_SIZE) {
+            let mut block = [0u8; AES_BLOCK_SIZE];
+            for (byte, (a, b)) in block.iter_mut().zip(chunk.iter().zip(previous.iter())) {
+                *byte = a ^ b;
+            }
+            cipher.encrypt_block(&mut block);
+            chunk.copy_from_slice(&block);
+            previous = block;
+        }
+        result
+    }
+
+    /// Fails if `data` is not made of whole blocks, or if the padding is invalid,
+    /// which usually means the key is wrong.
+    pub fn decrypt(key: &[u8; AES_BLOCK_SIZE], iv: &[u8; AES_BLOCK_SIZE], data: &[u8]) -> Result<Vec<u8>, FlvError> {
+        if data.is_empty() || !data.len().is_multiple_of(AES_BLOCK_SIZE) {
+            return Err(FlvError::malformed(format!("Encrypted body of {} bytes is not made of whole AES blocks.", data.len())));
+        }
+        let cipher = Aes128::new(key);
+        let mut result = Vec::with_capacity(data.len());
+        let mut previous = *iv;
+        for chunk in data.chunks_exact(AES_BLOCK_SIZE) {
+            let mut block = [0u8; AES_BLOCK_SIZE];
+            block.copy_from_slice(chunk);
+            cipher.decrypt_block(&mut block);
+            for (byte, previous) in block.iter_mut().zip(previous.iter()) {
+                *byte ^= previous;
+            }
+            result.extend_from_slice(&block);
+            previous.copy_from_slice(chunk);
+        }
+
+        let padding = result[result.len() - 1] as usize;
+        if padding == 0 || padding > AES_BLOCK_SIZE || result[result.len() - padding..].iter().any(|byte| *byte as usize != padding) {
+            return Err(FlvError::malformed("Invalid padding in the decrypted body, the key is probably wrong."));
+        }
+        result.truncate(result.len() - padding);
+        Ok(result)
+    }
+}
@@ -1,20 +1,70 @@
-use crate::core::IConsumable;
+use crate::core::{IConsumable, TrackCodecs};
+use crate::error::FlvError;
 use crate::exchange::{Destination, Packed, PackedContent, PackedContentToDecoder, PackedContentToDemuxer, PackedContentToRemuxer, RemuxedData};
+use crate::flv::crypto::{Cbc, AES_BLOCK_SIZE};
 use crate::flv::demuxer::Demuxer;
+use crate::flv::event::ScriptEvent;
+use crate::flv::frames::ElementaryFrames;
 use crate::flv::header::{AudioTagHeader, EncryptionTagHeader, FilterParameters, FlvHeader, TagHeader, VideoTagHeader};
+use crate::flv::meta::{KeyframeIndex, MetaData, RawMetaData, XMPData};
+use crate::flv::observer::{ITagObserver, TagAction};
 use crate::flv::script::ScriptTagBody;
 use crate::flv::tag::{EncryptedTagBody, NormalTagBody, Tag, TagBody, TagType};
+use crate::flv::timestamp::{TimestampPolicy, TimestampTracker};
+use crate::fmpeg::remux_context::{ProbeWarning, TrackType};
 use crate::io::bit::BitIO;
+use crate::io::reader::{IReader, SeekableReader, StreamReader};
 use std::collections::VecDeque;
+use std::io::{Read, Seek};
 use std::thread;
 use std::thread::JoinHandle;
 
+const FLV_SIGNATURE: [u8; 3] = *b"FLV";
+const FLV_HEADER_SIZE: usize = 9;
+/// A `data_offset` beyond this is taken as garbage, rather than waiting for that much header.
+const MAX_HEADER_SIZE: u32 = 64 * 1024;
+const PREVIOUS_TAG_SIZE_LENGTH: usize = 4;
+const TAG_HEADER_SIZE: usize = 11;
+
+const MAX_SCRIPT_DEPTH: usize = 64;
+
+/// Tags larger than this are not considered plausible while resynchronising.
+/// Real tags are rarely anywhere near this, but a random size field usually is.
+const MAX_RESYNC_TAG_SIZE: u32 = 8 * 1024 * 1024;
+
 pub struct Decoder {
     pack_buffer: VecDeque<Packed>,
     data: VecDeque<u8>,
     previous_tag_size: u32,
     decoding: bool,
+    header_decoded: bool,
     demuxer: Demuxer,
+
+    script_depth: usize,
+    /// Offset of the front of `data` from the start of the stream.
+    stream_offset: u64,
+    /// Index of the next tag to be decoded.
+    tag_index: u64,
+
+    resync_enabled: bool,
+    resyncing: bool,
+    /// Bytes skipped by the resync in progress.
+    resync_skipped: usize,
+    /// Bytes skipped by all resyncs so far.
+    skipped_bytes: u64,
+
+    source: Option<Box<dyn IReader + Send>>,
+    source_exhausted: bool,
+
+    keyframes: Option<KeyframeIndex>,
+    metadata: Option<MetaData>,
+    xmp: Option<XMPData>,
+
+    decryption_key: Option<[u8; AES_BLOCK_SIZE]>,
+
+    timestamps: TimestampTracker,
+
+    tag_observers: Vec<Box<dyn ITagObserver + Send>>,
 }
 
 impl Decoder {
@@ -24,10 +74,81 @@ impl Decoder {
             data,
             previous_tag_size: 0,
             decoding: false,
+            header_decoded: false,
             demuxer: Demuxer::new(),
+
+            script_depth: 0,
+            stream_offset: 0,
+            tag_index: 0,
+
+            resync_enabled: true,
+            resyncing: false,
+            resync_skipped: 0,
+            skipped_bytes: 0,
+
+            source: None,
+            source_exhausted: false,
+
+            keyframes: None,
+            metadata: None,
+            xmp: None,
+
+            decryption_key: None,
+
+            timestamps: TimestampTracker::default(),
+
+            tag_observers: vec![],
         }
     }
 
+    /// Create a decoder which pulls its data from `reader` chunk by chunk,
+    /// instead of holding the whole stream in memory.
+    pub fn from_reader<R: Read + Send + 'static>(reader: R) -> Self {
+        let mut decoder = Self::new(VecDeque::new());
+        decoder.set_source(StreamReader::new(reader));
+        decoder
+    }
+
+    /// Create a decoder which pulls its data from `reader` chunk by chunk,
+    /// and which can jump around in it with `seek_to`.
+    pub fn from_seekable_reader<R: Read + Seek + Send + 'static>(reader: R) -> Self {
+        let mut decoder = Self::new(VecDeque::new());
+        decoder.set_source(SeekableReader::new(reader));
+        decoder
+    }
+
+    /// Attach a source to pull data from.
+    /// Every call to `continue_decoding` pulls at most one chunk from it,
+    /// so memory stays bounded as long as the remuxed data is consumed in between.
+    pub fn set_source(&mut self, source: impl IReader + Send + 'static) {
+        self.source = Some(Box::new(source));
+        self.source_exhausted = false;
+    }
+
+    /// Whether the attached source has reached its end.
+    /// Always false if there is no source.
+    #[inline]
+    pub fn is_source_exhausted(&self) -> bool {
+        self.source_exhausted
+    }
+
+    /// Pull the next chunk from the source into the buffer, and return its size.
+    /// Returns 0 if the source is exhausted, or if there is no source at all.
+    pub fn pull_chunk(&mut self) -> Result<usize, FlvError> {
+        let source = match self.source {
+            Some(ref mut source) if !self.source_exhausted => source,
+            _ => return Ok(0),
+        };
+        let mut chunk = vec![0; source.chunk_size()];
+        let read = source.read_into(&mut chunk).map_err(|e| e.at_offset(self.stream_offset + self.data.len() as u64))?;
+        if read == 0 {
+            println!("[Decoder] Source exhausted.");
+            self.source_exhausted = true;
+        }
+        self.data.extend(&chunk[..read]);
+        Ok(read)
+    }
+
     pub fn push_data(&mut self, data: &mut VecDeque<u8>) {
         self.data.append(data);
     }
@@ -37,200 +158,287 @@ impl Decoder {
     }
 
     #[inline]
-    pub fn drain_u8(&mut self) -> u8 {
-        self.data.pop_front().unwrap()
+    fn ensure_available(&self, size: usize) -> Result<(), FlvError> {
+        if self.data.len() < size {
+            return Err(FlvError::insufficient_data(size, self.data.len()).at_offset(self.stream_offset));
+        }
+        Ok(())
+    }
+
+    /// Offset of the next unread byte from the start of the stream.
+    #[inline]
+    pub fn stream_offset(&self) -> u64 {
+        self.stream_offset
+    }
+
+    /// Number of tags decoded so far.
+    #[inline]
+    pub fn tag_index(&self) -> u64 {
+        self.tag_index
+    }
+
+    /// Whether the decoder scans forward for the next plausible tag after a corrupt one,
+    /// instead of stopping. Enabled by default.
+    #[inline]
+    pub fn set_resync_enabled(&mut self, enabled: bool) {
+        self.resync_enabled = enabled;
+    }
+
+    /// Set the AES-128 key for tags with the filter bit set.
+    /// Without a key their bodies are passed on as `TagBody::Encrypted`, which the remuxer can not use.
+    #[inline]
+    pub fn set_decryption_key(&mut self, key: [u8; AES_BLOCK_SIZE]) {
+        self.decryption_key = Some(key);
+    }
+
+    /// Choose what happens when the timestamps jump back, skip ahead or wrap.
+    /// `TimestampPolicy::Passthrough` by default.
+    #[inline]
+    pub fn set_timestamp_policy(&mut self, policy: TimestampPolicy) {
+        self.timestamps.set_policy(policy);
+    }
+
+    /// Set the backward tolerance and the forward gap in milliseconds, beyond which the timestamps count as broken.
+    /// 500ms and 10s by default.
+    #[inline]
+    pub fn set_discontinuity_thresholds(&mut self, backward_tolerance: u32, forward_gap: u32) {
+        self.timestamps.set_thresholds(backward_tolerance, forward_gap);
+    }
+
+    /// Let `observer` see, change, drop or replace every tag before it goes to the demuxer.
+    /// Observers are called in the order they were added, each one with the output of the one before.
+    pub fn add_tag_observer(&mut self, observer: impl ITagObserver + Send + 'static) {
+        self.tag_observers.push(Box::new(observer));
+    }
+
+    /// Total number of bytes skipped while resynchronising.
+    #[inline]
+    pub fn skipped_bytes(&self) -> u64 {
+        self.skipped_bytes
     }
 
     #[inline]
-    pub fn drain_bytes<const SIZE: usize>(&mut self) -> [u8; SIZE] {
+    pub fn drain_u8(&mut self) -> Result<u8, FlvError> {
+        let byte = self.data.pop_front().ok_or(FlvError::insufficient_data(1, 0).at_offset(self.stream_offset))?;
+        self.stream_offset += 1;
+        Ok(byte)
+    }
+
+    /// Note: nothing is drained unless all the `SIZE` bytes are available.
+    /// This is the same for all the other multi-byte drain methods.
+    #[inline]
+    pub fn drain_bytes<const SIZE: usize>(&mut self) -> Result<[u8; SIZE], FlvError> {
+        self.ensure_available(SIZE)?;
         let mut result = [0; SIZE];
-        for i in 0..SIZE {
-            result[i] = self.drain_u8();
+        for (byte, drained) in result.iter_mut().zip(self.data.drain(0..SIZE)) {
+            *byte = drained;
         }
-        result
+        self.stream_offset += SIZE as u64;
+        Ok(result)
+    }
+
+    #[inline]
+    pub fn drain_bytes_vec(&mut self, size: usize) -> Result<Vec<u8>, FlvError> {
+        self.ensure_available(size)?;
+        self.stream_offset += size as u64;
+        Ok(self.data.drain(0..size).collect::<Vec<_>>())
     }
 
     #[inline]
-    pub fn drain_bytes_vec(&mut self, size: usize) -> Vec<u8> {
-        let drained = self.data.drain(0..size).collect::<Vec<_>>();
-        drained
+    pub fn drain_bytes_deque(&mut self, size: usize) -> Result<VecDeque<u8>, FlvError> {
+        self.ensure_available(size)?;
+        self.stream_offset += size as u64;
+        Ok(self.data.drain(0..size).collect::<VecDeque<_>>())
     }
 
     #[inline]
-    pub fn drain_bytes_deque(&mut self, size: usize) -> VecDeque<u8> {
-        let drained = self.data.drain(0..size).collect::<VecDeque<_>>();
-        drained
+    pub fn drain_u16_le(&mut self) -> Result<u16, FlvError> {
+        Ok(u16::from_le_bytes(self.drain_bytes::<2>()?))
     }
 
     #[inline]
-    pub fn drain_u16_le(&mut self) -> u16 {
-        let mut result = 0;
-        result |= self.drain_u8() as u16;
-        result |= (self.drain_u8() as u16) << 8;
-        result
+    pub fn drain_u16(&mut self) -> Result<u16, FlvError> {
+        Ok(u16::from_be_bytes(self.drain_bytes::<2>()?))
     }
 
     #[inline]
-    pub fn drain_u16(&mut self) -> u16 {
-        let mut result = 0;
-        result |= (self.drain_u8() as u16) << 8;
-        result |= self.drain_u8() as u16;
-        result
+    pub fn drain_u24_le(&mut self) -> Result<u32, FlvError> {
+        let [b0, b1, b2] = self.drain_bytes::<3>()?;
+        Ok(u32::from_le_bytes([b0, b1, b2, 0]))
     }
 
     #[inline]
-    pub fn drain_u24_le(&mut self) -> u32 {
-        let mut result = 0;
-        result |= self.drain_u8() as u32;
-        result |= (self.drain_u8() as u32) << 8;
-        result |= (self.drain_u8() as u32) << 16;
-        result
+    pub fn drain_u24(&mut self) -> Result<u32, FlvError> {
+        let [b0, b1, b2] = self.drain_bytes::<3>()?;
+        Ok(u32::from_be_bytes([0, b0, b1, b2]))
     }
 
     #[inline]
-    pub fn drain_u24(&mut self) -> u32 {
-        let mut result = 0;
-        result |= (self.drain_u8() as u32) << 16;
-        result |= (self.drain_u8() as u32) << 8;
-        result |= self.drain_u8() as u32;
-        result
+    pub fn drain_u32_le(&mut self) -> Result<u32, FlvError> {
+        Ok(u32::from_le_bytes(self.drain_bytes::<4>()?))
     }
 
     #[inline]
-    pub fn drain_u32_le(&mut self) -> u32 {
-        let mut result = 0;
-        result |= self.drain_u8() as u32;
-        result |= (self.drain_u8() as u32) << 8;
-        result |= (self.drain_u8() as u32) << 16;
-        result |= (self.drain_u8() as u32) << 24;
-        result
+    pub fn drain_u32(&mut self) -> Result<u32, FlvError> {
+        Ok(u32::from_be_bytes(self.drain_bytes::<4>()?))
     }
 
     #[inline]
-    pub fn drain_u32(&mut self) -> u32 {
-        let mut result = 0;
-        result |= (self.drain_u8() as u32) << 24;
-        result |= (self.drain_u8() as u32) << 16;
-        result |= (self.drain_u8() as u32) << 8;
-        result |= self.drain_u8() as u32;
-        result
+    pub fn drain_u64(&mut self) -> Result<u64, FlvError> {
+        Ok(u64::from_be_bytes(self.drain_bytes::<8>()?))
     }
 
     #[inline]
-    pub fn drain_u64(&mut self) -> u64 {
-        let mut result = 0;
-        result |= (self.drain_u8() as u64) << 56;
-        result |= (self.drain_u8() as u64) << 48;
-        result |= (self.drain_u8() as u64) << 40;
-        result |= (self.drain_u8() as u64) << 32;
-        result |= (self.drain_u8() as u64) << 24;
-        result |= (self.drain_u8() as u64) << 16;
-        result |= (self.drain_u8() as u64) << 8;
-        result |= self.drain_u8() as u64;
-        result
+    pub fn drain_i8(&mut self) -> Result<i8, FlvError> {
+        Ok(self.drain_u8()? as i8)
     }
 
     #[inline]
-    pub fn drain_i8(&mut self) -> i8 {
-        self.drain_u8() as i8
+    pub fn drain_i16(&mut self) -> Result<i16, FlvError> {
+        Ok(i16::from_be_bytes(self.drain_bytes::<2>()?))
     }
 
     #[inline]
-    pub fn drain_i16(&mut self) -> i16 {
-        let mut result = 0;
-        result |= (self.drain_u8() as i16) << 8;
-        result |= self.drain_u8() as i16;
-        result
+    pub fn drain_i24(&mut self) -> Result<i32, FlvError> {
+        let [b0, b1, b2] = self.drain_bytes::<3>()?;
+        Ok(i32::from_be_bytes([0, b0, b1, b2]))
     }
 
     #[inline]
-    pub fn drain_i24(&mut self) -> i32 {
-        let mut result = 0;
-        result |= (self.drain_u8() as i32) << 16;
-        result |= (self.drain_u8() as i32) << 8;
-        result |= self.drain_u8() as i32;
-        result
+    pub fn drain_i32(&mut self) -> Result<i32, FlvError> {
+        Ok(i32::from_be_bytes(self.drain_bytes::<4>()?))
     }
 
     #[inline]
-    pub fn drain_i32(&mut self) -> i32 {
-        let mut result = 0;
-        result |= (self.drain_u8() as i32) << 24;
-        result |= (self.drain_u8() as i32) << 16;
-        result |= (self.drain_u8() as i32) << 8;
-        result |= self.drain_u8() as i32;
-        result
+    pub fn drain_i64(&mut self) -> Result<i64, FlvError> {
+        Ok(i64::from_be_bytes(self.drain_bytes::<8>()?))
     }
 
     #[inline]
-    pub fn drain_i64(&mut self) -> i64 {
-        let mut result = 0;
-        result |= (self.drain_u8() as i64) << 56;
-        result |= (self.drain_u8() as i64) << 48;
-        result |= (self.drain_u8() as i64) << 40;
-        result |= (self.drain_u8() as i64) << 32;
-        result |= (self.drain_u8() as i64) << 24;
-        result |= (self.drain_u8() as i64) << 16;
-        result |= (self.drain_u8() as i64) << 8;
-        result |= self.drain_u8() as i64;
-        result
+    pub fn drain_f64(&mut self) -> Result<f64, FlvError> {
+        Ok(f64::from_be_bytes(self.drain_bytes::<8>()?))
     }
 
     #[inline]
-    pub fn drain_f64(&mut self) -> f64 {
-        let mut result = 0;
-        result |= (self.drain_u8() as u64) << 56;
-        result |= (self.drain_u8() as u64) << 48;
-        result |= (self.drain_u8() as u64) << 40;
-        result |= (self.drain_u8() as u64) << 32;
-        result |= (self.drain_u8() as u64) << 24;
-        result |= (self.drain_u8() as u64) << 16;
-        result |= (self.drain_u8() as u64) << 8;
-        result |= self.drain_u8() as u64;
-        f64::from_bits(result)
+    pub fn drain_f64_le(&mut self) -> Result<f64, FlvError> {
+        Ok(f64::from_le_bytes(self.drain_bytes::<8>()?))
     }
 
     #[inline]
-    pub fn drain_f64_le(&mut self) -> f64 {
-        let mut result = 0;
-        result |= self.drain_u8() as u64;
-        result |= (self.drain_u8() as u64) << 8;
-        result |= (self.drain_u8() as u64) << 16;
-        result |= (self.drain_u8() as u64) << 24;
-        result |= (self.drain_u8() as u64) << 32;
-        result |= (self.drain_u8() as u64) << 40;
-        result |= (self.drain_u8() as u64) << 48;
-        result |= (self.drain_u8() as u64) << 56;
-        f64::from_bits(result)
+    pub fn drain_f32_le(&mut self) -> Result<f32, FlvError> {
+        Ok(f32::from_le_bytes(self.drain_bytes::<4>()?))
+    }
+
+    #[inline]
+    pub fn drain_f32(&mut self) -> Result<f32, FlvError> {
+        Ok(f32::from_be_bytes(self.drain_bytes::<4>()?))
+    }
+
+    /// Drops the next `size` bytes without copying them anywhere.
+    #[inline]
+    fn skip_bytes(&mut self, size: usize) -> Result<(), FlvError> {
+        self.ensure_available(size)?;
+        self.data.drain(0..size);
+        self.stream_offset += size as u64;
+        Ok(())
     }
 
+    /// Peeks a big-endian u24 at `offset` without draining anything.
     #[inline]
-    pub fn drain_f32_le(&mut self) -> f32 {
-        let mut result = 0;
-        result |= self.drain_u8() as u32;
-        result |= (self.drain_u8() as u32) << 8;
-        result |= (self.drain_u8() as u32) << 16;
-        result |= (self.drain_u8() as u32) << 24;
-        f32::from_bits(result)
+    fn peek_u24_at(&self, offset: usize) -> Result<u32, FlvError> {
+        self.ensure_available(offset + 3)?;
+        Ok(u32::from_be_bytes([0, self.data[offset], self.data[offset + 1], self.data[offset + 2]]))
     }
 
+    /// Peeks a big-endian u32 at `offset` without draining anything.
     #[inline]
-    pub fn drain_f32(&mut self) -> f32 {
-        let mut result = 0;
-        result |= (self.drain_u8() as u32) << 24;
-        result |= (self.drain_u8() as u32) << 16;
-        result |= (self.drain_u8() as u32) << 8;
-        result |= self.drain_u8() as u32;
-        f32::from_bits(result)
+    fn peek_u32_at(&self, offset: usize) -> Result<u32, FlvError> {
+        self.ensure_available(offset + 4)?;
+        Ok(u32::from_be_bytes([self.data[offset], self.data[offset + 1], self.data[offset + 2], self.data[offset + 3]]))
     }
 
-    pub fn decode_header(&mut self) -> Result<FlvHeader, Box<dyn std::error::Error>> {
-        let signature: [u8; 3] = self.drain_bytes::<3>();
-        let version = self.drain_u8();
-        let bits = BitIO::new(self.drain_u8());
+    /// Enter one more level of nested script data.
+    /// Fails when the nesting gets deeper than any sane metadata would,
+    /// instead of letting a crafted tag overflow the stack.
+    pub(crate) fn enter_script_level(&mut self) -> Result<(), FlvError> {
+        if self.script_depth >= MAX_SCRIPT_DEPTH {
+            return Err(FlvError::amf(format!("Script data nested deeper than {} levels.", MAX_SCRIPT_DEPTH)).at_offset(self.stream_offset));
+        }
+        self.script_depth += 1;
+        Ok(())
+    }
+
+    pub(crate) fn leave_script_level(&mut self) {
+        self.script_depth = self.script_depth.saturating_sub(1);
+    }
+
+    /// Run `parse` over exactly the next `size` bytes of the buffer.
+    /// A malformed body can not read into the following tag this way:
+    /// running out of bytes inside the bound is reported as invalid data, not as insufficient data.
+    /// Bytes left unread by `parse` are dropped together with the body.
+    fn decode_bounded<T>(
+        &mut self,
+        size: usize,
+        parse: impl FnOnce(&mut Self) -> Result<T, FlvError>,
+    ) -> Result<T, FlvError> {
+        let body_offset = self.stream_offset;
+        let body = self.drain_bytes_deque(size)?;
+        let rest = std::mem::replace(&mut self.data, body);
+        let end_offset = std::mem::replace(&mut self.stream_offset, body_offset);
+        let parsed = parse(self);
+        self.data = rest;
+        self.stream_offset = end_offset;
+        self.script_depth = 0;
+
+        parsed.map_err(|e| match e {
+            FlvError::InsufficientData { context, .. } => {
+                let mut error = FlvError::malformed(format!("Tag body is shorter than its content ({} bytes).", size));
+                if let Some(byte_offset) = context.byte_offset {
+                    error = error.at_offset(byte_offset);
+                }
+                error
+            }
+            _ => e,
+        })
+    }
+
+    /// Decode the flv header at the front of the buffer, and skip the rest of it up to `data_offset`.
+    /// If the header is not completely buffered yet, nothing is drained
+    /// and `FlvError::InsufficientData` is returned, so it may arrive in any number of pieces.
+    /// Input which does not start with the FLV signature is rejected as soon as the first bytes are in.
+    pub fn decode_header(&mut self) -> Result<FlvHeader, FlvError> {
+        let buffered = self.data.len().min(FLV_SIGNATURE.len());
+        if !self.data.iter().take(buffered).eq(FLV_SIGNATURE[..buffered].iter()) {
+            return Err(FlvError::malformed("Missing FLV signature, this is not an flv stream.").at_offset(self.stream_offset));
+        }
+        self.ensure_available(FLV_HEADER_SIZE)?;
+
+        let version = self.data[3];
+        if version == 0 {
+            return Err(FlvError::malformed("Invalid flv version 0.").at_offset(self.stream_offset + 3));
+        }
+        let data_offset = self.peek_u32_at(5)?;
+        if data_offset > MAX_HEADER_SIZE {
+            return Err(FlvError::malformed(format!("Implausible flv header size {}.", data_offset)).at_offset(self.stream_offset + 5));
+        }
+        // some muxers write a smaller offset, the body can not start inside the header anyway.
+        let header_size = (data_offset as usize).max(FLV_HEADER_SIZE);
+        self.ensure_available(header_size)?;
+
+        let signature: [u8; 3] = self.drain_bytes::<3>()?;
+        let version = self.drain_u8()?;
+        let bits = BitIO::new(self.drain_u8()?);
         let has_audio = bits.read_bit(5);
         let has_video = bits.read_bit(7);
-        let data_offset = self.drain_u32();
+        let data_offset = self.drain_u32()?;
+        if version != 1 {
+            println!("[Decoder] Unknown flv version {}, decoding it as version 1.", version);
+        }
+        if header_size > FLV_HEADER_SIZE {
+            println!("[Decoder] Skipping {} extra header bytes.", header_size - FLV_HEADER_SIZE);
+            self.skip_bytes(header_size - FLV_HEADER_SIZE)?;
+        }
+        self.header_decoded = true;
         Ok(
             FlvHeader::new(
                 signature,
@@ -242,38 +450,52 @@ impl Decoder {
         )
     }
 
+    /// Decode the header and pass it on to the demuxer, unless that has happened already.
+    /// Returns whether the header is done, false means that it is not completely buffered yet.
+    fn try_decode_header(&mut self) -> Result<bool, FlvError> {
+        if self.header_decoded {
+            return Ok(true);
+        }
+        match self.decode_header() {
+            Ok(flv_header) => {
+                self.send_header_to_demuxer(flv_header)?;
+                Ok(true)
+            }
+            Err(e) if e.is_insufficient_data() => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
     #[inline]
     pub fn concat_ts(ts: u32, ts_ext: u8) -> u32 {
         (ts & 0x00FFFFFFu32) | ((ts_ext as u32) << 24)
     }
 
-    pub fn peek_tag_size(&mut self) -> Result<u32, Box<dyn std::error::Error>> {
-        if self.data.len() < 4 {
-            return Err("Insufficient data to peek buffer size.".into());
-        }
-        let mut data_size = self.data.iter_mut()
-            .skip(1)
-            .take(3)
-            .map(|x| *x)
-            .collect::<Vec<u8>>();
-        let mut extended = vec![0];
-        extended.append(&mut data_size);
-        let data_size = u32::from_be_bytes(<[u8; 4]>::try_from(extended.as_slice())?);
-        Ok(data_size)
-    }
-
-    pub fn decode_tag(&mut self) -> Result<Tag, Box<dyn std::error::Error>> {
-        let bit = BitIO::new(self.drain_u8());
+    /// Peek the data size of the tag at the front of the buffer.
+    pub fn peek_tag_size(&self) -> Result<u32, FlvError> {
+        self.peek_u24_at(1)
+    }
+
+    /// Decode the tag at the front of the buffer.
+    /// If the tag is not completely buffered yet, nothing is drained
+    /// and `FlvError::InsufficientData` is returned.
+    pub fn decode_tag(&mut self) -> Result<Tag, FlvError> {
+        let data_size = self.peek_tag_size()?;
+        self.ensure_available(TAG_HEADER_SIZE + data_size as usize)?;
+
+        // the type is checked before anything is drained, so that a resync can start right here.
+        let bit = BitIO::new(self.data[0]);
         let filter = bit.read_bit(2);
         let tag_type = TagType::from(bit.read_range(3, 7))?;
+        self.drain_u8()?;
 
-        let data_size = self.drain_u24();
+        let data_size = self.drain_u24()?;
 
-        let timestamp = self.drain_u24();
-        let timestamp_extended = self.drain_u8();
+        let timestamp = self.drain_u24()?;
+        let timestamp_extended = self.drain_u8()?;
         let ts_concatenated = Self::concat_ts(timestamp, timestamp_extended);
 
-        let stream_id = self.drain_u24(); // always 0.
+        let stream_id = self.drain_u24()?; // always 0.
 
         // Note: all the elements before stream_id made up for 11 bytes in total.
         //
@@ -283,31 +505,56 @@ impl Decoder {
 
         let mut header_size: usize = 0;
 
-        let tag_header: TagHeader;
-        let tag_body = if !filter {
-            TagBody::Normal(match tag_type {
-                TagType::Audio => {
-                    tag_header = TagHeader::Audio(AudioTagHeader::parse(self, &mut header_size)?);
-                    NormalTagBody::Audio(self.drain_bytes_deque((data_size as usize) - header_size))
-                }
-                TagType::Video => {
-                    tag_header = TagHeader::Video(VideoTagHeader::parse(self, &mut header_size)?);
-                    NormalTagBody::Video(self.drain_bytes_deque((data_size as usize) - header_size))
-                }
-                TagType::Script => {
-                    tag_header = TagHeader::Script;
-                    NormalTagBody::Script(ScriptTagBody::parse(self)?)
-                }
-                _ => {
-                    return Err(Box::new(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid tag type")));
-                }
-            })
-        } else {
-            tag_header = TagHeader::Placeholder;
-            encryption_header = Some(EncryptionTagHeader::parse(self, &mut header_size)?);
-            filter_params = Some(FilterParameters::parse(self, &mut header_size)?);
-            TagBody::Encrypted(EncryptedTagBody::Placeholder)
-        };
+        let (tag_header, tag_body) = self.decode_bounded(data_size as usize, |decoder| {
+            if !filter {
+                Ok(match tag_type {
+                    TagType::Audio => {
+                        let header = TagHeader::Audio(AudioTagHeader::parse(decoder, &mut header_size)?);
+                        let remaining = decoder.data.len();
+                        (header, TagBody::Normal(NormalTagBody::Audio(decoder.drain_bytes_deque(remaining)?)))
+                    }
+                    TagType::Video => {
+                        let header = TagHeader::Video(VideoTagHeader::parse(decoder, &mut header_size)?);
+                        let remaining = decoder.data.len();
+                        (header, TagBody::Normal(NormalTagBody::Video(decoder.drain_bytes_deque(remaining)?)))
+                    }
+                    TagType::Script => {
+                        (TagHeader::Script, TagBody::Normal(NormalTagBody::Script(ScriptTagBody::parse(decoder)?)))
+                    }
+                    _ => {
+                        return Err(FlvError::malformed("Invalid tag type"));
+                    }
+                })
+            } else {
+                // the audio and video headers stay in the clear, only the data after the filter params is encrypted.
+                let tag_header = match tag_type {
+                    TagType::Audio => TagHeader::Audio(AudioTagHeader::parse(decoder, &mut header_size)?),
+                    TagType::Video => TagHeader::Video(VideoTagHeader::parse(decoder, &mut header_size)?),
+                    _ => TagHeader::Script,
+                };
+                let header = EncryptionTagHeader::parse(decoder, &mut header_size)?;
+                let params = FilterParameters::parse(decoder, &header, &mut header_size)?;
+                let remaining = decoder.data.len();
+                let data = decoder.drain_bytes_vec(remaining)?;
+
+                let tag_body = match (params.iv(), decoder.decryption_key) {
+                    // a selectively encrypted tag which is not encrypted after all.
+                    (None, _) => decoder.decrypted_tag_body(&tag_type, data)?,
+                    (Some(iv), Some(key)) => {
+                        let plain = Cbc::decrypt(&key, iv, &data)?;
+                        decoder.decrypted_tag_body(&tag_type, plain)?
+                    }
+                    (Some(_), None) => TagBody::Encrypted(match tag_type {
+                        TagType::Audio => EncryptedTagBody::Audio(data),
+                        TagType::Video => EncryptedTagBody::Video(data),
+                        _ => EncryptedTagBody::Script(data),
+                    }),
+                };
+                encryption_header = Some(header);
+                filter_params = Some(params);
+                Ok((tag_header, tag_body))
+            }
+        }).map_err(|e| e.at_timestamp(ts_concatenated))?;
 
         Ok(Tag::new(
             filter,
@@ -324,6 +571,19 @@ impl Decoder {
         ))
     }
 
+    /// Turn the decrypted data of a tag into the body an unencrypted tag would have had.
+    fn decrypted_tag_body(&mut self, tag_type: &TagType, plain: Vec<u8>) -> Result<TagBody, FlvError> {
+        Ok(TagBody::Normal(match tag_type {
+            TagType::Audio => NormalTagBody::Audio(VecDeque::from(plain)),
+            TagType::Video => NormalTagBody::Video(VecDeque::from(plain)),
+            _ => {
+                // only ever called on a tag body bounded by `decode_bounded`, which restores the buffer.
+                self.data = VecDeque::from(plain);
+                NormalTagBody::Script(ScriptTagBody::parse(self)?)
+            }
+        }))
+    }
+
     fn set_decoding(&mut self, flag: bool) {
         self.decoding = flag;
     }
@@ -332,7 +592,7 @@ impl Decoder {
         self.pack_buffer.push_back(pack);
     }
 
-    pub fn decode_body(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn decode_body(&mut self) -> Result<(), FlvError> {
         while let Some(received) = self.pack_buffer.pop_front() {
             if received.packed_routing != Destination::Decoder {
                 self.demuxer.push_pack(received);
@@ -363,90 +623,214 @@ impl Decoder {
             }
         }
 
+        if self.decoding {
+            self.pull_chunk()?;
+        }
+
         // don't throw error here! it will interrupt the decoding process and lead to unexpected behavior.
         'decoding: loop {
             if self.data.is_empty() || (!self.decoding) {
                 //return Err("No more data.".into())
                 break 'decoding;
             }
-            if let Err(e) = self.decode_body_once() {
-                //return Err(e);
+            // anything but the flv header itself is worth an error here, there is nothing to resync to.
+            if !self.try_decode_header()? {
                 break 'decoding;
             }
+            match self.next_tags() {
+                Ok(Some(tags)) => {
+                    for tag in tags {
+                        self.send_tag_to_demuxer(tag)?;
+                    }
+                }
+                Ok(None) => break 'decoding,
+                Err(e) => {
+                    println!("[Decoder] Decoding error: {}", e);
+                    if !self.resync_enabled {
+                        break 'decoding;
+                    }
+                }
+            }
         }
         Ok(())
     }
 
-    pub fn decode_body_once(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        const HEADER_SIZE: u32 = 11;
+    /// Decode the next tags, after resynchronising if the previous ones were corrupt.
+    /// Returns None if more data is needed first. After an error, the next call resyncs if that is enabled.
+    fn next_tags(&mut self) -> Result<Option<Vec<Tag>>, FlvError> {
+        if self.resyncing {
+            match self.resync() {
+                Ok(0) => {}
+                Ok(skipped) => {
+                    println!("[Decoder] Resynchronised at byte {} after skipping {} bytes.", self.stream_offset, skipped);
+                }
+                // the next candidate can not be verified yet, wait for more data.
+                Err(_) => return Ok(None),
+            }
+        }
+        match self.decode_next_tags() {
+            Ok(tags) => Ok(Some(tags)),
+            // a partially received tag is simply left in the buffer until more data is pushed.
+            Err(e) if e.is_insufficient_data() => Ok(None),
+            Err(e) => {
+                if self.resync_enabled {
+                    self.resyncing = true;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Checks whether a plausible tag header starts at `offset`:
+    /// a known tag type, a sane data size, a zero stream id,
+    /// and a trailing PreviousTagSize which matches the tag.
+    fn is_plausible_tag_at(&self, offset: usize) -> Result<bool, FlvError> {
+        self.ensure_available(offset + TAG_HEADER_SIZE)?;
 
-        // todo: [IMPORTANT] this is not safe. fix it!
-        // for instance, when the video is streamed, the data in the buffer may be insufficient to read.
-        // however the program has already acquired the tag size and will make an attempt to read.
-        // this may cause the program to panic!!
+        let type_byte = self.data[offset];
+        // the two reserved bits must be zero, the filter bit may be set.
+        if type_byte & 0xC0 != 0 || !matches!(type_byte & 0x1F, 8 | 9 | 18) {
+            return Ok(false);
+        }
+        let data_size = self.peek_u24_at(offset + 1)?;
+        if data_size == 0 || data_size > MAX_RESYNC_TAG_SIZE {
+            return Ok(false);
+        }
+        if self.peek_u24_at(offset + 8)? != 0 {
+            return Ok(false);
+        }
+        let trailing_tag_size = self.peek_u32_at(offset + TAG_HEADER_SIZE + data_size as usize)?;
+        Ok(trailing_tag_size == data_size + TAG_HEADER_SIZE as u32)
+    }
 
-        // possible solution: don't drain buffer to get the size first. use slice instead.
-        // check whether the buffer has sufficient data to read.
-        // if so, drain the 'size', and read buffer.
-        // otherwise, PAUSE IT!
+    /// Skip forward to the next plausible tag, and return the number of bytes skipped.
+    /// Afterwards the buffer starts with the PreviousTagSize in front of that tag,
+    /// so `decode_body_once` continues from there as usual.
+    ///
+    /// Note: if a candidate can not be verified with the buffered data,
+    /// the bytes already ruled out are dropped and `FlvError::InsufficientData` is returned.
+    /// Call it again after pushing more data, the skipped bytes are summed up across calls.
+    pub fn resync(&mut self) -> Result<usize, FlvError> {
+        self.resyncing = true;
+
+        let mut candidate = PREVIOUS_TAG_SIZE_LENGTH;
+        let found = loop {
+            match self.is_plausible_tag_at(candidate) {
+                Ok(true) => break Ok(()),
+                Ok(false) => candidate += 1,
+                Err(e) => break Err(e),
+            }
+        };
 
-        let previous_tag_size = self.drain_u32();
+        // everything in front of the candidate has been ruled out.
+        let skipped = candidate - PREVIOUS_TAG_SIZE_LENGTH;
+        self.skip_bytes(skipped)?;
+        self.resync_skipped += skipped;
+        self.skipped_bytes += skipped as u64;
+        found?;
+
+        // the 4 bytes in front of the tag may be damaged as well, so take them as they are.
+        self.previous_tag_size = self.peek_u32_at(0)?;
+        self.resyncing = false;
+        Ok(std::mem::take(&mut self.resync_skipped))
+    }
 
-        if self.data.is_empty() {
-            return Err("No more data.".into());
+    pub fn decode_body_once(&mut self) -> Result<(), FlvError> {
+        for tag in self.decode_next_tags()? {
+            self.send_tag_to_demuxer(tag)?;
         }
+        Ok(())
+    }
 
-        //dbg!(previous_tag_size);
-        if previous_tag_size == self.previous_tag_size {
-
-            // check whether the next tag size is sufficient to read.
-            if let Ok(next_tag_size) = self.peek_tag_size() {
-                if next_tag_size + HEADER_SIZE > self.data.len() as u32 {
-                    // insufficient data.
-                    // put back the previous tag size.
-                    for i in previous_tag_size.to_le_bytes() {
-                        self.data.push_front(i)
-                    }
-                    return Err(
-                        format!("Insufficient data for decoding. Expected minimum buffer size {}, real size {}.",
-                                next_tag_size + HEADER_SIZE,
-                                self.data.len()
-                        ).into()
-                    );
+    /// Decode the next tag, and return what the observers left of it.
+    fn decode_next_tags(&mut self) -> Result<Vec<Tag>, FlvError> {
+        // the previous tag size, the tag header and the tag body must all be buffered
+        // before anything is drained, so that an incomplete tag can be retried later.
+        // corruption that is visible early is reported early though,
+        // otherwise a damaged size field could keep the decoder waiting for data forever.
+        let previous_tag_size = self.peek_u32_at(0)?;
+        if previous_tag_size != self.previous_tag_size {
+            return Err(
+                FlvError::malformed(format!("Tag size mismatch: expected {}, read {}.", self.previous_tag_size, previous_tag_size))
+                    .at_offset(self.stream_offset)
+                    .at_tag(self.tag_index)
+            );
+        }
+        self.ensure_available(PREVIOUS_TAG_SIZE_LENGTH + 1)?;
+        TagType::from(BitIO::new(self.data[PREVIOUS_TAG_SIZE_LENGTH]).read_range(3, 7))
+            .map_err(|e| e.at_offset(self.stream_offset + PREVIOUS_TAG_SIZE_LENGTH as u64).at_tag(self.tag_index))?;
+
+        let next_tag_size = self.peek_u24_at(PREVIOUS_TAG_SIZE_LENGTH + 1)?;
+        self.ensure_available(PREVIOUS_TAG_SIZE_LENGTH + TAG_HEADER_SIZE + next_tag_size as usize)?;
+        self.drain_u32()?;
+
+        let tag_offset = self.stream_offset;
+        let mut tag = self.decode_tag().map_err(|e| e.at_offset(tag_offset).at_tag(self.tag_index))?;
+        self.tag_index += 1;
+        // only `timestamp` is rewritten, the short and the extended part are kept as they were read.
+        let (timestamp, discontinuity) = self.timestamps.track(&tag.tag_type, tag.timestamp);
+        tag.timestamp = timestamp;
+        tag.discontinuity = discontinuity;
+        //dbg!(tag.data_size + HEADER_SIZE);
+        self.previous_tag_size = tag.data_size + TAG_HEADER_SIZE as u32;
+
+        if let TagBody::Normal(NormalTagBody::Script(ref script)) = tag.tag_body {
+            if script.name.data == "onMetaData" {
+                if let Some(keyframes) = script.value.properties.iter().find(|prop| prop.name.data == "keyframes") {
+                    self.keyframes = KeyframeIndex::parse(&keyframes.value);
                 }
-            } else {
-                for i in previous_tag_size.to_le_bytes() {
-                    self.data.push_front(i)
+                let metadata = MetaData::from(&RawMetaData::new(script.clone()));
+                if !metadata.invalid_properties.is_empty() {
+                    println!("[Decoder] Ignored invalid onMetaData properties: {}", metadata.invalid_properties.join(", "));
                 }
-                return Err("Insufficient data for peeking size of next tag.".into());
+                self.metadata = Some(metadata);
+            }
+            if let Some(xmp) = XMPData::parse(script) {
+                self.xmp = Some(xmp);
             }
+        }
 
+        // dbg!(&tag);
+        Ok(self.observe_tag(tag, tag_offset))
+    }
 
-            let tag = self.decode_tag()?;
-            //dbg!(tag.data_size + HEADER_SIZE);
-            self.previous_tag_size = tag.data_size + HEADER_SIZE;
+    /// Decode the next tags without passing them to the demuxer, resynchronising as `decode_body` does.
+    /// Returns None if more data is needed first.
+    pub(crate) fn poll_tags(&mut self) -> Result<Option<Vec<Tag>>, FlvError> {
+        if !self.try_decode_header()? {
+            return Ok(None);
+        }
+        self.next_tags()
+    }
 
-            // dbg!(&tag);
-            self.send_tag_to_demuxer(tag)?;
-            Ok(())
-        } else {
-            Err(
-                Box::new(
-                    std::io::Error::new(
-                        std::io::ErrorKind::InvalidData,
-                        format!("Tag size mismatch: expected {}, read {}.", previous_tag_size, self.previous_tag_size),
-                    )
-                )
-            )
+    #[inline]
+    pub(crate) fn is_resync_enabled(&self) -> bool {
+        self.resync_enabled
+    }
+
+    /// Run `tag` through all the observers, and return what is left of it.
+    fn observe_tag(&mut self, tag: Tag, byte_offset: u64) -> Vec<Tag> {
+        let mut tags = vec![tag];
+        for observer in self.tag_observers.iter_mut() {
+            let mut observed = Vec::with_capacity(tags.len());
+            for mut tag in tags {
+                match observer.observe(&mut tag, byte_offset) {
+                    TagAction::Pass => observed.push(tag),
+                    TagAction::Drop => {}
+                    TagAction::Replace(replacement) => observed.extend(replacement),
+                }
+            }
+            tags = observed;
         }
+        tags
     }
 
-    fn send_to_demuxer(&mut self, pack: Packed) -> Result<(), Box<dyn std::error::Error>> {
+    fn send_to_demuxer(&mut self, pack: Packed) -> Result<(), FlvError> {
         self.demuxer.push_pack(pack);
         Ok(())
     }
 
-    fn send_tag_to_demuxer(&mut self, tag: Tag) -> Result<(), Box<dyn std::error::Error>> {
+    fn send_tag_to_demuxer(&mut self, tag: Tag) -> Result<(), FlvError> {
         let pack: Packed = Packed {
             packed_routing: Destination::Demuxer,
             packed_content: PackedContent::ToDemuxer(PackedContentToDemuxer::PushTag(tag)),
@@ -454,7 +838,7 @@ impl Decoder {
         self.send_to_demuxer(pack)
     }
 
-    fn send_header_to_demuxer(&mut self, flv_header: FlvHeader) -> Result<(), Box<dyn std::error::Error>> {
+    fn send_header_to_demuxer(&mut self, flv_header: FlvHeader) -> Result<(), FlvError> {
         let pack: Packed = Packed {
             packed_routing: Destination::Demuxer,
             packed_content: PackedContent::ToDemuxer(PackedContentToDemuxer::PushFlvHeader(flv_header)),
@@ -462,13 +846,12 @@ impl Decoder {
         self.send_to_demuxer(pack)
     }
 
-    pub fn run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn run(&mut self) -> Result<(), FlvError> {
         // todo: [IMPORTANT] add some mechanism to reset the demuxer-remuxer instance.
-        loop {
-            // this is to ensure the header is read.
-            if let Ok(flv_header) = self.decode_header() {
-                self.send_header_to_demuxer(flv_header)?;
-                break;
+        // without a source, an incomplete header is left for `continue_decoding` after more data is pushed.
+        while !self.try_decode_header()? && self.source.is_some() {
+            if self.pull_chunk()? == 0 {
+                return Err(FlvError::malformed("The source ended before the flv header."));
             }
         }
         self.decode_body()?;
@@ -476,15 +859,100 @@ impl Decoder {
         Ok(())
     }
 
+    /// The keyframe index from onMetaData, if the stream has one.
+    #[inline]
+    pub fn keyframes(&self) -> Option<&KeyframeIndex> {
+        self.keyframes.as_ref()
+    }
+
+    /// The typed onMetaData of the stream, as soon as its script tag has been decoded.
+    /// A later onMetaData replaces it. Invalid properties are None, and listed in `invalid_properties`.
+    #[inline]
+    pub fn metadata(&self) -> Option<&MetaData> {
+        self.metadata.as_ref()
+    }
+
+    /// The XMP packet from onXMPData, as soon as its script tag has been decoded.
+    #[inline]
+    pub fn xmp(&self) -> Option<&XMPData> {
+        self.xmp.as_ref()
+    }
+
+    /// Use `keyframes` for seeking, e.g. one built by `KeyframeIndexer` for a file without an index.
+    /// A `keyframes` object in onMetaData decoded later replaces it.
+    pub fn set_keyframes(&mut self, keyframes: KeyframeIndex) {
+        self.keyframes = Some(keyframes);
+    }
+
+    /// Jump to the last keyframe at or before `time_ms`, and return the time of that keyframe.
+    /// This needs a seekable source, see `from_seekable_reader`, and a keyframe index:
+    /// either the one in onMetaData, so the stream must have been decoded past its metadata first,
+    /// or one passed to `set_keyframes`.
+    ///
+    /// Everything which has not been remuxed yet is dropped, including unconsumed fragments.
+    /// The init segment stays valid, and the next fragments carry their original decode times.
+    pub fn seek_to(&mut self, time_ms: u32) -> Result<u32, FlvError> {
+        let (keyframe_time, position) = self.keyframes
+            .as_ref()
+            .and_then(|keyframes| keyframes.nearest_preceding(time_ms))
+            .ok_or(FlvError::internal("No keyframe index available, unable to seek."))?;
+        let source = self.source
+            .as_mut()
+            .ok_or(FlvError::internal("No source attached, unable to seek."))?;
+
+        // the positions point at the tag itself, decoding resumes at the PreviousTagSize in front of it.
+        let position = position.saturating_sub(PREVIOUS_TAG_SIZE_LENGTH as u64);
+        source.seek_to(position)?;
+        self.source_exhausted = false;
+        self.data.clear();
+        self.stream_offset = position;
+        self.pack_buffer.retain(|pack| !matches!(pack.packed_content, PackedContent::ToDecoder(PackedContentToDecoder::PushData(_))));
+        self.demuxer.reset_for_seek();
+        self.timestamps.reset();
+
+        // the index may be slightly off, or point somewhere else entirely.
+        // a resync lands on the first plausible tag from there, and accepts its PreviousTagSize.
+        self.resyncing = true;
+        self.resync_skipped = 0;
+        println!("[Decoder] Seeking to {}ms, keyframe at {}ms, byte {}.", time_ms, keyframe_time, position);
+        Ok(keyframe_time)
+    }
+
     /// Continue decoding.
     /// To restore the decoder from idle state, call `continue_decoding`.
     /// Do not call this before calling `start`!!
-    pub fn continue_decoding(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn continue_decoding(&mut self) -> Result<(), FlvError> {
         self.decode_body()?;
         self.demuxer.run()?;
         Ok(())
     }
 
+    /// Leave the audio or the video out of the output, e.g. for an audio-only player.
+    /// Tags of a disabled track are dropped, and it gets no `trak` and no codec string.
+    /// Call it before decoding starts, the tracks are fixed once the initialization segment is out.
+    pub fn set_track_enabled(&mut self, track_type: TrackType, enabled: bool) {
+        self.demuxer.remuxer.set_track_enabled(track_type, enabled);
+    }
+
+    /// Set how many audio and video tags are looked at before a track which the flv header announces,
+    /// but which does not show up, is given up on. 64 by default.
+    /// Unless both track types show up earlier, the initialization segment waits for these tags,
+    /// so a track the flv header leaves out still gets its `trak`.
+    pub fn set_probe_limit(&mut self, tags: u32) {
+        self.demuxer.remuxer.set_probe_limit(tags);
+    }
+
+    /// Where the flv header flags or the metadata turned out to be wrong about the tracks and codecs.
+    pub fn probe_warnings(&self) -> &[ProbeWarning] {
+        self.demuxer.remuxer.probe_warnings()
+    }
+
+    /// Demux only: iterate over the elementary frames and codec configuration records of the stream,
+    /// without remuxing them into fmp4. The data already pushed and the source, if any, are read from.
+    pub fn into_frames(self) -> ElementaryFrames {
+        ElementaryFrames::new(self)
+    }
+
     /// Launch a worker thread that will read from the stream and send the data to the demuxer.
     /// After calling this method, the decoder instance will be moved away from the main thread.
     /// Instead, use the exchange to manipulate the decoder.
@@ -498,24 +966,24 @@ impl Decoder {
 }
 
 impl Decoder {
-    pub fn send(&mut self, pack: Packed) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn send(&mut self, pack: Packed) -> Result<(), FlvError> {
         self.pack_buffer.push_back(pack);
         Ok(())
     }
 
-    pub fn push_data_to_decoder(&mut self, data: &mut VecDeque<u8>) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn push_data_to_decoder(&mut self, data: &mut VecDeque<u8>) -> Result<(), FlvError> {
         self.data.append(data);
         Ok(())
     }
 
-    pub fn start(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn start(&mut self) -> Result<(), FlvError> {
         self.start_decoding()?;
         self.start_demuxing()?;
         self.start_remuxing()?;
         Ok(())
     }
 
-    fn start_decoding(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    fn start_decoding(&mut self) -> Result<(), FlvError> {
         // todo: when the video stream is chunked, it's necessary to 'wait' for the next chunk than simply break the decoder loop.
         self.send(
             Packed {
@@ -527,7 +995,7 @@ impl Decoder {
         )
     }
 
-    fn start_demuxing(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    fn start_demuxing(&mut self) -> Result<(), FlvError> {
         self.send(
             Packed {
                 packed_routing: Destination::Demuxer,
@@ -538,7 +1006,7 @@ impl Decoder {
         )
     }
 
-    fn start_remuxing(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    fn start_remuxing(&mut self) -> Result<(), FlvError> {
         self.send(
             Packed {
                 packed_routing: Destination::Remuxer,
@@ -549,14 +1017,14 @@ impl Decoder {
         )
     }
 
-    pub fn stop(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn stop(&mut self) -> Result<(), FlvError> {
         self.stop_decoding()?;
         self.stop_demuxing()?;
         self.stop_remuxing()?;
         Ok(())
     }
 
-    fn stop_decoding(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    fn stop_decoding(&mut self) -> Result<(), FlvError> {
         self.send(
             Packed {
                 packed_routing: Destination::Decoder,
@@ -567,7 +1035,7 @@ impl Decoder {
         )
     }
 
-    fn stop_demuxing(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    fn stop_demuxing(&mut self) -> Result<(), FlvError> {
         self.send(
             Packed {
                 packed_routing: Destination::Demuxer,
@@ -578,7 +1046,7 @@ impl Decoder {
         )
     }
 
-    fn stop_remuxing(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    fn stop_remuxing(&mut self) -> Result<(), FlvError> {
         self.send(
             Packed {
                 packed_routing: Destination::Remuxer,
@@ -589,14 +1057,14 @@ impl Decoder {
         )
     }
 
-    pub fn now(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn now(&mut self) -> Result<(), FlvError> {
         self.decode_now()?;
         self.demux_now()?;
         self.remux_now()?;
         Ok(())
     }
 
-    fn decode_now(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    fn decode_now(&mut self) -> Result<(), FlvError> {
         self.send(
             Packed {
                 packed_routing: Destination::Decoder,
@@ -607,7 +1075,7 @@ impl Decoder {
         )
     }
 
-    fn demux_now(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    fn demux_now(&mut self) -> Result<(), FlvError> {
         self.send(
             Packed {
                 packed_routing: Destination::Demuxer,
@@ -618,7 +1086,7 @@ impl Decoder {
         )
     }
 
-    fn remux_now(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    fn remux_now(&mut self) -> Result<(), FlvError> {
         self.send(
             Packed {
                 packed_routing: Destination::Remuxer,
@@ -629,14 +1097,14 @@ impl Decoder {
         )
     }
 
-    pub fn drop_all_workers(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn drop_all_workers(&mut self) -> Result<(), FlvError> {
         self.drop_decoding_worker()?;
         self.drop_demuxing_worker()?;
         self.drop_remuxing_worker()?;
         Ok(())
     }
 
-    fn drop_decoding_worker(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    fn drop_decoding_worker(&mut self) -> Result<(), FlvError> {
         self.send(
             Packed {
                 packed_routing: Destination::Decoder,
@@ -647,7 +1115,7 @@ impl Decoder {
         )
     }
 
-    fn drop_demuxing_worker(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    fn drop_demuxing_worker(&mut self) -> Result<(), FlvError> {
         self.send(
             Packed {
                 packed_routing: Destination::Demuxer,
@@ -658,7 +1126,7 @@ impl Decoder {
         )
     }
 
-    fn drop_remuxing_worker(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    fn drop_remuxing_worker(&mut self) -> Result<(), FlvError> {
         self.send(
             Packed {
                 packed_routing: Destination::Remuxer,
@@ -669,11 +1137,16 @@ impl Decoder {
         )
     }
 
-    pub fn consume(&mut self) -> Result<RemuxedData, Box<dyn std::error::Error>> {
+    pub fn consume(&mut self) -> Result<RemuxedData, FlvError> {
         self.demuxer.remuxer.core.consume()
     }
 
-    pub fn get_codec_conf(&mut self) -> Result<(String, String), Box<dyn std::error::Error>> {
+    /// Take the next script event, see `Core::consume_event`.
+    pub fn consume_event(&mut self) -> Result<Option<ScriptEvent>, FlvError> {
+        self.demuxer.remuxer.core.consume_event()
+    }
+
+    pub fn get_codec_conf(&mut self) -> Result<(String, String), FlvError> {
         self.demuxer.remuxer.core.get_codec_conf()
     }
 
@@ -681,11 +1154,19 @@ impl Decoder {
         self.demuxer.remuxer.core.try_get_codec_conf()
     }
 
+    pub fn try_get_codecs(&mut self) -> Option<TrackCodecs> {
+        self.demuxer.remuxer.core.try_get_codecs()
+    }
+
+    pub fn get_codecs(&mut self) -> Result<TrackCodecs, FlvError> {
+        self.demuxer.remuxer.core.get_codecs()
+    }
+
     pub fn get_codec_conf_or_default(&mut self) -> Result<(String, String), (String, String)> {
         self.demuxer.remuxer.core.get_codec_conf_or_default()
     }
 
-    pub fn get_codec_conf_with_timeout(&mut self, timeout: std::time::Duration) -> Result<(String, String), Box<dyn std::error::Error>> {
+    pub fn get_codec_conf_with_timeout(&mut self, timeout: std::time::Duration) -> Result<(String, String), FlvError> {
         self.demuxer.remuxer.core.get_codec_conf_with_timeout(timeout)
     }
 }
\ No newline at end of file
@@ -1,3 +1,4 @@
+use crate::error::FlvError;
 use crate::exchange::{Destination, Packed, PackedContent, PackedContentToDemuxer, PackedContentToRemuxer};
 use crate::flv::header::FlvHeader;
 use crate::flv::meta::RawMetaData;
@@ -58,12 +59,20 @@ impl Demuxer {
         self.pack_buffer.push_back(pack);
     }
 
-    fn send_to_remuxer(&mut self, pack: Packed) -> Result<(), Box<dyn std::error::Error>> {
+    /// Drop every tag which has not been passed on yet, after the input jumped.
+    pub(crate) fn reset_for_seek(&mut self) {
+        self.pack_buffer.retain(|pack| !matches!(pack.packed_content, PackedContent::ToDemuxer(PackedContentToDemuxer::PushTag(_))));
+        self.cache_media_tags.clear();
+        self.cache_script_tags.clear();
+        self.remuxer.reset_for_seek();
+    }
+
+    fn send_to_remuxer(&mut self, pack: Packed) -> Result<(), FlvError> {
         self.remuxer.push_pack(pack);
         Ok(())
     }
 
-    fn send_from_cache(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    fn send_from_cache(&mut self) -> Result<(), FlvError> {
         if let Some(flv_header) = self.cache_flv_header.take() {
             let pack = Packed {
                 packed_routing: Destination::Remuxer,
@@ -99,7 +108,7 @@ impl Demuxer {
         Ok(())
     }
 
-    pub fn run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn run(&mut self) -> Result<(), FlvError> {
         while let Some(received) = self.pack_buffer.pop_front() {
             if received.packed_routing != Destination::Demuxer {
                 self.remuxer.push_pack(received);
@@ -0,0 +1,93 @@
+use crate::flv::script::{ScriptData, ScriptDataObjectProp, ScriptTagBody};
+
+/// What a script tag other than onMetaData carries, for the well-known handlers.
+/// Properties which are missing or of an unexpected type are None.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptEventKind {
+    /// onCuePoint, as written by flash encoders and ad insertion.
+    CuePoint {
+        name: Option<String>,
+        /// Usually "event" or "navigation".
+        cue_type: Option<String>,
+        /// In seconds, as written in the cue point, which may differ from the tag timestamp.
+        time: Option<f64>,
+        parameters: Vec<ScriptDataObjectProp>,
+    },
+    /// onTextData, timed text.
+    TextData {
+        text: Option<String>,
+        language: Option<String>,
+        track_id: Option<f64>,
+    },
+    /// onCaption, closed captions, usually base64 encoded CEA-608 or CEA-708 data.
+    Caption {
+        caption_type: Option<String>,
+        data: Option<String>,
+    },
+    /// onFI, the wall clock time of the frame, as a date like "01-01-2024" and a time like "12:00:00.000".
+    FrameInfo {
+        date: Option<String>,
+        time: Option<String>,
+    },
+    /// Any other handler, see `ScriptEvent::body`.
+    Other,
+}
+
+/// A script tag which is passed to the consumer at its timestamp, instead of being remuxed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptEvent {
+    /// The timestamp of the script tag in milliseconds, on the same clock as the audio and video tags.
+    pub presentation_time: u32,
+    pub kind: ScriptEventKind,
+    /// The whole tag body, for the handler name and anything the kind does not cover.
+    pub body: ScriptTagBody,
+}
+
+impl ScriptEvent {
+    pub fn new(presentation_time: u32, body: ScriptTagBody) -> Self {
+        let find = |key: &str| body.value.properties.iter().find(|prop| prop.name.data == key).map(|prop| &prop.value);
+        let string = |key: &str| match find(key) {
+            Some(ScriptData::String(string)) => Some(string.data.clone()),
+            Some(ScriptData::LongString(string)) => Some(string.data.clone()),
+            _ => None,
+        };
+        let number = |key: &str| match find(key) {
+            Some(ScriptData::Number(number)) => Some(*number),
+            _ => None,
+        };
+
+        let kind = match body.name.data.as_str() {
+            "onCuePoint" => ScriptEventKind::CuePoint {
+                name: string("name"),
+                cue_type: string("type"),
+                time: number("time"),
+                parameters: match find("parameters") {
+                    Some(ScriptData::Object(object)) => object.properties.clone(),
+                    Some(ScriptData::EcmaArray(array)) => array.properties.clone(),
+                    _ => vec![],
+                },
+            },
+            "onTextData" => ScriptEventKind::TextData {
+                text: string("text"),
+                language: string("language"),
+                track_id: number("trackid"),
+            },
+            "onCaption" => ScriptEventKind::Caption {
+                caption_type: string("type"),
+                data: string("data"),
+            },
+            "onFI" => ScriptEventKind::FrameInfo {
+                date: string("sd"),
+                time: string("st"),
+            },
+            _ => ScriptEventKind::Other,
+        };
+        Self { presentation_time, kind, body }
+    }
+
+    /// The name of the handler, e.g. "onCuePoint".
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.body.name.data
+    }
+}
@@ -0,0 +1,218 @@
+use crate::error::FlvError;
+use crate::flv::decoder::Decoder;
+use crate::flv::tag::{Tag, TagType};
+use crate::fmpeg::parser::{AudioParseResult, Avc1ParseResult, AvcNalu, ExAudioConfig, ExAudioParseResult, ExVideoParseResult, KeyframeType, Parser, VideoParseResult};
+use crate::fmpeg::remux_context::{AudioCodecType, VideoCodecType};
+use std::collections::{BTreeMap, VecDeque};
+
+/// The codec of an elementary stream, which also tells its track type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameCodec {
+    Audio(AudioCodecType),
+    Video(VideoCodecType),
+}
+
+impl FrameCodec {
+    #[inline]
+    pub fn is_audio(&self) -> bool {
+        matches!(self, FrameCodec::Audio(_))
+    }
+
+    #[inline]
+    pub fn is_video(&self) -> bool {
+        matches!(self, FrameCodec::Video(_))
+    }
+}
+
+/// A codec configuration record, as the decoder of the track needs it:
+/// the AudioSpecificConfig for aac, the body of `dOps`, `dfLa`, `dac3` or `dec3` for the other audio codecs,
+/// and the AVCDecoderConfigurationRecord, `hvcC`, `av1C` or `vpcC` body for video.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodecConfig {
+    pub track_id: u8,
+    pub codec: FrameCodec,
+    pub record: Vec<u8>,
+}
+
+/// One access unit of an elementary stream, with the payload as it is in the tag.
+/// That is, length prefixed NAL units for avc and hevc, and raw frames for aac.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElementaryFrame {
+    pub track_id: u8,
+    pub codec: FrameCodec,
+    /// Decode timestamp in milliseconds, i.e. the tag timestamp.
+    pub dts: u32,
+    /// Presentation timestamp in milliseconds, the dts plus the composition time offset.
+    pub pts: u32,
+    pub keyframe: bool,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ElementaryItem {
+    /// Comes before the frames of a track, and again whenever the record changes.
+    Config(CodecConfig),
+    Frame(ElementaryFrame),
+}
+
+/// Iterator over the elementary frames of a flv stream, see `Decoder::into_frames`.
+/// Tags are decoded, tracked and observed just as in the full pipeline, but never remuxed.
+///
+/// Errors are yielded as they occur. With resync enabled the iteration continues after them,
+/// otherwise an error ends it.
+pub struct ElementaryFrames {
+    decoder: Decoder,
+    pending: VecDeque<ElementaryItem>,
+    /// The last record per track, keyed by whether it is a video track and the track id.
+    configs: BTreeMap<(bool, u8), Vec<u8>>,
+    finished: bool,
+}
+
+impl ElementaryFrames {
+    pub fn new(decoder: Decoder) -> Self {
+        Self {
+            decoder,
+            pending: VecDeque::new(),
+            configs: BTreeMap::new(),
+            finished: false,
+        }
+    }
+
+    /// The decoder underneath, e.g. for the metadata or the keyframe index.
+    #[inline]
+    pub fn decoder(&self) -> &Decoder {
+        &self.decoder
+    }
+
+    #[inline]
+    pub fn decoder_mut(&mut self) -> &mut Decoder {
+        &mut self.decoder
+    }
+
+    #[inline]
+    pub fn into_decoder(self) -> Decoder {
+        self.decoder
+    }
+
+    fn push_config(&mut self, track_id: u8, codec: FrameCodec, record: Vec<u8>) {
+        let key = (codec.is_video(), track_id);
+        if self.configs.get(&key) == Some(&record) {
+            return;
+        }
+        self.configs.insert(key, record.clone());
+        self.pending.push_back(ElementaryItem::Config(CodecConfig { track_id, codec, record }));
+    }
+
+    fn push_frame(&mut self, track_id: u8, codec: FrameCodec, dts: u32, composition_time_offset: i32, keyframe: bool, payload: Vec<u8>) {
+        let pts = (dts as i64 + composition_time_offset as i64).clamp(0, u32::MAX as i64) as u32;
+        self.pending.push_back(ElementaryItem::Frame(ElementaryFrame { track_id, codec, dts, pts, keyframe, payload }));
+    }
+
+    fn push_nalu(&mut self, track_id: u8, codec: VideoCodecType, dts: u32, nalu: AvcNalu) {
+        let keyframe = matches!(nalu.keyframe_type, KeyframeType::Keyframe);
+        self.push_frame(track_id, FrameCodec::Video(codec), dts, nalu.composition_time_offset, keyframe, nalu.payload.into());
+    }
+
+    fn push_ex_audio(&mut self, track_id: u8, codec: AudioCodecType, dts: u32, parsed: ExAudioParseResult) {
+        let codec = FrameCodec::Audio(codec);
+        let push_config = |frames: &mut Self, config: ExAudioConfig| frames.push_config(track_id, codec, config.config_box.payload().clone());
+        match parsed {
+            ExAudioParseResult::SequenceStart(config) => push_config(self, config),
+            ExAudioParseResult::CodedFrames(frame) => {
+                if let Some(config) = frame.config {
+                    push_config(self, config);
+                }
+                self.push_frame(track_id, codec, dts, 0, true, frame.payload);
+            }
+            ExAudioParseResult::SequenceEnd | ExAudioParseResult::MultichannelConfig => {}
+        }
+    }
+
+    fn push_tag(&mut self, tag: Tag) -> Result<(), FlvError> {
+        let dts = tag.timestamp;
+        match tag.tag_type {
+            TagType::Audio => {
+                for (track_id, parsed) in Parser::parse_audio_tracks(&tag)? {
+                    match parsed {
+                        AudioParseResult::AacSequenceHeader(header) => self.push_config(track_id, FrameCodec::Audio(AudioCodecType::Aac), header.raw.into()),
+                        AudioParseResult::AacRaw(raw) => self.push_frame(track_id, FrameCodec::Audio(AudioCodecType::Aac), dts, 0, true, raw.into()),
+                        AudioParseResult::Mp3(mp3) => self.push_frame(track_id, FrameCodec::Audio(AudioCodecType::Mp3), dts, 0, true, mp3.body),
+                        AudioParseResult::Opus(parsed) => self.push_ex_audio(track_id, AudioCodecType::Opus, dts, parsed),
+                        AudioParseResult::Flac(parsed) => self.push_ex_audio(track_id, AudioCodecType::Flac, dts, parsed),
+                        AudioParseResult::Ac3(parsed) => self.push_ex_audio(track_id, AudioCodecType::Ac3, dts, parsed),
+                        AudioParseResult::Eac3(parsed) => self.push_ex_audio(track_id, AudioCodecType::Eac3, dts, parsed),
+                    }
+                }
+            }
+            TagType::Video => {
+                for (track_id, parsed) in Parser::parse_video_tracks(&tag)? {
+                    let (codec, parsed) = match parsed {
+                        VideoParseResult::Avc1(parsed) => {
+                            match parsed {
+                                Avc1ParseResult::AvcSequenceHeader(record) => self.push_config(track_id, FrameCodec::Video(VideoCodecType::Avc1), record.into()),
+                                Avc1ParseResult::AvcNalu(nalu) => self.push_nalu(track_id, VideoCodecType::Avc1, dts, nalu),
+                                Avc1ParseResult::AvcEndOfSequence => {}
+                            }
+                            continue;
+                        }
+                        VideoParseResult::Hvc1(parsed) | VideoParseResult::LegacyHevc(parsed) => (VideoCodecType::Hvc1, parsed),
+                        VideoParseResult::Av01(parsed) => (VideoCodecType::Av01, parsed),
+                        VideoParseResult::Vp09(parsed) => (VideoCodecType::Vp09, parsed),
+                    };
+                    match parsed {
+                        ExVideoParseResult::SequenceStart(record) => self.push_config(track_id, FrameCodec::Video(codec), record.into()),
+                        ExVideoParseResult::CodedFrames(nalu) => self.push_nalu(track_id, codec, dts, nalu),
+                        ExVideoParseResult::SequenceEnd | ExVideoParseResult::Mpeg2TsSequenceStart(_) | ExVideoParseResult::Metadata => {}
+                    }
+                }
+            }
+            // script data is available from the decoder, e.g. `Decoder::metadata`.
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for ElementaryFrames {
+    type Item = Result<ElementaryItem, FlvError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.pending.pop_front() {
+                return Some(Ok(item));
+            }
+            if self.finished {
+                return None;
+            }
+            match self.decoder.poll_tags() {
+                Ok(Some(tags)) => {
+                    // a tag which the parser can not make sense of does not break the stream,
+                    // the frames of the other tags are yielded after the error.
+                    let mut error = None;
+                    for tag in tags {
+                        if let Err(e) = self.push_tag(tag) {
+                            error.get_or_insert(e);
+                        }
+                    }
+                    if let Some(e) = error {
+                        return Some(Err(e));
+                    }
+                }
+                Ok(None) => match self.decoder.pull_chunk() {
+                    Ok(0) => self.finished = true,
+                    Ok(_) => {}
+                    Err(e) => {
+                        self.finished = true;
+                        return Some(Err(e));
+                    }
+                },
+                Err(e) => {
+                    if !self.decoder.is_resync_enabled() {
+                        self.finished = true;
+                    }
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
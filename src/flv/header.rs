@@ -1,3 +1,4 @@
+use crate::error::FlvError;
 use crate::flv::decoder::Decoder;
 use crate::io::bit::BitIO;
 
@@ -27,6 +28,7 @@ pub enum TagHeader {
 #[derive(Debug, Clone)]
 pub struct AudioTagHeader {
     // UB4
+    // 9 for the enhanced rtmp ex-header
     pub sound_format: u8,
     // UB2
     pub sound_rate: u8,
@@ -37,77 +39,298 @@ pub struct AudioTagHeader {
     // UI8
     // if sound_format == 10
     pub aac_packet_type: Option<u8>,
+    // UB4
+    // if sound_format == 9, in place of the rate, size and type
+    // for multitrack tags, the packet type shared by every track
+    pub audio_packet_type: Option<AudioPacketType>,
+    // UB4
+    // if audio_packet_type == Multitrack
+    pub multitrack_type: Option<AvMultitrackType>,
+    // FOURCC
+    // if sound_format == 9, unless multitrack_type == ManyTracksManyCodecs
+    pub fourcc: Option<[u8; 4]>,
 }
 
 impl AudioTagHeader {
     pub fn new(sound_format: u8, sound_rate: u8, sound_size: bool, sound_type: bool, aac_packet_type: Option<u8>) -> Self {
-        Self { sound_format, sound_rate, sound_size, sound_type, aac_packet_type }
+        Self { sound_format, sound_rate, sound_size, sound_type, aac_packet_type, audio_packet_type: None, multitrack_type: None, fourcc: None }
     }
 
-    pub fn parse(decoder: &mut Decoder, header_size: &mut usize) -> Result<Self, Box<dyn std::error::Error>> {
+    pub fn parse(decoder: &mut Decoder, header_size: &mut usize) -> Result<Self, FlvError> {
         *header_size += 1;
-        let bits = BitIO::new(decoder.drain_u8());
+        let bits = BitIO::new(decoder.drain_u8()?);
         let sound_format = bits.read_range(0, 3);
+        if sound_format == 9 {
+            return Self::parse_ex(decoder, bits, header_size);
+        }
         let sound_rate = bits.read_range(4, 5);
         let sound_size = bits.read_bit(6);
         let sound_type = bits.read_bit(7);
 
         let aac_packet_type = if sound_format == 10 {
             *header_size += 1;
-            Some(decoder.drain_u8())
+            Some(decoder.drain_u8()?)
         } else {
             None
         };
-        Ok(Self { sound_format, sound_rate, sound_size, sound_type, aac_packet_type })
+        Ok(Self::new(sound_format, sound_rate, sound_size, sound_type, aac_packet_type))
+    }
+
+    /// The enhanced rtmp header, where the codec is a FourCC instead of the sound format.
+    fn parse_ex(decoder: &mut Decoder, bits: BitIO, header_size: &mut usize) -> Result<Self, FlvError> {
+        let mut audio_packet_type = AudioPacketType::try_from(bits.read_range(4, 7))?;
+
+        let mut multitrack_type = None;
+        if audio_packet_type == AudioPacketType::Multitrack {
+            *header_size += 1;
+            let bits = BitIO::new(decoder.drain_u8()?);
+            multitrack_type = Some(AvMultitrackType::try_from(bits.read_range(0, 3))?);
+            audio_packet_type = AudioPacketType::try_from(bits.read_range(4, 7))?;
+            if audio_packet_type == AudioPacketType::Multitrack {
+                return Err(FlvError::malformed("Multitrack audio packets cannot be nested."));
+            }
+        }
+
+        let mut fourcc = None;
+        if multitrack_type != Some(AvMultitrackType::ManyTracksManyCodecs) {
+            *header_size += 4;
+            fourcc = Some(decoder.drain_bytes::<4>()?);
+        }
+        Ok(Self {
+            sound_format: 9,
+            sound_rate: 0,
+            sound_size: false,
+            sound_type: false,
+            aac_packet_type: None,
+            audio_packet_type: Some(audio_packet_type),
+            multitrack_type,
+            fourcc,
+        })
+    }
+
+    #[inline]
+    pub fn is_ex_header(&self) -> bool {
+        self.sound_format == 9
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioPacketType {
+    SequenceStart,
+    CodedFrames,
+    SequenceEnd,
+    MultichannelConfig,
+    /// Never left in a parsed header, it is replaced by the packet type of the tracks.
+    Multitrack,
+}
+
+impl TryFrom<u8> for AudioPacketType {
+    type Error = FlvError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(AudioPacketType::SequenceStart),
+            1 => Ok(AudioPacketType::CodedFrames),
+            2 => Ok(AudioPacketType::SequenceEnd),
+            4 => Ok(AudioPacketType::MultichannelConfig),
+            5 => Ok(AudioPacketType::Multitrack),
+            _ => Err(FlvError::unsupported_codec(format!("audio packet type {}", value))),
+        }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct VideoTagHeader {
+    // UB1
+    // enhanced rtmp
+    pub is_ex_header: bool,
     // UB4
+    // UB3 if is_ex_header
     pub frame_type: u8,
     // UB4
+    // 0 if is_ex_header, see `fourcc` instead
+    // 12 for the non-standard hevc of some cdns and cameras
     pub codec_id: u8,
-    // UI24
-    // if codec_id == 7
+    // UI8
+    // if codec_id == 7 or codec_id == 12
     pub avc_packet_type: Option<u8>,
     // SI24
-    // if codec_id == 7
+    // if codec_id == 7 or codec_id == 12, or if fourcc == hvc1 and video_packet_type == CodedFrames
     pub composition_time_offset: Option<i32>,
+    // UB4
+    // if is_ex_header
+    // for multitrack tags, the packet type shared by every track
+    pub video_packet_type: Option<VideoPacketType>,
+    // UB4
+    // if video_packet_type == Multitrack
+    pub multitrack_type: Option<AvMultitrackType>,
+    // FOURCC
+    // if is_ex_header, unless multitrack_type == ManyTracksManyCodecs
+    pub fourcc: Option<[u8; 4]>,
 }
 
 impl VideoTagHeader {
     pub fn new(frame_type: u8, codec_id: u8, avc_packet_type: Option<u8>, composition_time: Option<i32>) -> Self {
-        Self { frame_type, codec_id, avc_packet_type, composition_time_offset: composition_time }
+        Self {
+            is_ex_header: false,
+            frame_type,
+            codec_id,
+            avc_packet_type,
+            composition_time_offset: composition_time,
+            video_packet_type: None,
+            multitrack_type: None,
+            fourcc: None,
+        }
     }
 
-    pub fn parse(decoder: &mut Decoder, header_size: &mut usize) -> Result<Self, Box<dyn std::error::Error>> {
+    pub fn parse(decoder: &mut Decoder, header_size: &mut usize) -> Result<Self, FlvError> {
         *header_size += 1;
-        let bits = BitIO::new(decoder.drain_u8());
+        let bits = BitIO::new(decoder.drain_u8()?);
+        if bits.read_bit(0) {
+            return Self::parse_ex(decoder, bits, header_size);
+        }
         let frame_type = bits.read_range(0, 3);
         let codec_id = bits.read_range(4, 7);
 
         let mut avc_packet_type = None;
         let mut composition_time = None;
-        if codec_id == 7 {
+        // legacy hevc has the same layout as avc.
+        if codec_id == 7 || codec_id == 12 {
             *header_size += 1;
-            avc_packet_type = Some(decoder.drain_u8());
+            avc_packet_type = Some(decoder.drain_u8()?);
 
             *header_size += 3;
-            composition_time = Some(decoder.drain_i24());
+            // SI24, `drain_i24` does not extend the sign.
+            composition_time = Some((decoder.drain_i24()? << 8) >> 8);
+        }
+        Ok(Self::new(frame_type, codec_id, avc_packet_type, composition_time))
+    }
+
+    /// The enhanced rtmp header, where the codec is a FourCC instead of a 4-bit id.
+    fn parse_ex(decoder: &mut Decoder, bits: BitIO, header_size: &mut usize) -> Result<Self, FlvError> {
+        let frame_type = bits.read_range(1, 3);
+        let mut video_packet_type = VideoPacketType::try_from(bits.read_range(4, 7))?;
+
+        let mut multitrack_type = None;
+        if video_packet_type == VideoPacketType::Multitrack {
+            *header_size += 1;
+            let bits = BitIO::new(decoder.drain_u8()?);
+            multitrack_type = Some(AvMultitrackType::try_from(bits.read_range(0, 3))?);
+            video_packet_type = VideoPacketType::try_from(bits.read_range(4, 7))?;
+            if video_packet_type == VideoPacketType::Multitrack {
+                return Err(FlvError::malformed("Multitrack video packets cannot be nested."));
+            }
+        }
+
+        let mut fourcc = None;
+        if multitrack_type != Some(AvMultitrackType::ManyTracksManyCodecs) {
+            *header_size += 4;
+            fourcc = Some(decoder.drain_bytes::<4>()?);
+        }
+
+        // the composition time of multitrack tags is part of every track instead.
+        let mut composition_time = None;
+        if video_packet_type == VideoPacketType::CodedFrames && fourcc == Some(*b"hvc1") && multitrack_type.is_none() {
+            *header_size += 3;
+            // SI24, `drain_i24` does not extend the sign.
+            composition_time = Some((decoder.drain_i24()? << 8) >> 8);
+        }
+        Ok(Self {
+            is_ex_header: true,
+            frame_type,
+            codec_id: 0,
+            avc_packet_type: None,
+            composition_time_offset: composition_time,
+            video_packet_type: Some(video_packet_type),
+            multitrack_type,
+            fourcc,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoPacketType {
+    SequenceStart,
+    CodedFrames,
+    SequenceEnd,
+    /// CodedFrames with an implicit composition time of 0.
+    CodedFramesX,
+    Metadata,
+    Mpeg2TsSequenceStart,
+    /// Never left in a parsed header, it is replaced by the packet type of the tracks.
+    Multitrack,
+}
+
+impl TryFrom<u8> for VideoPacketType {
+    type Error = FlvError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(VideoPacketType::SequenceStart),
+            1 => Ok(VideoPacketType::CodedFrames),
+            2 => Ok(VideoPacketType::SequenceEnd),
+            3 => Ok(VideoPacketType::CodedFramesX),
+            4 => Ok(VideoPacketType::Metadata),
+            5 => Ok(VideoPacketType::Mpeg2TsSequenceStart),
+            6 => Ok(VideoPacketType::Multitrack),
+            _ => Err(FlvError::unsupported_codec(format!("video packet type {}", value))),
+        }
+    }
+}
+
+/// How the tracks of an enhanced rtmp multitrack tag are laid out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AvMultitrackType {
+    /// A single track with an explicit track id.
+    OneTrack,
+    /// Several tracks of the codec in the header, each prefixed with its size.
+    ManyTracks,
+    /// Several tracks, each with its own FourCC and size.
+    ManyTracksManyCodecs,
+}
+
+impl TryFrom<u8> for AvMultitrackType {
+    type Error = FlvError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(AvMultitrackType::OneTrack),
+            1 => Ok(AvMultitrackType::ManyTracks),
+            2 => Ok(AvMultitrackType::ManyTracksManyCodecs),
+            _ => Err(FlvError::unsupported_codec(format!("multitrack type {}", value))),
         }
-        Ok(Self { frame_type, codec_id, avc_packet_type, composition_time_offset: composition_time })
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct EncryptionTagHeader {
-    // encryption
+    // UI8
+    // always 1
+    pub num_filters: u8,
+    // STRING
+    // "Encryption" or "SE"
+    pub filter_name: String,
+    // UI24
+    // size of the FilterParams which follow
+    pub length: u32,
 }
 
 impl EncryptionTagHeader {
-    pub fn parse(decoder: &mut Decoder, header_size: &mut usize) -> Result<Self, Box<dyn std::error::Error>> {
-        unimplemented!()
+    pub fn parse(decoder: &mut Decoder, header_size: &mut usize) -> Result<Self, FlvError> {
+        *header_size += 1;
+        let num_filters = decoder.drain_u8()?;
+        if num_filters != 1 {
+            return Err(FlvError::malformed(format!("Encrypted tag has {} filters, only 1 is allowed.", num_filters)));
+        }
+
+        let name_length = decoder.drain_u16()? as usize;
+        *header_size += 2 + name_length;
+        let filter_name = String::from_utf8(decoder.drain_bytes_vec(name_length)?)
+            .map_err(|_| FlvError::malformed("Filter name is not valid utf-8."))?;
+
+        *header_size += 3;
+        let length = decoder.drain_u24()?;
+        Ok(Self { num_filters, filter_name, length })
     }
 }
 
@@ -119,22 +342,67 @@ pub enum FilterParameters {
 
 #[derive(Debug, Clone)]
 pub struct EncryptionFilterParameters {
-    // encryption
+    // UI8[16]
+    pub iv: [u8; 16],
+}
+
+impl EncryptionFilterParameters {
+    pub fn parse(decoder: &mut Decoder, param_size: &mut usize) -> Result<Self, FlvError> {
+        *param_size += 16;
+        Ok(Self { iv: decoder.drain_bytes::<16>()? })
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct SelectiveEncryptionFilterParameters {
-    // selective encryption
+    // UB1
+    pub encrypted_au: bool,
+    // UB7
+    // reserved
+    // UI8[16]
+    // if encrypted_au
+    pub iv: Option<[u8; 16]>,
 }
 
 impl SelectiveEncryptionFilterParameters {
-    pub fn parse(decoder: &mut Decoder) -> Self {
-        unimplemented!()
+    pub fn parse(decoder: &mut Decoder, param_size: &mut usize) -> Result<Self, FlvError> {
+        *param_size += 1;
+        let encrypted_au = BitIO::new(decoder.drain_u8()?).read_bit(0);
+        let iv = if encrypted_au {
+            *param_size += 16;
+            Some(decoder.drain_bytes::<16>()?)
+        } else {
+            None
+        };
+        Ok(Self { encrypted_au, iv })
     }
 }
 
 impl FilterParameters {
-    pub fn parse(decoder: &mut Decoder, param_size: &mut usize) -> Result<Self, Box<dyn std::error::Error>> {
-        unimplemented!()
+    /// Parse the parameters of the filter named in `encryption_header`.
+    /// Bytes beyond the known parameters, up to `encryption_header.length`, are skipped.
+    pub fn parse(decoder: &mut Decoder, encryption_header: &EncryptionTagHeader, param_size: &mut usize) -> Result<Self, FlvError> {
+        let mut parsed_size = 0;
+        let params = match encryption_header.filter_name.as_str() {
+            "Encryption" => FilterParameters::EncryptionFilter(EncryptionFilterParameters::parse(decoder, &mut parsed_size)?),
+            "SE" => FilterParameters::SelectiveEncryptionFilter(SelectiveEncryptionFilterParameters::parse(decoder, &mut parsed_size)?),
+            name => return Err(FlvError::unsupported_codec(format!("encryption filter {}", name))),
+        };
+
+        let length = encryption_header.length as usize;
+        if parsed_size > length {
+            return Err(FlvError::malformed(format!("Filter parameters take {} bytes, but only {} are declared.", parsed_size, length)));
+        }
+        decoder.drain_bytes_vec(length - parsed_size)?;
+        *param_size += length;
+        Ok(params)
     }
-}
\ No newline at end of file
+
+    /// The IV to decrypt the body with, or None if the body is not encrypted.
+    pub fn iv(&self) -> Option<&[u8; 16]> {
+        match self {
+            FilterParameters::EncryptionFilter(params) => Some(&params.iv),
+            FilterParameters::SelectiveEncryptionFilter(params) => params.iv.as_ref(),
+        }
+    }
+}
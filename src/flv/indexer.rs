@@ -0,0 +1,176 @@
+use crate::error::FlvError;
+use crate::flv::decoder::Decoder;
+use crate::flv::meta::KeyframeIndex;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+const FLV_HEADER_SIZE: usize = 9;
+const TAG_HEADER_SIZE: usize = 11;
+const PREVIOUS_TAG_SIZE_LENGTH: usize = 4;
+
+const SIDECAR_MAGIC: [u8; 4] = *b"FLVI";
+const SIDECAR_VERSION: u8 = 1;
+/// magic (4) + version (1) + source size (8) + entry count (4)
+const SIDECAR_HEADER_SIZE: usize = 17;
+/// timestamp (4) + offset (8) + frame type (1)
+const SIDECAR_ENTRY_SIZE: usize = 13;
+
+/// A video keyframe found by the indexer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyframeEntry {
+    /// In milliseconds, including the extended timestamp.
+    pub timestamp: u32,
+    /// Byte offset of the tag header, counted from the start of the file.
+    pub offset: u64,
+    /// 1 for a keyframe, 4 for a generated keyframe.
+    pub frame_type: u8,
+}
+
+/// Keyframes collected by `KeyframeIndexer`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IndexedKeyframes {
+    /// Size of the scanned file, so that a stale sidecar can be told apart.
+    pub source_size: u64,
+    pub entries: Vec<KeyframeEntry>,
+}
+
+impl IndexedKeyframes {
+    /// Find the last keyframe at or before `time_ms`.
+    /// Falls back to the first keyframe when `time_ms` is before all of them.
+    pub fn nearest_preceding(&self, time_ms: u32) -> Option<&KeyframeEntry> {
+        let index = self.entries.iter().rposition(|entry| entry.timestamp <= time_ms).unwrap_or(0);
+        self.entries.get(index)
+    }
+
+    /// Serialise into the sidecar format:
+    /// magic `FLVI`, version, source size, entry count, then the entries, all big-endian.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(SIDECAR_HEADER_SIZE + self.entries.len() * SIDECAR_ENTRY_SIZE);
+        result.extend_from_slice(&SIDECAR_MAGIC);
+        result.push(SIDECAR_VERSION);
+        result.extend_from_slice(&self.source_size.to_be_bytes());
+        result.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+        for entry in &self.entries {
+            result.extend_from_slice(&entry.timestamp.to_be_bytes());
+            result.extend_from_slice(&entry.offset.to_be_bytes());
+            result.push(entry.frame_type);
+        }
+        result
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, FlvError> {
+        if bytes.len() < SIDECAR_HEADER_SIZE || bytes[0..4] != SIDECAR_MAGIC {
+            return Err(FlvError::malformed("Not a keyframe index sidecar."));
+        }
+        if bytes[4] != SIDECAR_VERSION {
+            return Err(FlvError::malformed(format!("Unsupported keyframe index sidecar version {}.", bytes[4])));
+        }
+        let source_size = u64::from_be_bytes(bytes[5..13].try_into().unwrap());
+        let count = u32::from_be_bytes(bytes[13..17].try_into().unwrap()) as usize;
+
+        let body = &bytes[SIDECAR_HEADER_SIZE..];
+        if body.len() != count.saturating_mul(SIDECAR_ENTRY_SIZE) {
+            return Err(FlvError::malformed(format!("Keyframe index sidecar should hold {} entries, found {} bytes.", count, body.len())));
+        }
+        let entries = body.chunks_exact(SIDECAR_ENTRY_SIZE).map(|entry| KeyframeEntry {
+            timestamp: u32::from_be_bytes(entry[0..4].try_into().unwrap()),
+            offset: u64::from_be_bytes(entry[4..12].try_into().unwrap()),
+            frame_type: entry[12],
+        }).collect();
+        Ok(Self { source_size, entries })
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> Result<(), FlvError> {
+        writer.write_all(&self.serialize())?;
+        Ok(())
+    }
+
+    pub fn read_from(reader: &mut impl Read) -> Result<Self, FlvError> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        Self::deserialize(&bytes)
+    }
+}
+
+impl From<&IndexedKeyframes> for KeyframeIndex {
+    fn from(indexed: &IndexedKeyframes) -> Self {
+        KeyframeIndex {
+            times: indexed.entries.iter().map(|entry| entry.timestamp as f64 / 1000.0).collect(),
+            file_positions: indexed.entries.iter().map(|entry| entry.offset).collect(),
+        }
+    }
+}
+
+/// Builds a keyframe index for files whose onMetaData has none.
+/// Only the tag headers and the first byte of every video body are read,
+/// everything else is skipped by seeking.
+pub struct KeyframeIndexer;
+
+impl KeyframeIndexer {
+    pub fn scan<R: Read + Seek>(source: &mut R) -> Result<IndexedKeyframes, FlvError> {
+        let source_size = source.seek(SeekFrom::End(0))?;
+        source.seek(SeekFrom::Start(0))?;
+
+        let mut header = [0u8; FLV_HEADER_SIZE];
+        source.read_exact(&mut header).map_err(|_| FlvError::malformed("Input is too short for an flv header."))?;
+        if header[0..3] != *b"FLV" {
+            return Err(FlvError::malformed("Missing FLV signature.").at_offset(0));
+        }
+        let data_offset = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) as u64;
+
+        let mut entries = vec![];
+        // skip the first PreviousTagSize together with each tag header, it is always 0 anyway.
+        let mut offset = data_offset.max(FLV_HEADER_SIZE as u64) + PREVIOUS_TAG_SIZE_LENGTH as u64;
+        source.seek(SeekFrom::Start(offset))?;
+        loop {
+            let mut tag_header = [0u8; TAG_HEADER_SIZE];
+            if !Self::read_fully(source, &mut tag_header)? {
+                break;
+            }
+            let tag_type = tag_header[0] & 0x1F;
+            let data_size = u32::from_be_bytes([0, tag_header[1], tag_header[2], tag_header[3]]) as u64;
+            let timestamp = Decoder::concat_ts(
+                u32::from_be_bytes([0, tag_header[4], tag_header[5], tag_header[6]]),
+                tag_header[7],
+            );
+
+            if offset + TAG_HEADER_SIZE as u64 + data_size > source_size {
+                // a truncated recording, the last tag is incomplete.
+                break;
+            }
+
+            let mut consumed = 0;
+            // the video header of encrypted tags is in the clear as well.
+            if tag_type == 9 && data_size > 0 {
+                let mut first = [0u8; 1];
+                if !Self::read_fully(source, &mut first)? {
+                    break;
+                }
+                consumed = 1;
+                // the top bit is the enhanced rtmp ex-header flag, the frame type follows in 3 bits.
+                let frame_type = (first[0] >> 4) & 0x07;
+                if frame_type == 1 || frame_type == 4 {
+                    entries.push(KeyframeEntry { timestamp, offset, frame_type });
+                }
+            }
+
+            source.seek(SeekFrom::Current((data_size - consumed) as i64 + PREVIOUS_TAG_SIZE_LENGTH as i64))?;
+            offset += (TAG_HEADER_SIZE + PREVIOUS_TAG_SIZE_LENGTH) as u64 + data_size;
+        }
+
+        Ok(IndexedKeyframes { source_size, entries })
+    }
+
+    /// Like `read_exact`, but returns false instead of failing at the end of the source.
+    fn read_fully<R: Read>(source: &mut R, buffer: &mut [u8]) -> Result<bool, FlvError> {
+        let mut filled = 0;
+        while filled < buffer.len() {
+            match source.read(&mut buffer[filled..]) {
+                Ok(0) => return Ok(false),
+                Ok(read) => filled += read,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(true)
+    }
+}
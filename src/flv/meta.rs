@@ -1,59 +1,85 @@
 use crate::flv::script::{ScriptData, ScriptTagBody};
 use std::collections::HashMap;
 
+/// The onMetaData properties most writers agree on, checked for type and range.
+/// Properties which are not there, or are invalid, are None, there is no telling what a default would mean.
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct MetaData {
-    pub audio_codec_id: f64,
-    pub audio_data_rate: f64,
-    pub audio_delay: f64,
-    pub audio_sample_rate: f64,
-    pub audio_samples_size: f64,
-    pub can_seek_to_end: bool,
-    pub creation_date: String,
-    pub duration: f64,
-    pub file_size: f64,
-    pub frame_rate: f64,
-    pub height: f64,
-    pub stereo: bool,
-    pub video_codec_id: f64,
-    pub video_data_rate: f64,
-    pub width: f64,
+    /// The codec id of the audio tags, or a FourCC for enhanced rtmp.
+    pub audio_codec_id: Option<u32>,
+    /// In kbps.
+    pub audio_data_rate: Option<f64>,
+    /// In seconds.
+    pub audio_delay: Option<f64>,
+    /// In Hz.
+    pub audio_sample_rate: Option<f64>,
+    /// In bits.
+    pub audio_samples_size: Option<u32>,
+    pub can_seek_to_end: Option<bool>,
+    /// Four characters per brand, as written by ffmpeg.
+    pub compatible_brands: Option<String>,
+    pub creation_date: Option<String>,
+    /// In seconds.
+    pub duration: Option<f64>,
+    pub encoder: Option<String>,
+    /// In bytes.
+    pub file_size: Option<u64>,
+    pub frame_rate: Option<f64>,
+    pub has_keyframes: Option<bool>,
+    pub height: Option<u32>,
+    /// The keys of the properties above which were there, but invalid.
+    pub invalid_properties: Vec<String>,
+    pub keyframes: Option<KeyframeIndex>,
+    /// In seconds.
+    pub last_timestamp: Option<f64>,
+    pub major_brand: Option<String>,
+    pub metadata_creator: Option<String>,
+    pub minor_version: Option<String>,
+    pub stereo: Option<bool>,
+    /// The codec id of the video tags, or a FourCC for enhanced rtmp.
+    pub video_codec_id: Option<u32>,
+    /// In kbps.
+    pub video_data_rate: Option<f64>,
+    pub width: Option<u32>,
 }
 
-impl MetaData {
-    pub fn new(
-        audio_codec_id: f64,
-        audio_data_rate: f64,
-        audio_delay: f64,
-        audio_sample_rate: f64,
-        audio_samples_size: f64,
-        can_seek_to_end: bool,
-        creation_date: String,
-        duration: f64,
-        file_size: f64,
-        frame_rate: f64,
-        height: f64,
-        stereo: bool,
-        video_codec_id: f64,
-        video_data_rate: f64,
-        width: f64,
-    ) -> Self {
-        Self {
-            audio_codec_id,
-            audio_data_rate,
-            audio_delay,
-            audio_sample_rate,
-            audio_samples_size,
-            can_seek_to_end,
-            creation_date,
-            duration,
-            file_size,
-            frame_rate,
-            height,
-            stereo,
-            video_codec_id,
-            video_data_rate,
-            width,
-        }
+impl From<&RawMetaData> for MetaData {
+    /// A property which is there, but has the wrong type or an impossible value, is left None
+    /// and listed in `invalid_properties`, the others are still taken.
+    fn from(raw: &RawMetaData) -> Self {
+        let mut invalid = vec![];
+        let mut metadata = Self {
+            audio_codec_id: raw.validated_integer("audiocodecid", &mut invalid),
+            audio_data_rate: raw.validated_number("audiodatarate", 0.0, &mut invalid),
+            audio_delay: raw.validated_number("audiodelay", f64::MIN, &mut invalid),
+            audio_sample_rate: raw.validated_number("audiosamplerate", 0.0, &mut invalid),
+            audio_samples_size: raw.validated_integer("audiosamplesize", &mut invalid),
+            can_seek_to_end: raw.validated_boolean("canSeekToEnd", &mut invalid),
+            compatible_brands: raw.validated_string("compatible_brands", &mut invalid),
+            creation_date: raw.validated_string("creationdate", &mut invalid),
+            duration: raw.validated_number("duration", 0.0, &mut invalid),
+            encoder: raw.validated_string("encoder", &mut invalid),
+            file_size: raw.validated_integer("filesize", &mut invalid),
+            frame_rate: raw.validated_number("framerate", 0.0, &mut invalid),
+            has_keyframes: raw.validated_boolean("hasKeyframes", &mut invalid),
+            height: raw.validated_integer("height", &mut invalid),
+            invalid_properties: vec![],
+            // live streams may write it without any entries, which is not an error.
+            keyframes: raw.validated("keyframes", &mut invalid, |value| match value {
+                ScriptData::Object(_) | ScriptData::EcmaArray(_) => Some(KeyframeIndex::parse(value)),
+                _ => None,
+            }).flatten(),
+            last_timestamp: raw.validated_number("lasttimestamp", 0.0, &mut invalid),
+            major_brand: raw.validated_string("major_brand", &mut invalid),
+            metadata_creator: raw.validated_string("metadatacreator", &mut invalid),
+            minor_version: raw.validated_string("minor_version", &mut invalid),
+            stereo: raw.validated_boolean("stereo", &mut invalid),
+            video_codec_id: raw.validated_integer("videocodecid", &mut invalid),
+            video_data_rate: raw.validated_number("videodatarate", 0.0, &mut invalid),
+            width: raw.validated_integer("width", &mut invalid),
+        };
+        metadata.invalid_properties = invalid;
+        metadata
     }
 }
 
@@ -93,14 +119,260 @@ impl RawMetaData {
             _ => None,
         }
     }
+
+    pub fn try_get_keyframes(&self) -> Option<KeyframeIndex> {
+        KeyframeIndex::parse(self.data.get("keyframes")?)
+    }
+
+    /// The value of the property, if it is there. One which `parse` refuses is recorded in `invalid`.
+    fn validated<T>(&self, key: &str, invalid: &mut Vec<String>, parse: impl FnOnce(&ScriptData) -> Option<T>) -> Option<T> {
+        let value = parse(self.data.get(key)?);
+        if value.is_none() {
+            invalid.push(key.to_string());
+        }
+        value
+    }
+
+    /// A finite number of at least `min`, if the property is there.
+    fn validated_number(&self, key: &str, min: f64, invalid: &mut Vec<String>) -> Option<f64> {
+        self.validated(key, invalid, |value| match value {
+            ScriptData::Number(number) if number.is_finite() && *number >= min => Some(*number),
+            _ => None,
+        })
+    }
+
+    /// A whole number which fits into `T`, if the property is there.
+    fn validated_integer<T: TryFrom<u64>>(&self, key: &str, invalid: &mut Vec<String>) -> Option<T> {
+        self.validated(key, invalid, |value| match value {
+            ScriptData::Number(number) if *number >= 0.0 && number.fract() == 0.0 && *number <= u64::MAX as f64 => {
+                T::try_from(*number as u64).ok()
+            }
+            _ => None,
+        })
+    }
+
+    fn validated_boolean(&self, key: &str, invalid: &mut Vec<String>) -> Option<bool> {
+        self.validated(key, invalid, |value| match value {
+            ScriptData::Boolean(boolean) => Some(*boolean != 0),
+            _ => None,
+        })
+    }
+
+    fn validated_string(&self, key: &str, invalid: &mut Vec<String>) -> Option<String> {
+        self.validated(key, invalid, |value| match value {
+            ScriptData::String(string) => Some(string.data.clone()),
+            ScriptData::LongString(string) => Some(string.data.clone()),
+            _ => None,
+        })
+    }
+}
+
+/// The `keyframes` object some writers put into onMetaData,
+/// which maps keyframe times to the byte positions of their tags.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KeyframeIndex {
+    /// In seconds, as written in the metadata.
+    pub times: Vec<f64>,
+    /// Byte positions of the keyframe tags, counted from the start of the file.
+    pub file_positions: Vec<u64>,
 }
 
+impl KeyframeIndex {
+    /// Parse the value of the `keyframes` property.
+    /// Entries where either the time or the position is missing are dropped.
+    pub fn parse(value: &ScriptData) -> Option<Self> {
+        let properties = match value {
+            ScriptData::Object(object) => &object.properties,
+            ScriptData::EcmaArray(array) => &array.properties,
+            _ => return None,
+        };
+
+        let numbers = |key: &str| -> Vec<f64> {
+            properties.iter()
+                .find(|prop| prop.name.data == key)
+                .map(|prop| match &prop.value {
+                    ScriptData::StrictArray(array) => array.values.iter().filter_map(|v| match v {
+                        ScriptData::Number(number) => Some(*number),
+                        _ => None,
+                    }).collect(),
+                    _ => vec![],
+                })
+                .unwrap_or_default()
+        };
+
+        let times = numbers("times");
+        let file_positions = numbers("filepositions");
+        let count = times.len().min(file_positions.len());
+        if count == 0 {
+            return None;
+        }
+        Some(Self {
+            times: times[..count].to_vec(),
+            file_positions: file_positions[..count].iter().map(|position| *position as u64).collect(),
+        })
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.times.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.times.is_empty()
+    }
+
+    /// Find the last keyframe at or before `time_ms`, as (time in ms, file position).
+    /// Falls back to the first keyframe when `time_ms` is before all of them.
+    pub fn nearest_preceding(&self, time_ms: u32) -> Option<(u32, u64)> {
+        let time = time_ms as f64 / 1000.0;
+        let index = self.times.iter().rposition(|t| *t <= time).unwrap_or(0);
+        let keyframe_time = *self.times.get(index)?;
+        Some(((keyframe_time * 1000.0).max(0.0) as u32, self.file_positions[index]))
+    }
+}
+
+/// The XMP packet of an onXMPData tag, with the Dublin Core and XMP basic fields a catalogue needs.
+///
+/// Note: this is not a full XML parser. The fields are looked up by their usual prefixes,
+/// `dc:`, `xmp:` and `rdf:`, either as elements or as attributes of `rdf:Description`.
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct XMPData {
+    /// The raw XMP packet.
     pub xmp: String,
+    /// dc:title, in the default language.
+    pub title: Option<String>,
+    /// dc:creator, in order.
+    pub creator: Vec<String>,
+    /// xmp:CreateDate, as written, usually ISO 8601.
+    pub create_date: Option<String>,
+    /// dc:rights, in the default language.
+    pub rights: Option<String>,
 }
 
 impl XMPData {
     pub fn new(xmp: String) -> Self {
-        Self { xmp }
+        let title = Self::property(&xmp, "dc:title").into_iter().next();
+        let creator = Self::property(&xmp, "dc:creator");
+        let create_date = Self::property(&xmp, "xmp:CreateDate").into_iter().next();
+        let rights = Self::property(&xmp, "dc:rights").into_iter().next();
+        Self { xmp, title, creator, create_date, rights }
+    }
+
+    /// Find the packet in an onXMPData tag, which keeps it in `liveXML`.
+    pub fn parse(body: &ScriptTagBody) -> Option<Self> {
+        if body.name.data != "onXMPData" {
+            return None;
+        }
+        body.value.properties.iter()
+            .find(|prop| prop.name.data == "liveXML")
+            .and_then(|prop| match &prop.value {
+                ScriptData::String(string) => Some(string.data.clone()),
+                ScriptData::LongString(string) | ScriptData::XmlDocument(string) => Some(string.data.clone()),
+                _ => None,
+            })
+            .map(Self::new)
+    }
+
+    /// The values of a property: the items of an `rdf:Alt`, `rdf:Bag` or `rdf:Seq`,
+    /// the text of a simple element, or the value of an attribute.
+    /// For an `rdf:Alt`, the x-default item is moved to the front.
+    fn property(xmp: &str, name: &str) -> Vec<String> {
+        if let Some(content) = Self::element_content(xmp, name) {
+            let mut items = vec![];
+            let mut rest = content;
+            while let (Some(item), Some(start), Some(end)) = (Self::element_content(rest, "rdf:li"), rest.find("<rdf:li"), rest.find("</rdf:li>")) {
+                let is_default = rest[start..].split('>').next().is_some_and(|tag| tag.contains("x-default"));
+                let value = Self::unescape(item.trim());
+                if is_default {
+                    items.insert(0, value);
+                } else {
+                    items.push(value);
+                }
+                rest = &rest[end + "</rdf:li>".len()..];
+            }
+            if items.is_empty() && !content.contains('<') && !content.trim().is_empty() {
+                items.push(Self::unescape(content.trim()));
+            }
+            return items;
+        }
+        Self::attribute(xmp, name).map(|value| vec![Self::unescape(value)]).unwrap_or_default()
+    }
+
+    /// The text between `<name ...>` and `</name>`, None for a missing or empty element.
+    fn element_content<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+        let open = format!("<{}", name);
+        let mut offset = 0;
+        while let Some(found) = xml[offset..].find(&open) {
+            let start = offset + found + open.len();
+            offset = start;
+            // `<dc:titles` is a different element.
+            match xml[start..].chars().next() {
+                Some('>') | Some('/') => {}
+                Some(c) if c.is_whitespace() => {}
+                _ => continue,
+            }
+            let tag_end = start + xml[start..].find('>')?;
+            if xml[..tag_end].ends_with('/') {
+                return None;
+            }
+            let close = format!("</{}>", name);
+            let content_end = tag_end + 1 + xml[tag_end + 1..].find(&close)?;
+            return Some(&xml[tag_end + 1..content_end]);
+        }
+        None
+    }
+
+    fn attribute<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+        let mut offset = 0;
+        while let Some(found) = xml[offset..].find(name) {
+            let start = offset + found;
+            offset = start + name.len();
+            // only a whole attribute name counts, not `xmp:CreateDateFoo` or an element.
+            if !xml[..start].ends_with(char::is_whitespace) {
+                continue;
+            }
+            let rest = xml[offset..].trim_start().strip_prefix('=')?.trim_start();
+            let quote = rest.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+            let value = &rest[1..];
+            return Some(&value[..value.find(quote)?]);
+        }
+        None
+    }
+
+    /// Resolve the predefined entities and character references.
+    fn unescape(text: &str) -> String {
+        let mut result = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(amp) = rest.find('&') {
+            result.push_str(&rest[..amp]);
+            rest = &rest[amp..];
+            let Some(semicolon) = rest.find(';') else { break };
+            let entity = &rest[1..semicolon];
+            let resolved = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                _ => entity.strip_prefix("#x").or(entity.strip_prefix("#X"))
+                    .map(|hex| u32::from_str_radix(hex, 16))
+                    .or(entity.strip_prefix('#').map(|decimal| decimal.parse::<u32>()))
+                    .and_then(|code| code.ok())
+                    .and_then(char::from_u32),
+            };
+            match resolved {
+                Some(c) => {
+                    result.push(c);
+                    rest = &rest[semicolon + 1..];
+                }
+                None => {
+                    result.push('&');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        result.push_str(rest);
+        result
     }
 }
\ No newline at end of file
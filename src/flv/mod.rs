@@ -4,3 +4,10 @@ pub mod header;
 pub mod tag;
 pub mod script;
 pub mod meta;
+pub mod indexer;
+pub mod crypto;
+pub mod timestamp;
+pub mod observer;
+pub mod amf3;
+pub mod event;
+pub mod frames;
\ No newline at end of file
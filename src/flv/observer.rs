@@ -0,0 +1,22 @@
+use crate::flv::tag::Tag;
+
+/// What becomes of a tag after an observer has seen it.
+#[derive(Debug)]
+pub enum TagAction {
+    /// Pass the tag on, including whatever the observer changed in place.
+    Pass,
+    /// Drop the tag, the demuxer never sees it.
+    Drop,
+    /// Pass these tags on instead, in order.
+    /// Put the observed tag in among them to inject tags next to it.
+    Replace(Vec<Tag>),
+}
+
+/// Sees every tag between the decoder and the demuxer, see `Decoder::add_tag_observer`.
+pub trait ITagObserver {
+    /// Called with each decoded tag, and the byte offset of its header from the start of the stream.
+    /// Timestamps have already been through the `TimestampPolicy` at this point.
+    ///
+    /// Note: replacement tags are not checked in any way, they go to the following observers and then to the demuxer as they are.
+    fn observe(&mut self, tag: &mut Tag, byte_offset: u64) -> TagAction;
+}
@@ -1,43 +1,100 @@
+use crate::flv::amf3::Amf3Context;
 use crate::flv::decoder::Decoder;
-use std::io::Error;
+use crate::error::FlvError;
+use std::collections::{BTreeMap, BTreeSet};
 
-pub fn parse_object(data: &mut Decoder) -> Result<ScriptData, Box<dyn std::error::Error>> {
-    let data_type = data.drain_u8();
+/// Values copied while resolving references in a single tag or AMF3 value, see `ScriptTagBody::resolve_references`.
+pub(crate) const MAX_RESOLVED_NODES: usize = 1 << 20;
+
+pub fn parse_object(data: &mut Decoder) -> Result<ScriptData, FlvError> {
+    data.enter_script_level()?;
+    let value = parse_object_value(data);
+    data.leave_script_level();
+    value
+}
+
+fn parse_object_value(data: &mut Decoder) -> Result<ScriptData, FlvError> {
+    let data_type = data.drain_u8()?;
     let value = match data_type {
-        0 => ScriptData::Number(data.drain_f64()),
-        1 => ScriptData::Boolean(data.drain_u8()),
+        0 => ScriptData::Number(data.drain_f64()?),
+        1 => ScriptData::Boolean(data.drain_u8()?),
         2 => ScriptData::String(ScriptDataString::parse_no_marker(data)?),
         3 => ScriptData::Object(ScriptDataObject::parse_no_marker(data)?),
-
-        7 => ScriptData::Reference(data.drain_u16()),
+        5 => ScriptData::Null,
+        6 => ScriptData::Undefined,
+        // resolved once the whole tag body is parsed, see `ScriptTagBody::resolve_references`.
+        7 => ScriptData::Reference(data.drain_u16()?),
         8 => ScriptData::EcmaArray(ScriptDataEcmaArray::parse_no_marker(data)?),
         9 => ScriptData::ObjectEndMarker,
         10 => ScriptData::StrictArray(ScriptStrictArray::parse_no_marker(data)?),
         11 => ScriptData::Date(ScriptDataDate::parse_no_marker(data)?),
         12 => ScriptData::LongString(ScriptDataLongString::parse_no_marker(data)?),
+        13 => ScriptData::Unsupported,
+        15 => ScriptData::XmlDocument(ScriptDataLongString::parse_no_marker(data)?),
+        16 => ScriptData::TypedObject(ScriptDataTypedObject::parse_no_marker(data)?),
+        // a single AMF3 value follows, with reference tables of its own.
+        17 => Amf3Context::new().parse_value(data)?,
+        // MovieClip (4) and RecordSet (14) are reserved, and like any unknown marker their size is unknown,
+        // so nothing after them could be read correctly.
         _ => {
-            println!("Reserved type {}.", data_type);
-            ScriptData::NotImplemented
+            return Err(FlvError::amf(format!("Unsupported AMF0 type marker {}.", data_type)));
         }
     };
     Ok(value)
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ScriptTagBody {
     pub name: ScriptDataString,
     pub value: ScriptDataEcmaArray,
 }
 
 impl ScriptTagBody {
-    pub fn parse(data: &mut Decoder) -> Result<ScriptTagBody, Box<dyn std::error::Error>> {
-        let name = ScriptDataString::parse(data)?;
-        let value = ScriptDataEcmaArray::parse(data)?;
-        Ok(ScriptTagBody { name, value })
+    /// The name and the value may both be AMF3 values behind a switch,
+    /// and the value may be an object instead of an ecma array, as in many cue points.
+    pub fn parse(data: &mut Decoder) -> Result<ScriptTagBody, FlvError> {
+        let name = match parse_object(data)? {
+            ScriptData::String(name) => name,
+            _ => return Err(FlvError::amf("Unable to parse script tag: Expected a string as its name.")),
+        };
+        let value = match parse_object(data)? {
+            ScriptData::EcmaArray(array) => array,
+            ScriptData::Object(ScriptDataObject { properties }) | ScriptData::TypedObject(ScriptDataTypedObject { properties, .. }) => {
+                ScriptDataEcmaArray { length: properties.len().saturating_sub(1) as u32, properties }
+            }
+            _ => return Err(FlvError::amf(format!("Unable to parse script tag {}: Expected an ecma array or an object as its value.", name.data))),
+        };
+        let mut body = ScriptTagBody { name, value };
+        body.resolve_references()?;
+        Ok(body)
+    }
+
+    /// Replace every `ScriptData::Reference` with a copy of the object it points at.
+    /// Objects, typed objects, ecma arrays and strict arrays are numbered from 0 in the order they start,
+    /// beginning with `value` itself.
+    ///
+    /// Note: a reference to an object which contains it would never end, and one to no object at all has nothing to copy,
+    /// so both stay `ScriptData::Reference`.
+    pub fn resolve_references(&mut self) -> Result<(), FlvError> {
+        let mut value = ScriptData::EcmaArray(std::mem::replace(&mut self.value, ScriptDataEcmaArray { length: 0, properties: vec![] }));
+
+        let mut wanted = BTreeSet::new();
+        value.collect_references(&mut wanted);
+        if !wanted.is_empty() {
+            let mut targets = BTreeMap::new();
+            value.collect_targets(&mut 0, &wanted, &mut targets);
+            let mut budget = MAX_RESOLVED_NODES;
+            value.resolve(Some(&mut 0), &mut vec![], &targets, &mut budget)?;
+        }
+
+        if let ScriptData::EcmaArray(array) = value {
+            self.value = array;
+        }
+        Ok(())
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ScriptData {
     Number(f64),
     Boolean(u8),
@@ -52,30 +109,126 @@ pub enum ScriptData {
     StrictArray(ScriptStrictArray),
     Date(ScriptDataDate),
     LongString(ScriptDataLongString),
+    Unsupported,
+    XmlDocument(ScriptDataLongString),
+    TypedObject(ScriptDataTypedObject),
+    /// Only found in AMF3.
+    ByteArray(Vec<u8>),
     NotImplemented,
 }
 
-#[derive(Debug, Clone)]
+impl ScriptData {
+    /// Whether this value gets an index which `ScriptData::Reference` can point at.
+    #[inline]
+    pub fn is_complex(&self) -> bool {
+        matches!(self, ScriptData::Object(_) | ScriptData::TypedObject(_) | ScriptData::EcmaArray(_) | ScriptData::StrictArray(_))
+    }
+
+    fn for_each_child(&self, f: &mut dyn FnMut(&ScriptData)) {
+        match self {
+            ScriptData::Object(ScriptDataObject { properties }) |
+            ScriptData::TypedObject(ScriptDataTypedObject { properties, .. }) |
+            ScriptData::EcmaArray(ScriptDataEcmaArray { properties, .. }) => properties.iter().for_each(|prop| f(&prop.value)),
+            ScriptData::StrictArray(array) => array.values.iter().for_each(f),
+            _ => {}
+        }
+    }
+
+    fn try_for_each_child_mut(&mut self, f: &mut dyn FnMut(&mut ScriptData) -> Result<(), FlvError>) -> Result<(), FlvError> {
+        match self {
+            ScriptData::Object(ScriptDataObject { properties }) |
+            ScriptData::TypedObject(ScriptDataTypedObject { properties, .. }) |
+            ScriptData::EcmaArray(ScriptDataEcmaArray { properties, .. }) => properties.iter_mut().try_for_each(|prop| f(&mut prop.value)),
+            ScriptData::StrictArray(array) => array.values.iter_mut().try_for_each(f),
+            _ => Ok(()),
+        }
+    }
+
+    /// Number of values in this one, itself included.
+    pub(crate) fn node_count(&self) -> usize {
+        let mut count = 1;
+        self.for_each_child(&mut |child| count += child.node_count());
+        count
+    }
+
+    fn collect_references(&self, references: &mut BTreeSet<usize>) {
+        if let ScriptData::Reference(index) = self {
+            references.insert(*index as usize);
+        }
+        self.for_each_child(&mut |child| child.collect_references(references));
+    }
+
+    fn collect_targets(&self, next_index: &mut usize, wanted: &BTreeSet<usize>, targets: &mut BTreeMap<usize, ScriptData>) {
+        if self.is_complex() {
+            if wanted.contains(next_index) {
+                targets.insert(*next_index, self.clone());
+            }
+            *next_index += 1;
+        }
+        self.for_each_child(&mut |child| child.collect_targets(next_index, wanted, targets));
+    }
+
+    /// `next_index` numbers the complex values as in `collect_targets`, it is None inside a copy, which has no numbers of its own.
+    /// `containing` holds the indices of the objects around this value.
+    fn resolve(
+        &mut self,
+        mut next_index: Option<&mut usize>,
+        containing: &mut Vec<usize>,
+        targets: &BTreeMap<usize, ScriptData>,
+        budget: &mut usize,
+    ) -> Result<(), FlvError> {
+        if let ScriptData::Reference(index) = *self {
+            let index = index as usize;
+            let target = match targets.get(&index) {
+                Some(target) if !containing.contains(&index) => target,
+                _ => return Ok(()),
+            };
+            // copies of copies grow exponentially, a few bytes of references could otherwise take all the memory.
+            *budget = budget.checked_sub(target.node_count())
+                .ok_or(FlvError::amf("Too many values behind AMF0 references."))?;
+            *self = target.clone();
+            containing.push(index);
+            let resolved = self.resolve(None, containing, targets, budget);
+            containing.pop();
+            return resolved;
+        }
+
+        let index = match next_index.as_deref_mut() {
+            Some(next_index) if self.is_complex() => {
+                *next_index += 1;
+                Some(*next_index - 1)
+            }
+            _ => None,
+        };
+        if let Some(index) = index {
+            containing.push(index);
+        }
+        let resolved = self.try_for_each_child_mut(&mut |child| child.resolve(next_index.as_deref_mut(), containing, targets, budget));
+        if index.is_some() {
+            containing.pop();
+        }
+        resolved
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct ScriptDataObject {
     pub properties: Vec<ScriptDataObjectProp>,
 }
 
 impl ScriptDataObject {
-    pub fn parse(data: &mut Decoder) -> Result<ScriptDataObject, Box<dyn std::error::Error>> {
-        let type_marker = data.drain_u8();
+    pub fn parse(data: &mut Decoder) -> Result<ScriptDataObject, FlvError> {
+        let type_marker = data.drain_u8()?;
         if type_marker != 3 {
             return Err(
-                Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    "Unable to parse object: Expected type marker Object(3), found something else.",
-                ).into()
+                FlvError::amf("Unable to parse object: Expected type marker Object(3), found something else.")
             );
         }
 
         ScriptDataObject::parse_no_marker(data)
     }
 
-    pub fn parse_no_marker(data: &mut Decoder) -> Result<ScriptDataObject, Box<dyn std::error::Error>> {
+    pub fn parse_no_marker(data: &mut Decoder) -> Result<ScriptDataObject, FlvError> {
         let mut properties = Vec::new();
         loop {
             let key = ScriptDataString::parse_no_marker(data)?;
@@ -91,86 +244,103 @@ impl ScriptDataObject {
     }
 }
 
-#[derive(Debug, Clone)]
+/// An object of a registered class, which is an object with the class name in front.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptDataTypedObject {
+    pub class_name: ScriptDataString,
+    pub properties: Vec<ScriptDataObjectProp>,
+}
+
+impl ScriptDataTypedObject {
+    pub fn parse(data: &mut Decoder) -> Result<ScriptDataTypedObject, FlvError> {
+        let type_marker = data.drain_u8()?;
+        if type_marker != 16 {
+            return Err(
+                FlvError::amf(format!("Unable to parse typed object: Expected type marker TypedObject(16), found {}.", type_marker))
+            );
+        }
+
+        Self::parse_no_marker(data)
+    }
+
+    pub fn parse_no_marker(data: &mut Decoder) -> Result<ScriptDataTypedObject, FlvError> {
+        let class_name = ScriptDataString::parse_no_marker(data)?;
+        let ScriptDataObject { properties } = ScriptDataObject::parse_no_marker(data)?;
+        Ok(ScriptDataTypedObject { class_name, properties })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct ScriptDataObjectProp {
     pub name: ScriptDataString,
     pub value: ScriptData,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ScriptDataString {
     pub length: u16,
     pub data: String,
 }
 
 impl ScriptDataString {
-    pub fn parse(data: &mut Decoder) -> Result<ScriptDataString, Box<dyn std::error::Error>> {
-        let type_marker = data.drain_u8();
+    pub fn parse(data: &mut Decoder) -> Result<ScriptDataString, FlvError> {
+        let type_marker = data.drain_u8()?;
         if type_marker != 2 {
             return Err(
-                Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    format!("Unable to parse string: Expected type marker String(2), found {}.", type_marker),
-                ).into()
+                FlvError::amf(format!("Unable to parse string: Expected type marker String(2), found {}.", type_marker))
             );
         }
-        let length = data.drain_u16();
-        let data = data.drain_bytes_vec(length as usize).into_iter().collect::<Vec<_>>();
+        let length = data.drain_u16()?;
+        let data = data.drain_bytes_vec(length as usize)?.into_iter().collect::<Vec<_>>();
         let data = String::from_utf8(data)?;
         Ok(ScriptDataString { length, data })
     }
 
-    pub fn parse_no_marker(data: &mut Decoder) -> Result<ScriptDataString, Box<dyn std::error::Error>> {
-        let length = data.drain_u16();
-        let data = data.drain_bytes_vec(length as usize).into_iter().collect::<Vec<_>>();
+    pub fn parse_no_marker(data: &mut Decoder) -> Result<ScriptDataString, FlvError> {
+        let length = data.drain_u16()?;
+        let data = data.drain_bytes_vec(length as usize)?.into_iter().collect::<Vec<_>>();
         let data = String::from_utf8(data)?;
         Ok(ScriptDataString { length, data })
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ScriptDataLongString {
     pub length: u32,
     pub data: String,
 }
 
 impl ScriptDataLongString {
-    pub fn parse(data: &mut Decoder) -> Result<ScriptDataLongString, Box<dyn std::error::Error>> {
-        let type_marker = data.drain_u8();
+    pub fn parse(data: &mut Decoder) -> Result<ScriptDataLongString, FlvError> {
+        let type_marker = data.drain_u8()?;
         if type_marker != 12 {
             return Err(
-                Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    format!("Unable to parse long string: Expected type marker LongString(12), found {}.", type_marker),
-                ).into()
+                FlvError::amf(format!("Unable to parse long string: Expected type marker LongString(12), found {}.", type_marker))
             );
         }
         Self::parse_no_marker(data)
     }
 
-    pub fn parse_no_marker(data: &mut Decoder) -> Result<ScriptDataLongString, Box<dyn std::error::Error>> {
-        let length = data.drain_u32();
-        let data = data.drain_bytes_vec(length as usize).into_iter().collect::<Vec<_>>();
+    pub fn parse_no_marker(data: &mut Decoder) -> Result<ScriptDataLongString, FlvError> {
+        let length = data.drain_u32()?;
+        let data = data.drain_bytes_vec(length as usize)?.into_iter().collect::<Vec<_>>();
         let data = String::from_utf8(data)?;
         Ok(ScriptDataLongString { length, data })
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ScriptDataEcmaArray {
     pub length: u32,
     pub properties: Vec<ScriptDataObjectProp>,
 }
 
 impl ScriptDataEcmaArray {
-    pub fn parse(data: &mut Decoder) -> Result<ScriptDataEcmaArray, Box<dyn std::error::Error>> {
-        let type_marker = data.drain_u8();
+    pub fn parse(data: &mut Decoder) -> Result<ScriptDataEcmaArray, FlvError> {
+        let type_marker = data.drain_u8()?;
         if type_marker != 8 {
             return Err(
-                Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    format!("Unable to parse ecma array: Expected type marker EcmaArray(8), found {}.", type_marker),
-                ).into()
+                FlvError::amf(format!("Unable to parse ecma array: Expected type marker EcmaArray(8), found {}.", type_marker))
             );
         }
 
@@ -179,43 +349,47 @@ impl ScriptDataEcmaArray {
         // it seems that the answer is no. but i'm not sure.
     }
 
-    pub fn parse_no_marker(data: &mut Decoder) -> Result<ScriptDataEcmaArray, Box<dyn std::error::Error>> {
-        let length = data.drain_u32();
-        let mut properties = Vec::with_capacity(length as usize);
-        for _ in 0..length + 1 {
+    pub fn parse_no_marker(data: &mut Decoder) -> Result<ScriptDataEcmaArray, FlvError> {
+        let length = data.drain_u32()?;
+        // the length is only a hint, and often wrong. the array runs up to its end marker, which is kept as the last property.
+        // it is also read from the stream, so do not trust it for pre-allocation.
+        let mut properties = Vec::new();
+        loop {
             let key = ScriptDataString::parse_no_marker(data)?;
             let data = parse_object(data)?;
+            let end = key.data.is_empty() && matches!(data, ScriptData::ObjectEndMarker);
             properties.push(ScriptDataObjectProp { name: key, value: data });
+            if end {
+                break;
+            }
         }
         Ok(ScriptDataEcmaArray { length, properties })
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ScriptStrictArray {
     pub length: u32,
     pub values: Vec<ScriptData>,
 }
 
 impl ScriptStrictArray {
-    pub fn parse(data: &mut Decoder) -> Result<ScriptStrictArray, Box<dyn std::error::Error>> {
-        let type_marker = data.drain_u8();
+    pub fn parse(data: &mut Decoder) -> Result<ScriptStrictArray, FlvError> {
+        let type_marker = data.drain_u8()?;
         if type_marker != 10 {
             return Err(
-                Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    format!("Unable to parse strict array: Expected type marker StrictArray(10), found {}.", type_marker),
-                ).into()
+                FlvError::amf(format!("Unable to parse strict array: Expected type marker StrictArray(10), found {}.", type_marker))
             );
         }
 
         Self::parse_no_marker(data)
     }
 
-    pub fn parse_no_marker(data: &mut Decoder) -> Result<ScriptStrictArray, Box<dyn std::error::Error>> {
-        let length = data.drain_u32();
-        let mut values = Vec::with_capacity(length as usize);
-        for _ in 0..length + 1 {
+    pub fn parse_no_marker(data: &mut Decoder) -> Result<ScriptStrictArray, FlvError> {
+        let length = data.drain_u32()?;
+        let mut values = Vec::new();
+        // unlike ecma arrays, strict arrays have no end marker.
+        for _ in 0..length {
             let value = parse_object(data)?;
             values.push(value);
         }
@@ -223,30 +397,189 @@ impl ScriptStrictArray {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ScriptDataDate {
     pub date: f64,
     pub local_time_offset: i16,
 }
 
 impl ScriptDataDate {
-    pub fn parse(data: &mut Decoder) -> Result<ScriptDataDate, Box<dyn std::error::Error>> {
-        let type_marker = data.drain_u8();
+    pub fn parse(data: &mut Decoder) -> Result<ScriptDataDate, FlvError> {
+        let type_marker = data.drain_u8()?;
         if type_marker != 11 {
             return Err(
-                Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    format!("Unable to parse date: Expected type marker Date(11), found {}.", type_marker),
-                ).into()
+                FlvError::amf(format!("Unable to parse date: Expected type marker Date(11), found {}.", type_marker))
             );
         }
 
         Self::parse_no_marker(data)
     }
 
-    pub fn parse_no_marker(data: &mut Decoder) -> Result<ScriptDataDate, Box<dyn std::error::Error>> {
-        let date = data.drain_f64();
-        let local_time_offset = data.drain_i16();
+    pub fn parse_no_marker(data: &mut Decoder) -> Result<ScriptDataDate, FlvError> {
+        let date = data.drain_f64()?;
+        let local_time_offset = data.drain_i16()?;
         Ok(ScriptDataDate { date, local_time_offset })
     }
-}
\ No newline at end of file
+}
+/// Writes a U29 for the AMF3 parts of AMF0 output, which are only ever byte arrays.
+fn serialize_u29(value: u32, out: &mut Vec<u8>) -> Result<(), FlvError> {
+    match value {
+        0..=0x7F => out.push(value as u8),
+        0x80..=0x3FFF => out.extend_from_slice(&[(value >> 7) as u8 | 0x80, value as u8 & 0x7F]),
+        0x4000..=0x1F_FFFF => out.extend_from_slice(&[(value >> 14) as u8 | 0x80, (value >> 7) as u8 | 0x80, value as u8 & 0x7F]),
+        0x20_0000..=0x1FFF_FFFF => out.extend_from_slice(&[(value >> 22) as u8 | 0x80, (value >> 15) as u8 | 0x80, (value >> 8) as u8 | 0x80, value as u8]),
+        _ => return Err(FlvError::amf(format!("{} does not fit in an AMF3 U29.", value))),
+    }
+    Ok(())
+}
+
+/// Write the properties, and an end marker after them.
+/// The end marker property which parsing keeps at the end is skipped, so it is not written twice.
+fn serialize_properties(properties: &[ScriptDataObjectProp], out: &mut Vec<u8>) -> Result<(), FlvError> {
+    for prop in properties {
+        if prop.name.data.is_empty() && matches!(prop.value, ScriptData::ObjectEndMarker) {
+            continue;
+        }
+        prop.name.serialize_no_marker(out)?;
+        prop.value.serialize_into(out)?;
+    }
+    out.extend_from_slice(&[0, 0, 9]);
+    Ok(())
+}
+
+impl ScriptTagBody {
+    /// Serialise into AMF0, the way it is found in the body of a script tag.
+    pub fn serialize(&self) -> Result<Vec<u8>, FlvError> {
+        let mut out = vec![2];
+        self.name.serialize_no_marker(&mut out)?;
+        out.push(8);
+        self.value.serialize_no_marker(&mut out)?;
+        Ok(out)
+    }
+}
+
+impl ScriptData {
+    /// Serialise into AMF0, so that `parse_object` reads back the same value.
+    ///
+    /// Note: AMF0 has no byte arrays, they are written as AMF3 behind the AVM+ switch.
+    /// `MovieClip` and `NotImplemented` have no encoding and fail.
+    pub fn serialize(&self) -> Result<Vec<u8>, FlvError> {
+        let mut out = vec![];
+        self.serialize_into(&mut out)?;
+        Ok(out)
+    }
+
+    pub fn serialize_into(&self, out: &mut Vec<u8>) -> Result<(), FlvError> {
+        match self {
+            ScriptData::Number(number) => {
+                out.push(0);
+                out.extend_from_slice(&number.to_be_bytes());
+            }
+            ScriptData::Boolean(boolean) => out.extend_from_slice(&[1, *boolean]),
+            ScriptData::String(string) => {
+                // too long for a string after all, it could only have been built by hand.
+                if string.data.len() > u16::MAX as usize {
+                    out.push(12);
+                    ScriptDataLongString { length: string.data.len() as u32, data: string.data.clone() }.serialize_no_marker(out)?;
+                } else {
+                    out.push(2);
+                    string.serialize_no_marker(out)?;
+                }
+            }
+            ScriptData::Object(object) => {
+                out.push(3);
+                serialize_properties(&object.properties, out)?;
+            }
+            ScriptData::Null => out.push(5),
+            ScriptData::Undefined => out.push(6),
+            ScriptData::Reference(index) => {
+                out.push(7);
+                out.extend_from_slice(&index.to_be_bytes());
+            }
+            ScriptData::EcmaArray(array) => {
+                out.push(8);
+                array.serialize_no_marker(out)?;
+            }
+            ScriptData::ObjectEndMarker => out.push(9),
+            ScriptData::StrictArray(array) => {
+                out.push(10);
+                array.serialize_no_marker(out)?;
+            }
+            ScriptData::Date(date) => {
+                out.push(11);
+                date.serialize_no_marker(out);
+            }
+            ScriptData::LongString(string) => {
+                out.push(12);
+                string.serialize_no_marker(out)?;
+            }
+            ScriptData::Unsupported => out.push(13),
+            ScriptData::XmlDocument(xml) => {
+                out.push(15);
+                xml.serialize_no_marker(out)?;
+            }
+            ScriptData::TypedObject(object) => {
+                out.push(16);
+                object.class_name.serialize_no_marker(out)?;
+                serialize_properties(&object.properties, out)?;
+            }
+            ScriptData::ByteArray(bytes) => {
+                out.extend_from_slice(&[17, 0x0C]);
+                serialize_u29(((bytes.len() as u32) << 1) | 1, out)?;
+                out.extend_from_slice(bytes);
+            }
+            ScriptData::MovieClip | ScriptData::NotImplemented => {
+                return Err(FlvError::amf(format!("{:?} can not be written as AMF0.", self)));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ScriptDataString {
+    pub fn serialize_no_marker(&self, out: &mut Vec<u8>) -> Result<(), FlvError> {
+        let length = u16::try_from(self.data.len())
+            .map_err(|_| FlvError::amf(format!("A string of {} bytes does not fit in AMF0.", self.data.len())))?;
+        out.extend_from_slice(&length.to_be_bytes());
+        out.extend_from_slice(self.data.as_bytes());
+        Ok(())
+    }
+}
+
+impl ScriptDataLongString {
+    pub fn serialize_no_marker(&self, out: &mut Vec<u8>) -> Result<(), FlvError> {
+        let length = u32::try_from(self.data.len())
+            .map_err(|_| FlvError::amf(format!("A string of {} bytes does not fit in AMF0.", self.data.len())))?;
+        out.extend_from_slice(&length.to_be_bytes());
+        out.extend_from_slice(self.data.as_bytes());
+        Ok(())
+    }
+}
+
+impl ScriptDataEcmaArray {
+    /// The length written is the actual number of properties, `length` may be stale after editing.
+    pub fn serialize_no_marker(&self, out: &mut Vec<u8>) -> Result<(), FlvError> {
+        let count = self.properties.iter()
+            .filter(|prop| !(prop.name.data.is_empty() && matches!(prop.value, ScriptData::ObjectEndMarker)))
+            .count();
+        out.extend_from_slice(&(count as u32).to_be_bytes());
+        serialize_properties(&self.properties, out)
+    }
+}
+
+impl ScriptStrictArray {
+    pub fn serialize_no_marker(&self, out: &mut Vec<u8>) -> Result<(), FlvError> {
+        out.extend_from_slice(&(self.values.len() as u32).to_be_bytes());
+        for value in &self.values {
+            value.serialize_into(out)?;
+        }
+        Ok(())
+    }
+}
+
+impl ScriptDataDate {
+    pub fn serialize_no_marker(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.date.to_be_bytes());
+        out.extend_from_slice(&self.local_time_offset.to_be_bytes());
+    }
+}
@@ -1,5 +1,7 @@
+use crate::error::FlvError;
 use crate::flv::header::{EncryptionTagHeader, FilterParameters, TagHeader};
 use crate::flv::script::ScriptTagBody;
+use crate::flv::timestamp::TimestampDiscontinuity;
 use std::collections::VecDeque;
 use std::fmt::{Debug, Formatter};
 
@@ -16,6 +18,8 @@ pub struct Tag {
     pub encryption_tag_header: Option<EncryptionTagHeader>,
     pub filter_parameters: Option<FilterParameters>,
     pub tag_body: TagBody,
+    /// Set on the first tag after a break in the timestamps, see `TimestampPolicy::Signal`.
+    pub discontinuity: Option<TimestampDiscontinuity>,
 }
 
 #[derive(Debug, Clone)]
@@ -92,16 +96,17 @@ impl Debug for NormalTagBody {
     }
 }
 
+/// The still encrypted body of a tag, kept as is when no decryption key is set.
 #[derive(Debug, Clone)]
 pub enum EncryptedTagBody {
     Audio(Vec<u8>),
     Video(Vec<u8>),
-    Script(ScriptTagBody),
+    Script(Vec<u8>),
     Placeholder,
 }
 
 impl TagType {
-    pub fn from(tag_type: u8) -> Result<TagType, Box<dyn std::error::Error>> {
+    pub fn from(tag_type: u8) -> Result<TagType, FlvError> {
         match tag_type {
             8 => {
                 Ok(TagType::Audio)
@@ -113,7 +118,7 @@ impl TagType {
                 Ok(TagType::Script)
             }
             _ => {
-                Err(Box::new(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid tag type")))
+                Err(FlvError::malformed(format!("Invalid tag type {}.", tag_type)))
             }
         }
     }
@@ -145,6 +150,7 @@ impl Tag {
             tag_body,
             encryption_tag_header,
             filter_parameters,
+            discontinuity: None,
         }
     }
 }
\ No newline at end of file
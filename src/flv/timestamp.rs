@@ -0,0 +1,187 @@
+use crate::flv::tag::TagType;
+
+/// Backward steps up to this size are taken as jitter, not as a discontinuity.
+/// Audio and video are checked separately, so their interleaving does not count.
+const DEFAULT_BACKWARD_TOLERANCE_MS: u32 = 500;
+/// Forward steps larger than this are taken as a gap in the stream.
+const DEFAULT_FORWARD_GAP_MS: u32 = 10_000;
+
+const TIMESTAMP_RANGE: i64 = 1 << 32;
+
+/// What the decoder does about timestamps which do not continue the previous ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimestampPolicy {
+    /// Pass the timestamps on as they are, without looking at them. This is the default.
+    #[default]
+    Passthrough,
+    /// Shift the timestamps after a backward jump or a forward gap,
+    /// so that they continue right after the previous ones.
+    Rebase,
+    /// Keep the timestamps, but attach a `TimestampDiscontinuity` to the first tag after the break.
+    /// The remuxer flushes its buffered samples there and passes the event on to the core.
+    Signal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiscontinuityKind {
+    /// The timestamps went back further than the tolerance, e.g. after the publisher reconnected.
+    BackwardJump,
+    /// The timestamps skipped ahead further than the allowed gap.
+    ForwardGap,
+    /// The 32-bit timestamp overflowed and started over from 0.
+    /// The stream itself is continuous, so this is never rebased.
+    Wrap,
+}
+
+#[derive(Debug, Clone)]
+pub struct TimestampDiscontinuity {
+    pub kind: DiscontinuityKind,
+    /// Type of the tag the break was detected on.
+    pub tag_type: TagType,
+    /// Timestamp of the previous tag of the same type, as it was in the stream.
+    pub previous: u32,
+    /// Timestamp of the tag after the break, as it is in the stream.
+    pub current: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct LastTimestamp {
+    raw: u32,
+    /// After the offset, and not truncated to 32 bits.
+    rebased: i64,
+    /// Distance to the tag before, used as the step across a rebased break.
+    step: i64,
+}
+
+/// Watches the timestamps of the audio and video tags, and keeps them continuous according to a `TimestampPolicy`.
+///
+/// Note: the offset is shared by all tag types, so audio and video are shifted by the same amount
+/// and stay in sync across a break, whichever of them runs into it first.
+#[derive(Debug, Clone)]
+pub struct TimestampTracker {
+    policy: TimestampPolicy,
+    backward_tolerance: u32,
+    forward_gap: u32,
+
+    offset: i64,
+    // indexed by `Self::slot`: audio, video.
+    last: [Option<LastTimestamp>; 2],
+}
+
+impl Default for TimestampTracker {
+    fn default() -> Self {
+        Self::new(TimestampPolicy::default())
+    }
+}
+
+impl TimestampTracker {
+    pub fn new(policy: TimestampPolicy) -> Self {
+        Self {
+            policy,
+            backward_tolerance: DEFAULT_BACKWARD_TOLERANCE_MS,
+            forward_gap: DEFAULT_FORWARD_GAP_MS,
+            offset: 0,
+            last: [None; 2],
+        }
+    }
+
+    #[inline]
+    pub fn policy(&self) -> TimestampPolicy {
+        self.policy
+    }
+
+    #[inline]
+    pub fn set_policy(&mut self, policy: TimestampPolicy) {
+        self.policy = policy;
+    }
+
+    /// Set how far the timestamps may go back, and how far they may skip ahead, before it counts as a break.
+    /// Both in milliseconds.
+    #[inline]
+    pub fn set_thresholds(&mut self, backward_tolerance: u32, forward_gap: u32) {
+        self.backward_tolerance = backward_tolerance;
+        self.forward_gap = forward_gap;
+    }
+
+    /// Forget the previous timestamps and the offset, e.g. after a seek, where the jump is intended.
+    /// Seek targets are timestamps in the file, so the tags after it are taken as they are again.
+    pub fn reset(&mut self) {
+        self.offset = 0;
+        self.last = [None; 2];
+    }
+
+    #[inline]
+    fn slot(tag_type: &TagType) -> Option<usize> {
+        match tag_type {
+            TagType::Audio => Some(0),
+            TagType::Video => Some(1),
+            _ => None,
+        }
+    }
+
+    /// Take the timestamp of the next tag, and return the one to use instead,
+    /// along with the discontinuity in front of it if there is one.
+    pub fn track(&mut self, tag_type: &TagType, timestamp: u32) -> (u32, Option<TimestampDiscontinuity>) {
+        if self.policy == TimestampPolicy::Passthrough {
+            return (timestamp, None);
+        }
+        // script tags are often stamped 0 anywhere in the stream, they are only shifted.
+        let slot = match Self::slot(tag_type) {
+            Some(slot) => slot,
+            None => return (Self::truncate(timestamp as i64 + self.offset), None),
+        };
+
+        let mut rebased = timestamp as i64 + self.offset;
+        let mut discontinuity = None;
+
+        if let Some(last) = self.last[slot] {
+            let delta = rebased - last.rebased;
+            if delta < -(self.backward_tolerance as i64) || delta > self.forward_gap as i64 {
+                let wrapped = last.raw >= 0x8000_0000
+                    && timestamp < 0x8000_0000
+                    && timestamp.wrapping_sub(last.raw) <= self.forward_gap;
+                let kind = if wrapped {
+                    DiscontinuityKind::Wrap
+                } else if delta < 0 {
+                    DiscontinuityKind::BackwardJump
+                } else {
+                    DiscontinuityKind::ForwardGap
+                };
+
+                if wrapped {
+                    self.offset += TIMESTAMP_RANGE;
+                } else if self.policy == TimestampPolicy::Rebase {
+                    // continue after the latest tag of any type, so the other one lines up as well.
+                    let latest = self.last.iter().flatten().map(|last| last.rebased).max().unwrap_or(last.rebased);
+                    self.offset += latest + last.step - rebased;
+                }
+                rebased = timestamp as i64 + self.offset;
+
+                discontinuity = Some(TimestampDiscontinuity {
+                    kind,
+                    tag_type: tag_type.clone(),
+                    previous: last.raw,
+                    current: timestamp,
+                });
+            }
+        }
+
+        let step = match self.last[slot] {
+            Some(last) if discontinuity.is_none() => rebased - last.rebased,
+            Some(last) => last.step,
+            None => 0,
+        };
+        self.last[slot] = Some(LastTimestamp { raw: timestamp, rebased, step: step.max(0) });
+
+        // with `Rebase`, there is nothing left for the downstream to react on.
+        if self.policy == TimestampPolicy::Rebase {
+            discontinuity = None;
+        }
+        (Self::truncate(rebased), discontinuity)
+    }
+
+    #[inline]
+    fn truncate(rebased: i64) -> u32 {
+        rebased.rem_euclid(TIMESTAMP_RANGE) as u32
+    }
+}
@@ -1,3 +1,4 @@
+use crate::error::FlvError;
 use crate::fmpeg::mp4frag::{MergedSampleDependencyTableBoxBuilder, MergedTrackFragmentBox, MergedTrackFragmentBoxBuilder, MergedTrackRunBox, MergedTrackRunBoxEntryBuilder, MovieDataBox, MovieFragmentBox, SampleDependencyTableBoxBuilder, SampleFlagBuilder, TrackFragmentBox, TrackFragmentBoxBuilder, TrackRunBoxBuilder};
 use crate::fmpeg::mp4head;
 use crate::fmpeg::mp4head::aac_utils::AacAudioSpecConfLike;
@@ -6,36 +7,51 @@ use crate::fmpeg::remux_context::{AudioCodecType, RemuxContext, SampleContext, T
 
 pub struct Encoder;
 
-pub const DEFAULT_VIDEO_TRACK_ID: u32 = 1;
-pub const DEFAULT_AUDIO_TRACK_ID: u32 = 2;
-
 impl Encoder {
+    /// The mp4 track id follows from the flv track id, so it is known before every track is.
+    /// Track 0 gets 1 for video and 2 for audio, like a stream which is not multitrack.
+    pub fn mp4_track_id(track_type: &TrackType, flv_track_id: u8) -> u32 {
+        match track_type {
+            TrackType::Video => 1 + 2 * flv_track_id as u32,
+            TrackType::Audio => 2 + 2 * flv_track_id as u32,
+        }
+    }
+
     pub fn encode_ftyp(ctx: &RemuxContext) -> FileTypeBox {
         let ftyp = mp4head::FileTypeBoxBuilder::new()
             .major_brand(&ctx.major_brand)
-            .minor_version(ctx.minor_version.parse().unwrap())
+            .minor_version(ctx.minor_version.parse().unwrap_or(512))
             .compatible_brands(ctx.compatible_brands.clone())
             .build();
         // dbg!(&ftyp);
         ftyp
     }
 
-    pub fn encode_moov(ctx: &RemuxContext) -> MovieBox {
-        let moov = mp4head::MovieBoxBuilder::new()
-            .movie_header_box(Self::encode_mhdv(ctx))
-            .track(Self::encode_trak(ctx, DEFAULT_VIDEO_TRACK_ID, Self::encode_mdia(ctx, HandlerType::Video)))
-            .track(Self::encode_trak(ctx, DEFAULT_AUDIO_TRACK_ID, Self::encode_mdia(ctx, HandlerType::Audio)))
-            .build();
-        moov
+    pub fn encode_moov(ctx: &RemuxContext) -> Result<MovieBox, FlvError> {
+        let mut moov = mp4head::MovieBoxBuilder::new()
+            .movie_header_box(Self::encode_mhdv(ctx));
+        for &track_id in ctx.video_tracks.keys() {
+            let mdia = Self::encode_mdia(ctx, HandlerType::Video, track_id)?;
+            moov = moov.track(Self::encode_trak(ctx, Self::mp4_track_id(&TrackType::Video, track_id), ctx.video_size(track_id), mdia));
+        }
+        for &track_id in ctx.audio_tracks.keys() {
+            let mdia = Self::encode_mdia(ctx, HandlerType::Audio, track_id)?;
+            moov = moov.track(Self::encode_trak(ctx, Self::mp4_track_id(&TrackType::Audio, track_id), (0.0, 0.0), mdia));
+        }
+        Ok(moov.build())
     }
 
     pub fn encode_mhdv(ctx: &RemuxContext) -> MovieHeaderBox {
+        let last_track_id = ctx.video_tracks.keys().map(|&id| Self::mp4_track_id(&TrackType::Video, id))
+            .chain(ctx.audio_tracks.keys().map(|&id| Self::mp4_track_id(&TrackType::Audio, id)))
+            .max()
+            .unwrap_or(0);
         let mhdv = mp4head::MovieHeaderBoxV0Builder::new()
             .creation_time(0)
             .modification_time(0)
             .duration(ctx.duration_ms)
             .timescale(TIME_SCALE)
-            .next_track_id(3)
+            .next_track_id(last_track_id + 1)
             .rate(1.0)
             .volume(1.0)
             .build();
@@ -43,7 +59,8 @@ impl Encoder {
         MovieHeaderBox::V0(mhdv)
     }
 
-    pub fn encode_trak(ctx: &RemuxContext, track_id: u32, media_box: MediaBox) -> mp4head::TrackBox {
+    /// `(width, height)` is the picture size of a video track, an audio track has none.
+    pub fn encode_trak(ctx: &RemuxContext, track_id: u32, (width, height): (f64, f64), media_box: MediaBox) -> mp4head::TrackBox {
         let trak = mp4head::TrackBox::new(
             mp4head::TrackHeaderBox::V0(
                 mp4head::TrackHeaderBoxV0Builder::new()
@@ -51,8 +68,8 @@ impl Encoder {
                     .duration(ctx.duration_ms)
                     .creation_time(0)
                     .modification_time(0)
-                    .width(FixedPoint32::from(ctx.width))
-                    .height(FixedPoint32::from(ctx.height))
+                    .width(FixedPoint32::from(width))
+                    .height(FixedPoint32::from(height))
                     .build()
             ),
             media_box,
@@ -61,14 +78,14 @@ impl Encoder {
         trak
     }
 
-    pub fn encode_mdia(ctx: &RemuxContext, handler_type: HandlerType) -> MediaBox {
+    pub fn encode_mdia(ctx: &RemuxContext, handler_type: HandlerType, track_id: u8) -> Result<MediaBox, FlvError> {
         let mdia = mp4head::MediaBox::new(
             Self::encode_mdhd(ctx),
             Self::encode_hdlr(ctx, handler_type.clone()),
-            Self::encode_minf(ctx, handler_type),
+            Self::encode_minf(ctx, handler_type, track_id)?,
         );
         // dbg!(&mdia);
-        mdia
+        Ok(mdia)
     }
 
     pub fn encode_mdhd(ctx: &RemuxContext) -> mp4head::MediaHeaderBoxV0 {
@@ -88,7 +105,8 @@ impl Encoder {
         hdlr
     }
 
-    pub fn encode_minf(ctx: &RemuxContext, handler_type: HandlerType) -> mp4head::MediaInfoBox {
+    /// `track_id` is the flv track id, whose configuration goes into the sample entry.
+    pub fn encode_minf(ctx: &RemuxContext, handler_type: HandlerType, track_id: u8) -> Result<mp4head::MediaInfoBox, FlvError> {
         let xmhd: XMediaHandlerBox = match handler_type {
             HandlerType::Video => {
                 XMediaHandlerBox::Video(VideoMediaHandlerBox::new())
@@ -102,40 +120,56 @@ impl Encoder {
             .add_sample_description_table_box(
                 match handler_type {
                     HandlerType::Video => {
-                        if let VideoCodecType::Avc1 = ctx.video_codec_type {
-                            mp4head::SubSampleDescriptionTableBox::Avc1(
-                                mp4head::Avc1DescriptionBoxBuilder::new()
-                                    .set_width(ctx.width as u16)
-                                    .set_height(ctx.height as u16)
-                                    .avcc_box(ctx.video_avcc_info.clone())
-                                    // here is the place to add video configuration
-                                    .build()
-                            )
-                        } else {
-                            panic!("Unsupported video codec type")
+                        let track = ctx.video_tracks.get(&track_id)
+                            .ok_or(FlvError::internal(format!("Video track {} is not configured.", track_id)))?;
+                        if let VideoCodecType::None = track.codec_type {
+                            return Err(FlvError::unsupported_codec(format!("video codec id {}", ctx.video_codec_id)));
                         }
+                        // the sample entry type follows the configuration box, so this covers every codec.
+                        let (width, height) = ctx.video_size(track_id);
+                        mp4head::SubSampleDescriptionTableBox::Avc1(
+                            mp4head::Avc1DescriptionBoxBuilder::new()
+                                .set_width(width as u16)
+                                .set_height(height as u16)
+                                .avcc_box(track.avcc_info.clone())
+                                // here is the place to add video configuration
+                                .build()
+                        )
                     }
                     HandlerType::Audio => {
-                        match ctx.audio_codec_type {
+                        let track = ctx.audio_tracks.get(&track_id)
+                            .ok_or(FlvError::internal(format!("Audio track {} is not configured.", track_id)))?;
+                        match track.codec_type {
                             AudioCodecType::Aac => {
                                 mp4head::SubSampleDescriptionTableBox::Mp4a(
                                     mp4head::Mp4aDescriptionBoxBuilder::new()
-                                        .sample_rate(ctx.audio_sample_rate as f32)
-                                        .num_audio_channels(ctx.audio_channels as u16)
-                                        .spec_config(AacAudioSpecConfLike::VectorConfig(ctx.audio_aac_info.clone()))
+                                        .sample_rate(track.sample_rate as f32)
+                                        .num_audio_channels(track.channels as u16)
+                                        .spec_config(AacAudioSpecConfLike::VectorConfig(track.aac_info.clone()))
                                         .build()
                                 )
                             }
                             AudioCodecType::Mp3 => {
                                 mp4head::SubSampleDescriptionTableBox::Mp3(
                                     mp4head::Mp3DescriptionBoxBuilder::new()
-                                        .sample_rate(ctx.audio_sample_rate as f32)
-                                        .num_audio_channels(ctx.audio_channels as u16)
+                                        .sample_rate(track.sample_rate as f32)
+                                        .num_audio_channels(track.channels as u16)
+                                        .build()
+                                )
+                            }
+                            AudioCodecType::Opus | AudioCodecType::Flac | AudioCodecType::Ac3 | AudioCodecType::Eac3 => {
+                                let config_box = track.ex_config.clone()
+                                    .ok_or(FlvError::internal("No audio configuration box for the enhanced audio codec."))?;
+                                mp4head::SubSampleDescriptionTableBox::ExAudio(
+                                    mp4head::ExAudioDescriptionBoxBuilder::new()
+                                        .sample_rate(track.sample_rate as f32)
+                                        .num_audio_channels(track.channels as u16)
+                                        .config_box(config_box)
                                         .build()
                                 )
                             }
                             AudioCodecType::None => {
-                                panic!("Unsupported audio codec type")
+                                return Err(FlvError::internal("No audio codec type specified."));
                             }
                         }
                     }
@@ -149,7 +183,7 @@ impl Encoder {
             stbl,
         );
         // dbg!(&minf);
-        minf
+        Ok(minf)
     }
 
     pub fn encode_moof(ctx: &mut RemuxContext, track_ctx: &mut TrackContext, encoding_ctx: &mut SampleContext) -> MovieFragmentBox<TrackFragmentBox> {
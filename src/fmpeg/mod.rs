@@ -3,4 +3,5 @@ pub mod mp4head;
 pub mod mp4frag;
 pub mod remux_context;
 pub mod parser;
-pub mod encoder;
\ No newline at end of file
+pub mod encoder;
+pub mod sps;
\ No newline at end of file
@@ -1,4 +1,3 @@
-use crate::fmpeg::encoder::{DEFAULT_AUDIO_TRACK_ID, DEFAULT_VIDEO_TRACK_ID};
 use crate::fmpeg::mp4head::avc1_utils::AvcCBoxLike::AvcCBoxLike;
 
 pub struct Utils;
@@ -6,7 +5,7 @@ impl Utils {
     #[inline]
     pub fn str_to_char_array(s: &String) -> [char; 4] {
         let mut result = ['\0', '\0', '\0', '\0'];
-        for (i, c) in s.chars().enumerate() {
+        for (i, c) in s.chars().take(4).enumerate() {
             result[i] = c;
         }
         result
@@ -15,7 +14,7 @@ impl Utils {
     #[inline]
     pub fn slice_to_char_array(slice: &str) -> [char; 4] {
         let mut result = ['\0', '\0', '\0', '\0'];
-        for (i, c) in slice.chars().enumerate() {
+        for (i, c) in slice.chars().take(4).enumerate() {
             result[i] = c;
         }
         result
@@ -357,8 +356,8 @@ impl MovieBoxBuilder {
             size: 0,
             box_type: ['m', 'o', 'o', 'v'],
             movie_header: self.movie_header_box.unwrap(),
+            movie_extend_box: MovieExtendBox::new(self.tracks.iter().map(|track| track.track_id())),
             tracks: self.tracks,
-            movie_extend_box: MovieExtendBox::new(),
         };
         box_instance.size = box_instance.size();
         assert_ne!(box_instance.size, 0);
@@ -788,6 +787,13 @@ impl TrackBox {
             media_box,
         }
     }
+
+    pub fn track_id(&self) -> u32 {
+        match &self.track_header_box {
+            TrackHeaderBox::V0(header) => header.track_id,
+            TrackHeaderBox::V1(header) => header.track_id,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -1610,6 +1616,7 @@ pub enum SubSampleDescriptionTableBox {
     Mp4a(Mp4aDescriptionBox),
     Mp3(Mp3DescriptionBox),
     Avc1(Avc1DescriptionBox),
+    ExAudio(ExAudioDescriptionBox),
 }
 
 impl ISerializable for SubSampleDescriptionTableBox {
@@ -1619,6 +1626,7 @@ impl ISerializable for SubSampleDescriptionTableBox {
             SubSampleDescriptionTableBox::Mp4a(mp4a) => mp4a.serialize(),
             SubSampleDescriptionTableBox::Mp3(mp3) => mp3.serialize(),
             SubSampleDescriptionTableBox::Avc1(avc1) => avc1.serialize(),
+            SubSampleDescriptionTableBox::ExAudio(audio) => audio.serialize(),
         }
     }
 
@@ -1627,11 +1635,13 @@ impl ISerializable for SubSampleDescriptionTableBox {
             SubSampleDescriptionTableBox::Mp4a(mp4a) => mp4a.size(),
             SubSampleDescriptionTableBox::Mp3(mp3) => mp3.size(),
             SubSampleDescriptionTableBox::Avc1(avc1) => avc1.size(),
+            SubSampleDescriptionTableBox::ExAudio(audio) => audio.size(),
         }
     }
 }
 
 pub mod aac_utils {
+    use crate::error::FlvError;
     use crate::fmpeg::mp4head::ISerializable;
     use crate::io;
     use crate::io::bit::UIntParserEndian;
@@ -1677,20 +1687,22 @@ pub mod aac_utils {
         }
     }
 
-    impl From<u16> for AacObjectType {
+    impl TryFrom<u16> for AacObjectType {
+        type Error = FlvError;
+
         /// Note: this is for the decoder, not the encoder!!
         /// do not use it directly for flv metadata!
         #[inline]
-        fn from(value: u16) -> Self {
+        fn try_from(value: u16) -> Result<Self, Self::Error> {
             match value {
-                0 => AacObjectType::Null,
-                1 => AacObjectType::AacMain,
-                2 => AacObjectType::AacLc,
-                3 => AacObjectType::AacSsr,
-                4 => AacObjectType::AacLtp,
-                5 => AacObjectType::AacSbr,
-                6 => AacObjectType::AacScalable,
-                _ => panic!("Invalid AAC object type")
+                0 => Ok(AacObjectType::Null),
+                1 => Ok(AacObjectType::AacMain),
+                2 => Ok(AacObjectType::AacLc),
+                3 => Ok(AacObjectType::AacSsr),
+                4 => Ok(AacObjectType::AacLtp),
+                5 => Ok(AacObjectType::AacSbr),
+                6 => Ok(AacObjectType::AacScalable),
+                _ => Err(FlvError::unsupported_codec(format!("aac object type {}", value))),
             }
         }
     }
@@ -2319,40 +2331,271 @@ impl Mp3DescriptionBoxBuilder {
     }
 }
 
+pub mod ex_audio_utils {
+    use crate::fmpeg::mp4head::ISerializable;
+
+    /// The decoder configuration box inside an enhanced rtmp audio sample entry.
+    /// Each variant holds the box payload, without the box header.
+    #[derive(Debug, Clone)]
+    pub enum AudioConfigBoxLike {
+        DOpsBoxLike(Vec<u8>),
+        DfLaBoxLike(Vec<u8>),
+        Dac3BoxLike(Vec<u8>),
+        Dec3BoxLike(Vec<u8>),
+    }
+
+    impl AudioConfigBoxLike {
+        /// The body of the box, i.e. the codec specific configuration.
+        #[inline]
+        pub fn payload(&self) -> &Vec<u8> {
+            match self {
+                Self::DOpsBoxLike(data) | Self::DfLaBoxLike(data) | Self::Dac3BoxLike(data) | Self::Dec3BoxLike(data) => data,
+            }
+        }
+
+        #[inline]
+        pub fn box_type(&self) -> [char; 4] {
+            match self {
+                Self::DOpsBoxLike(_) => ['d', 'O', 'p', 's'],
+                Self::DfLaBoxLike(_) => ['d', 'f', 'L', 'a'],
+                Self::Dac3BoxLike(_) => ['d', 'a', 'c', '3'],
+                Self::Dec3BoxLike(_) => ['d', 'e', 'c', '3'],
+            }
+        }
+
+        /// Type of the sample entry which carries this box.
+        #[inline]
+        pub fn sample_entry_type(&self) -> [char; 4] {
+            match self {
+                Self::DOpsBoxLike(_) => ['O', 'p', 'u', 's'],
+                Self::DfLaBoxLike(_) => ['f', 'L', 'a', 'C'],
+                Self::Dac3BoxLike(_) => ['a', 'c', '-', '3'],
+                Self::Dec3BoxLike(_) => ['e', 'c', '-', '3'],
+            }
+        }
+    }
+
+    impl ISerializable for AudioConfigBoxLike {
+        #[inline]
+        fn serialize(&mut self) -> Vec<u8> {
+            let mut serialized = self.size().to_be_bytes().to_vec();
+            serialized.extend(self.box_type().map(|c| c as u8));
+            serialized.extend_from_slice(self.payload());
+            serialized
+        }
+
+        fn size(&self) -> u32 {
+            self.payload().len() as u32 + 8
+        }
+    }
+}
+
+/// The audio sample entry for the enhanced rtmp codecs,
+/// `Opus`, `fLaC`, `ac-3` or `ec-3` depending on the configuration box.
+#[derive(Debug)]
+pub struct ExAudioDescriptionBox {
+    pub size: u32,
+    pub box_type: [char; 4],
+    pub reserved: [u8; 6],
+    pub data_reference_index: u16,
+    pub version: u16,
+    pub revision_level: u16,
+    pub max_packet_size: u32,
+    pub num_audio_channels: u16,
+    pub sample_size: u16,
+    pub compression_id: u16,
+    pub packet_size: u16,
+    pub sample_rate: FixedPoint32,
+
+    pub config_box: ex_audio_utils::AudioConfigBoxLike,
+}
+
+impl ISerializable for ExAudioDescriptionBox {
+    #[inline]
+    fn serialize(&mut self) -> Vec<u8> {
+        self.size = self.size();
+
+        let mut result = vec![];
+        result.extend_from_slice(&self.size.to_be_bytes());
+        result.extend_from_slice(&self.box_type.map(|c| c as u8));
+
+        result.extend_from_slice(&self.reserved);
+        result.extend_from_slice(&self.data_reference_index.to_be_bytes());
+        result.extend_from_slice(&self.version.to_be_bytes());
+        result.extend_from_slice(&self.revision_level.to_be_bytes());
+        result.extend_from_slice(&self.max_packet_size.to_be_bytes());
+
+        result.extend_from_slice(&self.num_audio_channels.to_be_bytes());
+        result.extend_from_slice(&self.sample_size.to_be_bytes());
+
+        result.extend_from_slice(&self.compression_id.to_be_bytes());
+        result.extend_from_slice(&self.packet_size.to_be_bytes());
+
+        result.extend_from_slice(&self.sample_rate.serialize());
+
+        result.extend_from_slice(&self.config_box.serialize());
+        assert_eq!(result.len(), 36 + self.config_box.size() as usize);
+        result
+    }
+
+    fn size(&self) -> u32 {
+        36 + self.config_box.size()
+    }
+}
+
+impl ExAudioDescriptionBox {
+    pub fn new(sample_rate: f32, num_audio_channels: u16, config_box: ex_audio_utils::AudioConfigBoxLike) -> Self {
+        Self {
+            size: 0,
+            box_type: config_box.sample_entry_type(),
+            reserved: [0; 6],
+            data_reference_index: 1,
+            version: 0,
+            revision_level: 0,
+            max_packet_size: 0,
+            num_audio_channels,
+            sample_size: 16,
+            compression_id: 0,
+            packet_size: 0,
+            // the field only holds 16 bits of integer, higher rates are written as 0.
+            sample_rate: FixedPoint32::from(if sample_rate > u16::MAX as f32 { 0.0 } else { sample_rate }),
+            config_box,
+        }
+    }
+}
+
+pub struct ExAudioDescriptionBoxBuilder {
+    sample_rate: f32,
+    num_audio_channels: u16,
+    config_box: ex_audio_utils::AudioConfigBoxLike,
+}
+
+impl ExAudioDescriptionBoxBuilder {
+    pub fn new() -> Self {
+        Self {
+            sample_rate: 0.0,
+            num_audio_channels: 0,
+            config_box: ex_audio_utils::AudioConfigBoxLike::DOpsBoxLike(vec![]),
+        }
+    }
+
+    pub fn sample_rate(mut self, sample_rate: f32) -> Self {
+        self.sample_rate = sample_rate;
+        self
+    }
+
+    pub fn num_audio_channels(mut self, num_audio_channels: u16) -> Self {
+        self.num_audio_channels = num_audio_channels;
+        self
+    }
+
+    pub fn config_box(mut self, config_box: ex_audio_utils::AudioConfigBoxLike) -> Self {
+        self.config_box = config_box;
+        self
+    }
+
+    pub fn build(self) -> ExAudioDescriptionBox {
+        ExAudioDescriptionBox::new(self.sample_rate, self.num_audio_channels, self.config_box)
+    }
+}
+
 pub mod avc1_utils {
     use crate::fmpeg::mp4head::ISerializable;
 
+    /// The decoder configuration box inside a visual sample entry.
+    /// Each variant holds the raw configuration record, without the box header.
     #[derive(Debug, Clone)]
     pub enum AvcCBoxLike {
-        AvcCBoxLike(Vec<u8>)
+        AvcCBoxLike(Vec<u8>),
+        HvcCBoxLike(Vec<u8>),
+        /// An `hvcC` without parameter sets, which are in the samples instead.
+        HvcCInBandBoxLike(Vec<u8>),
+        Av1CBoxLike(Vec<u8>),
+        VpcCBoxLike(Vec<u8>),
+    }
+
+    impl AvcCBoxLike {
+        /// The decoder configuration record, i.e. the body of the box.
+        #[inline]
+        pub fn record(&self) -> &Vec<u8> {
+            match self {
+                Self::AvcCBoxLike(data) | Self::HvcCBoxLike(data) | Self::HvcCInBandBoxLike(data) | Self::Av1CBoxLike(data) | Self::VpcCBoxLike(data) => data,
+            }
+        }
+
+        /// `hvc1` requires the VPS, SPS and PPS in the record, otherwise they are in-band and the entry is `hev1`.
+        pub fn from_hvcc_record(record: Vec<u8>) -> Self {
+            // configurationVersion up to lengthSizeMinusOne take 22 bytes, then comes numOfArrays.
+            let mut found = [false; 3];
+            let mut offset = 23;
+            for _ in 0..record.get(22).copied().unwrap_or(0) {
+                let Some(header) = record.get(offset..offset + 3) else { break };
+                let nal_unit_type = header[0] & 0x3F;
+                let count = u16::from_be_bytes([header[1], header[2]]);
+                offset += 3;
+                for _ in 0..count {
+                    let Some(length) = record.get(offset..offset + 2) else { break };
+                    offset += 2 + u16::from_be_bytes([length[0], length[1]]) as usize;
+                }
+                // 32, 33 and 34 are the VPS, SPS and PPS.
+                if (32..=34).contains(&nal_unit_type) && count > 0 {
+                    found[(nal_unit_type - 32) as usize] = true;
+                }
+            }
+
+            if found.iter().all(|found| *found) {
+                Self::HvcCBoxLike(record)
+            } else {
+                Self::HvcCInBandBoxLike(record)
+            }
+        }
+
+        #[inline]
+        pub fn box_type(&self) -> [char; 4] {
+            match self {
+                Self::AvcCBoxLike(_) => ['a', 'v', 'c', 'C'],
+                Self::HvcCBoxLike(_) | Self::HvcCInBandBoxLike(_) => ['h', 'v', 'c', 'C'],
+                Self::Av1CBoxLike(_) => ['a', 'v', '1', 'C'],
+                Self::VpcCBoxLike(_) => ['v', 'p', 'c', 'C'],
+            }
+        }
+
+        /// Type of the sample entry which carries this box.
+        #[inline]
+        pub fn sample_entry_type(&self) -> [char; 4] {
+            match self {
+                Self::AvcCBoxLike(_) => ['a', 'v', 'c', '1'],
+                Self::HvcCBoxLike(_) => ['h', 'v', 'c', '1'],
+                Self::HvcCInBandBoxLike(_) => ['h', 'e', 'v', '1'],
+                Self::Av1CBoxLike(_) => ['a', 'v', '0', '1'],
+                Self::VpcCBoxLike(_) => ['v', 'p', '0', '9'],
+            }
+        }
     }
 
     impl ISerializable for AvcCBoxLike {
         #[inline]
         fn serialize(&mut self) -> Vec<u8> {
-            let mut raw = match self {
-                Self::AvcCBoxLike(data) => data.clone(),
-            };
+            let mut raw = self.record().clone();
 
             // dbg!(raw.len());
 
             let mut size = self.size();
 
             let mut serialized = size.to_be_bytes().to_vec();
-            let mut box_type = ['a', 'v', 'c', 'C'].map(|c| c as u8).to_vec();
+            let mut box_type = self.box_type().map(|c| c as u8).to_vec();
             serialized.append(&mut box_type);
             serialized.append(&mut raw);
             serialized
         }
 
         fn size(&self) -> u32 {
-            match self {
-                Self::AvcCBoxLike(data) => data.len() as u32 + 8,
-            }
+            self.record().len() as u32 + 8
         }
     }
 }
 
+/// The visual sample entry, `avc1`, `hvc1`, `av01` or `vp09` depending on the configuration box.
 #[derive(Debug)]
 pub struct Avc1DescriptionBox {
     pub size: u32,
@@ -2384,7 +2627,7 @@ impl Avc1DescriptionBox {
     pub fn new(width: u16, height: u16, avcc_box: avc1_utils::AvcCBoxLike) -> Self {
         Self {
             size: 0,
-            box_type: ['a', 'v', 'c', '1'],
+            box_type: avcc_box.sample_entry_type(),
             reserved: [0; 6],
             data_reference_index: 1,
             version: 0,
@@ -2594,14 +2837,12 @@ pub struct MovieExtendBox {
 }
 
 impl MovieExtendBox {
-    pub fn new() -> Self {
+    /// One `trex` for each track.
+    pub fn new(track_ids: impl IntoIterator<Item=u32>) -> Self {
         Self {
             size: 8,
             box_type: ['m', 'v', 'e', 'x'],
-            track_extend_boxes: vec![
-                TrackExtendsBox::new(DEFAULT_VIDEO_TRACK_ID),
-                TrackExtendsBox::new(DEFAULT_AUDIO_TRACK_ID),
-            ],
+            track_extend_boxes: track_ids.into_iter().map(TrackExtendsBox::new).collect(),
         }
     }
 }
@@ -1,7 +1,10 @@
-use crate::flv::header::{AudioTagHeader, TagHeader, VideoTagHeader};
+use crate::error::FlvError;
+use crate::flv::header::{AudioPacketType, AudioTagHeader, AvMultitrackType, TagHeader, VideoPacketType, VideoTagHeader};
 use crate::flv::tag::{NormalTagBody, Tag, TagBody};
-use crate::fmpeg::remux_context::TIME_SCALE;
+use crate::fmpeg::mp4head::ex_audio_utils::AudioConfigBoxLike;
+use crate::fmpeg::remux_context::{AudioCodecType, VideoCodecType, TIME_SCALE};
 use crate::io;
+use crate::io::bit::BitReader;
 use std::collections::VecDeque;
 
 #[inline]
@@ -37,19 +40,19 @@ pub fn parse_timescale_signed(timestamp_ms: i32) -> i32 {
 }
 
 #[inline]
-pub fn parse_mp3_timescale(sample_rate: u32, mp3version: Mp3Version) -> u32 {
+pub fn parse_mp3_timescale(sample_rate: u32, mp3version: Mp3Version) -> Result<u32, FlvError> {
     match mp3version {
         Mp3Version::Mp25 => {
-            parse_timescale_accurate(576000.0 / sample_rate as f32)
+            Ok(parse_timescale_accurate(576000.0 / sample_rate as f32))
         }
         Mp3Version::Mp20 => {
-            parse_timescale_accurate(576000.0 / sample_rate as f32)
+            Ok(parse_timescale_accurate(576000.0 / sample_rate as f32))
         }
         Mp3Version::Mp10 => {
-            parse_timescale_accurate(1152000.0 / sample_rate as f32)
+            Ok(parse_timescale_accurate(1152000.0 / sample_rate as f32))
         }
         Mp3Version::Reserved => {
-            panic!("Invalid mp3 version.");
+            Err(FlvError::malformed("Invalid mp3 version."))
         }
     }
 }
@@ -60,8 +63,18 @@ pub fn parse_aac_timescale(sample_rate: u32) -> u32 {
     parse_timescale_accurate((1024.0 * 1000.0) / sample_rate as f32)
 }
 
+/// Duration of `sample_count` samples at `sample_rate`.
+#[inline]
+pub fn parse_samples_timescale(sample_count: u32, sample_rate: u32) -> u32 {
+    parse_timescale_accurate(sample_count as f32 * 1000.0 / sample_rate as f32)
+}
+
+/// Assumed when neither the SPS nor the metadata tell the frame rate.
+const FALLBACK_FRAME_RATE: f32 = 30.0;
+
 #[inline]
 pub fn parse_avc_timescale(fps: f32) -> u32 {
+    let fps = if fps.is_finite() && fps > 0.0 { fps } else { FALLBACK_FRAME_RATE };
     parse_timescale_accurate(1000.0 / fps)
 }
 
@@ -69,10 +82,43 @@ pub enum AudioParseResult {
     AacRaw(VecDeque<u8>),
     AacSequenceHeader(AacSequenceHeader),
     Mp3(Mp3ParseResult),
+    Opus(ExAudioParseResult),
+    Flac(ExAudioParseResult),
+    Ac3(ExAudioParseResult),
+    Eac3(ExAudioParseResult),
+}
+
+/// The content of an enhanced rtmp audio tag, the same for every FourCC codec.
+pub enum ExAudioParseResult {
+    SequenceStart(ExAudioConfig),
+    CodedFrames(ExAudioFrame),
+    SequenceEnd,
+    /// The channel layout, which the sample entry does not need.
+    MultichannelConfig,
+}
+
+/// What the sample entry is built from.
+pub struct ExAudioConfig {
+    pub sample_rate: u32,
+    pub channels: u8,
+    pub config_box: AudioConfigBoxLike,
+}
+
+pub struct ExAudioFrame {
+    /// Duration of the frame in samples, at the rate of the track.
+    pub sample_count: u32,
+    /// ac-3 and e-ac-3 have no sequence start, every frame describes the stream instead.
+    pub config: Option<ExAudioConfig>,
+    pub payload: Vec<u8>,
 }
 
 pub enum VideoParseResult {
     Avc1(Avc1ParseResult),
+    Hvc1(ExVideoParseResult),
+    /// hevc with codec id 12, packed like avc.
+    LegacyHevc(ExVideoParseResult),
+    Av01(ExVideoParseResult),
+    Vp09(ExVideoParseResult),
 }
 
 pub enum Avc1ParseResult {
@@ -81,8 +127,85 @@ pub enum Avc1ParseResult {
     AvcEndOfSequence,
 }
 
+impl AudioParseResult {
+    /// Whether this configures the track, rather than carrying samples.
+    pub fn is_sequence_header(&self) -> bool {
+        match self {
+            AudioParseResult::AacSequenceHeader(_) => true,
+            AudioParseResult::Opus(parsed) | AudioParseResult::Flac(parsed) | AudioParseResult::Ac3(parsed) | AudioParseResult::Eac3(parsed) => {
+                matches!(parsed, ExAudioParseResult::SequenceStart(_))
+            }
+            AudioParseResult::AacRaw(_) | AudioParseResult::Mp3(_) => false,
+        }
+    }
+
+    pub fn codec_type(&self) -> AudioCodecType {
+        match self {
+            AudioParseResult::AacRaw(_) | AudioParseResult::AacSequenceHeader(_) => AudioCodecType::Aac,
+            AudioParseResult::Mp3(_) => AudioCodecType::Mp3,
+            AudioParseResult::Opus(_) => AudioCodecType::Opus,
+            AudioParseResult::Flac(_) => AudioCodecType::Flac,
+            AudioParseResult::Ac3(_) => AudioCodecType::Ac3,
+            AudioParseResult::Eac3(_) => AudioCodecType::Eac3,
+        }
+    }
+}
+
+impl VideoParseResult {
+    /// Whether this configures the track, rather than carrying samples.
+    pub fn is_sequence_header(&self) -> bool {
+        match self {
+            VideoParseResult::Avc1(parsed) => matches!(parsed, Avc1ParseResult::AvcSequenceHeader(_)),
+            VideoParseResult::Hvc1(parsed) | VideoParseResult::LegacyHevc(parsed) | VideoParseResult::Av01(parsed) | VideoParseResult::Vp09(parsed) => {
+                matches!(parsed, ExVideoParseResult::SequenceStart(_))
+            }
+        }
+    }
+
+    pub fn codec_type(&self) -> VideoCodecType {
+        match self {
+            VideoParseResult::Avc1(_) => VideoCodecType::Avc1,
+            VideoParseResult::Hvc1(_) | VideoParseResult::LegacyHevc(_) => VideoCodecType::Hvc1,
+            VideoParseResult::Av01(_) => VideoCodecType::Av01,
+            VideoParseResult::Vp09(_) => VideoCodecType::Vp09,
+        }
+    }
+
+    /// Once the track is configured, frames of every codec are remuxed the same way,
+    /// so map them onto the avc results. Tags without samples give None.
+    pub fn into_avc_like(self) -> Option<Avc1ParseResult> {
+        match self {
+            VideoParseResult::Avc1(parsed) => Some(parsed),
+            VideoParseResult::Hvc1(parsed) | VideoParseResult::LegacyHevc(parsed) | VideoParseResult::Av01(parsed) | VideoParseResult::Vp09(parsed) => match parsed {
+                ExVideoParseResult::SequenceStart(record) => Some(Avc1ParseResult::AvcSequenceHeader(record)),
+                ExVideoParseResult::CodedFrames(frames) => Some(Avc1ParseResult::AvcNalu(frames)),
+                ExVideoParseResult::SequenceEnd => Some(Avc1ParseResult::AvcEndOfSequence),
+                ExVideoParseResult::Mpeg2TsSequenceStart(_) | ExVideoParseResult::Metadata => None,
+            },
+        }
+    }
+}
+
+/// The content of an enhanced rtmp video tag, the same for every FourCC codec.
+pub enum ExVideoParseResult {
+    /// The decoder configuration record, i.e. the body of `hvcC`, `av1C` or `vpcC`.
+    SequenceStart(VecDeque<u8>),
+    /// Samples which go into `mdat` as they are.
+    CodedFrames(AvcNalu),
+    SequenceEnd,
+    /// An av1 descriptor for mpeg2-ts, of no use in mp4.
+    Mpeg2TsSequenceStart(VecDeque<u8>),
+    /// HDR metadata in AMF, ignored for now.
+    Metadata,
+}
+
+/// One track of a multitrack tag: track id, FourCC and track body.
+type TrackSlice = (u8, [u8; 4], VecDeque<u8>);
+
 pub struct AvcNalu {
     pub keyframe_type: KeyframeType,
+    /// pts - dts, in milliseconds.
+    pub composition_time_offset: i32,
     pub payload: VecDeque<u8>,
 }
 
@@ -91,13 +214,15 @@ pub enum KeyframeType {
     Interframe,
 }
 
-impl From<u8> for KeyframeType {
+impl TryFrom<u8> for KeyframeType {
+    type Error = FlvError;
+
     /// for conversion from flv tag only.
-    fn from(value: u8) -> Self {
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
         match value {
-            1 => KeyframeType::Keyframe,
-            2 => KeyframeType::Interframe,
-            _ => panic!("Invalid keyframe type."),
+            1 => Ok(KeyframeType::Keyframe),
+            2 => Ok(KeyframeType::Interframe),
+            _ => Err(FlvError::malformed(format!("Invalid keyframe type {}.", value))),
         }
     }
 }
@@ -114,14 +239,16 @@ pub enum Mp3Version {
     Reserved,
 }
 
-impl From<u8> for Mp3Version {
-    fn from(value: u8) -> Self {
+impl TryFrom<u8> for Mp3Version {
+    type Error = FlvError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
         match value {
-            0 => Mp3Version::Mp25,
-            1 => Mp3Version::Reserved,
-            2 => Mp3Version::Mp20,
-            3 => Mp3Version::Mp10,
-            _ => panic!("Invalid mp3 version."),
+            0 => Ok(Mp3Version::Mp25),
+            1 => Ok(Mp3Version::Reserved),
+            2 => Ok(Mp3Version::Mp20),
+            3 => Ok(Mp3Version::Mp10),
+            _ => Err(FlvError::malformed(format!("Invalid mp3 version {}.", value))),
         }
     }
 }
@@ -133,14 +260,16 @@ pub enum Mp3Layer {
     L3,
 }
 
-impl From<u8> for Mp3Layer {
-    fn from(value: u8) -> Self {
+impl TryFrom<u8> for Mp3Layer {
+    type Error = FlvError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
         match value {
-            0 => Mp3Layer::Reserved,
-            1 => Mp3Layer::L3,
-            2 => Mp3Layer::L2,
-            3 => Mp3Layer::L1,
-            _ => panic!("Invalid mp3 layer."),
+            0 => Ok(Mp3Layer::Reserved),
+            1 => Ok(Mp3Layer::L3),
+            2 => Ok(Mp3Layer::L2),
+            3 => Ok(Mp3Layer::L1),
+            _ => Err(FlvError::malformed(format!("Invalid mp3 layer {}.", value))),
         }
     }
 }
@@ -152,14 +281,16 @@ pub enum Channel {
     JointStereo,
 }
 
-impl From<u8> for Channel {
-    fn from(value: u8) -> Self {
+impl TryFrom<u8> for Channel {
+    type Error = FlvError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
         match value {
-            0 => Channel::Stereo,
-            1 => Channel::JointStereo,
-            2 => Channel::Dual,
-            3 => Channel::Mono,
-            _ => panic!("Invalid channel."),
+            0 => Ok(Channel::Stereo),
+            1 => Ok(Channel::JointStereo),
+            2 => Ok(Channel::Dual),
+            3 => Ok(Channel::Mono),
+            _ => Err(FlvError::malformed(format!("Invalid channel {}.", value))),
         }
     }
 }
@@ -195,24 +326,28 @@ pub struct AacSequenceHeader {
 pub struct Parser;
 
 impl Parser {
-    pub fn parse_audio(tag: &Tag) -> Result<AudioParseResult, Box<dyn std::error::Error>> {
+    pub fn parse_audio(tag: &Tag) -> Result<AudioParseResult, FlvError> {
         let header = match tag.tag_header {
             TagHeader::Audio(ref header) => header,
-            _ => return Err("Tag type mismatch.".into()),
+            _ => return Err(FlvError::internal("Tag type mismatch.")),
         };
 
         let body = match tag.tag_body {
             TagBody::Normal(ref body) =>
                 match body {
                     NormalTagBody::Audio(ref body) => { body }
-                    _ => return Err("Tag body type mismatch.".into()),
+                    _ => return Err(FlvError::internal("Tag body type mismatch.")),
                 },
-            _ => return Err("Encrypted audio is not supported.".into()),
+            _ => return Err(FlvError::unsupported_codec("encrypted audio")),
         };
 
+        if header.is_ex_header() {
+            return Self::parse_ex_audio(header, body);
+        }
+
         // mp3; aac
         if header.sound_format != 2 && header.sound_format != 10 {
-            return Err("Unsupported sound format.".into());
+            return Err(FlvError::unsupported_codec(format!("sound format {}", header.sound_format)));
         }
 
         if header.sound_format == 2 {
@@ -224,7 +359,235 @@ impl Parser {
         }
     }
 
-    fn parse_mp3(header: &AudioTagHeader, body: &VecDeque<u8>) -> Result<AudioParseResult, Box<dyn std::error::Error>> {
+    fn parse_ex_audio(header: &AudioTagHeader, body: &VecDeque<u8>) -> Result<AudioParseResult, FlvError> {
+        let fourcc = header.fourcc.ok_or(FlvError::malformed("Audio FourCC is not set."))?;
+        let packet_type = header.audio_packet_type.ok_or(FlvError::malformed("Audio packet type is not set."))?;
+        let body = Vec::from(body.clone());
+
+        if fourcc == *b".mp3" {
+            return match packet_type {
+                AudioPacketType::CodedFrames => Self::parse_mp3(header, &VecDeque::from(body)),
+                _ => Err(FlvError::unsupported_codec(format!("mp3 audio packet type {:?}", packet_type))),
+            };
+        }
+
+        let parsed = match (packet_type, &fourcc) {
+            (AudioPacketType::SequenceStart, b"Opus") => ExAudioParseResult::SequenceStart(Self::parse_opus_head(&body)?),
+            (AudioPacketType::SequenceStart, b"fLaC") => ExAudioParseResult::SequenceStart(Self::parse_flac_config(&body)?),
+            (AudioPacketType::CodedFrames, b"Opus") => ExAudioParseResult::CodedFrames(ExAudioFrame {
+                sample_count: Self::opus_sample_count(&body)?,
+                config: None,
+                payload: body,
+            }),
+            (AudioPacketType::CodedFrames, b"fLaC") => ExAudioParseResult::CodedFrames(ExAudioFrame {
+                sample_count: Self::flac_sample_count(&body)?,
+                config: None,
+                payload: body,
+            }),
+            (AudioPacketType::CodedFrames, b"ac-3") => {
+                let (config, sample_count) = Self::parse_ac3_frame(&body)?;
+                ExAudioParseResult::CodedFrames(ExAudioFrame { sample_count, config: Some(config), payload: body })
+            }
+            (AudioPacketType::CodedFrames, b"ec-3") => {
+                let (config, sample_count) = Self::parse_eac3_frame(&body)?;
+                ExAudioParseResult::CodedFrames(ExAudioFrame { sample_count, config: Some(config), payload: body })
+            }
+            // ac-3 and e-ac-3 are configured from their frames.
+            (AudioPacketType::SequenceStart, b"ac-3" | b"ec-3") => ExAudioParseResult::MultichannelConfig,
+            (AudioPacketType::SequenceEnd, _) => ExAudioParseResult::SequenceEnd,
+            (AudioPacketType::MultichannelConfig, _) => ExAudioParseResult::MultichannelConfig,
+            _ => return Err(FlvError::unsupported_codec(format!("audio fourcc {}", String::from_utf8_lossy(&fourcc)))),
+        };
+
+        Ok(match &fourcc {
+            b"Opus" => AudioParseResult::Opus(parsed),
+            b"fLaC" => AudioParseResult::Flac(parsed),
+            b"ac-3" => AudioParseResult::Ac3(parsed),
+            _ => AudioParseResult::Eac3(parsed),
+        })
+    }
+
+    /// Turn an OpusHead into the body of `dOps`, which is the same data in big-endian.
+    fn parse_opus_head(body: &[u8]) -> Result<ExAudioConfig, FlvError> {
+        if body.len() < 19 || &body[0..8] != b"OpusHead" {
+            return Err(FlvError::malformed("Opus sequence start is not an OpusHead."));
+        }
+        let channels = body[9];
+        let mapping_family = body[18];
+        // streams, coupled streams and one entry per channel.
+        let mapping_size = if mapping_family == 0 { 0 } else { 2 + channels as usize };
+        if body.len() < 19 + mapping_size {
+            return Err(FlvError::malformed("OpusHead channel mapping is truncated."));
+        }
+
+        let mut dops = vec![0, channels];
+        dops.extend_from_slice(&u16::from_le_bytes([body[10], body[11]]).to_be_bytes());
+        dops.extend_from_slice(&u32::from_le_bytes([body[12], body[13], body[14], body[15]]).to_be_bytes());
+        dops.extend_from_slice(&i16::from_le_bytes([body[16], body[17]]).to_be_bytes());
+        dops.push(mapping_family);
+        dops.extend_from_slice(&body[19..19 + mapping_size]);
+
+        // opus is always decoded at 48kHz, the input rate is informational only.
+        Ok(ExAudioConfig { sample_rate: 48000, channels, config_box: AudioConfigBoxLike::DOpsBoxLike(dops) })
+    }
+
+    /// Number of 48kHz samples in an opus packet, from its TOC byte.
+    fn opus_sample_count(packet: &[u8]) -> Result<u32, FlvError> {
+        let toc = *packet.first().ok_or(FlvError::malformed("Opus packet is empty."))?;
+        let config = toc >> 3;
+        let frame_size = match config {
+            // silk: 10, 20, 40, 60 ms.
+            0..=11 => [480, 960, 1920, 2880][(config % 4) as usize],
+            // hybrid: 10, 20 ms.
+            12..=15 => [480, 960][(config % 2) as usize],
+            // celt: 2.5, 5, 10, 20 ms.
+            _ => [120, 240, 480, 960][(config % 4) as usize],
+        };
+        let frame_count = match toc & 0x03 {
+            0 => 1,
+            1 | 2 => 2,
+            _ => (*packet.get(1).ok_or(FlvError::malformed("Opus frame count is missing."))? & 0x3F) as u32,
+        };
+        Ok(frame_size * frame_count)
+    }
+
+    /// Build the body of `dfLa` from the FLAC metadata blocks, with or without the `fLaC` marker.
+    /// A bare STREAMINFO is accepted as well.
+    fn parse_flac_config(body: &[u8]) -> Result<ExAudioConfig, FlvError> {
+        let blocks = body.strip_prefix(b"fLaC").unwrap_or(body);
+        // version and flags of the full box.
+        let mut dfla = vec![0, 0, 0, 0];
+        let streaminfo = if blocks.len() == 34 {
+            // the last-metadata-block flag and type 0, STREAMINFO.
+            dfla.extend_from_slice(&[0x80, 0, 0, 34]);
+            blocks
+        } else if blocks.len() >= 38 && blocks[0] & 0x7F == 0 {
+            &blocks[4..38]
+        } else {
+            return Err(FlvError::malformed("FLAC sequence start does not begin with STREAMINFO."));
+        };
+        dfla.extend_from_slice(blocks);
+
+        let sample_rate = ((streaminfo[10] as u32) << 12) | ((streaminfo[11] as u32) << 4) | (streaminfo[12] as u32 >> 4);
+        let channels = ((streaminfo[12] >> 1) & 0x07) + 1;
+        if sample_rate == 0 {
+            return Err(FlvError::malformed("FLAC sample rate is 0."));
+        }
+        Ok(ExAudioConfig { sample_rate, channels, config_box: AudioConfigBoxLike::DfLaBoxLike(dfla) })
+    }
+
+    /// Number of samples in a FLAC frame, from the block size in its header.
+    fn flac_sample_count(frame: &[u8]) -> Result<u32, FlvError> {
+        if frame.len() < 5 || frame[0] != 0xFF || frame[1] & 0xFE != 0xF8 {
+            return Err(FlvError::malformed("FLAC frame sync code mismatch!"));
+        }
+        let block_size_code = frame[2] >> 4;
+        match block_size_code {
+            1 => Ok(192),
+            2..=5 => Ok(576 << (block_size_code - 2)),
+            8..=15 => Ok(256 << (block_size_code - 8)),
+            6 | 7 => {
+                // the block size follows the frame or sample number, which is utf-8 coded.
+                let number_size = match frame[4].leading_ones() {
+                    0 => 1,
+                    ones => ones as usize,
+                };
+                let at = 4 + number_size;
+                let stored = if block_size_code == 6 {
+                    frame.get(at).map(|size| *size as u32)
+                } else {
+                    frame.get(at..at + 2).map(|size| u16::from_be_bytes([size[0], size[1]]) as u32)
+                };
+                stored.map(|size| size + 1).ok_or(FlvError::malformed("FLAC frame header is truncated."))
+            }
+            _ => Err(FlvError::malformed(format!("Reserved FLAC block size code {}.", block_size_code))),
+        }
+    }
+
+    const AC3_SAMPLE_RATES: [u32; 3] = [48000, 44100, 32000];
+    const AC3_CHANNELS: [u8; 8] = [2, 1, 2, 3, 3, 4, 4, 5];
+
+    /// Build `dac3` from the header of an ac-3 syncframe, which always holds 1536 samples.
+    fn parse_ac3_frame(frame: &[u8]) -> Result<(ExAudioConfig, u32), FlvError> {
+        if frame.len() < 2 || frame[0..2] != [0x0B, 0x77] {
+            return Err(FlvError::malformed("AC-3 sync word mismatch!"));
+        }
+        // crc1 comes between the sync word and the fields below.
+        let header = frame.get(4..).ok_or(FlvError::malformed("AC-3 syncframe header is truncated."))?;
+        let mut bits = BitReader::new(header);
+        let fscod = bits.read_bits(2)?;
+        let frmsizecod = bits.read_bits(6)?;
+        let bsid = bits.read_bits(5)?;
+        let bsmod = bits.read_bits(3)?;
+        let acmod = bits.read_bits(3)?;
+        if acmod & 0x01 != 0 && acmod != 1 {
+            bits.skip_bits(2)?; // cmixlev
+        }
+        if acmod & 0x04 != 0 {
+            bits.skip_bits(2)?; // surmixlev
+        }
+        if acmod == 2 {
+            bits.skip_bits(2)?; // dsurmod
+        }
+        let lfeon = bits.read_bits(1)?;
+
+        let sample_rate = *Self::AC3_SAMPLE_RATES.get(fscod as usize).ok_or(FlvError::malformed("Reserved AC-3 sample rate code."))?;
+        let dac3 = (fscod << 22) | (bsid << 17) | (bsmod << 14) | (acmod << 11) | (lfeon << 10) | ((frmsizecod >> 1) << 5);
+        Ok((
+            ExAudioConfig {
+                sample_rate,
+                channels: Self::AC3_CHANNELS[acmod as usize] + lfeon as u8,
+                config_box: AudioConfigBoxLike::Dac3BoxLike(dac3.to_be_bytes()[1..].to_vec()),
+            },
+            1536,
+        ))
+    }
+
+    /// Build `dec3` from the header of an e-ac-3 syncframe, assuming a single independent substream.
+    fn parse_eac3_frame(frame: &[u8]) -> Result<(ExAudioConfig, u32), FlvError> {
+        if frame.len() < 2 || frame[0..2] != [0x0B, 0x77] {
+            return Err(FlvError::malformed("E-AC-3 sync word mismatch!"));
+        }
+        let mut bits = BitReader::new(&frame[2..]);
+        bits.skip_bits(2 + 3)?; // strmtyp, substreamid
+        let frmsiz = bits.read_bits(11)?;
+        let fscod = bits.read_bits(2)?;
+        let (sample_rate, block_count) = if fscod == 3 {
+            // reduced sample rates always have 6 blocks.
+            let fscod2 = bits.read_bits(2)?;
+            let rate = *[24000, 22050, 16000].get(fscod2 as usize).ok_or(FlvError::malformed("Reserved E-AC-3 sample rate code."))?;
+            (rate, 6)
+        } else {
+            (Self::AC3_SAMPLE_RATES[fscod as usize], [1, 2, 3, 6][bits.read_bits(2)? as usize])
+        };
+        let acmod = bits.read_bits(3)?;
+        let lfeon = bits.read_bits(1)?;
+        let bsid = bits.read_bits(5)?;
+
+        let sample_count = block_count * 256;
+        let frame_bytes = (frmsiz + 1) * 2;
+        let data_rate = frame_bytes as u64 * 8 * sample_rate as u64 / sample_count as u64 / 1000;
+        // data_rate(13) num_ind_sub(3), then fscod(2) bsid(5) reserved(1) asvc(1) bsmod(3) acmod(3) lfeon(1) reserved(3) num_dep_sub(4) reserved(1).
+        let dec3 = (data_rate.min(0x1FFF) << 27)
+            | ((fscod as u64) << 22)
+            | ((bsid as u64) << 17)
+            | ((acmod as u64) << 9)
+            | ((lfeon as u64) << 8);
+        Ok((
+            ExAudioConfig {
+                sample_rate,
+                channels: Self::AC3_CHANNELS[acmod as usize] + lfeon as u8,
+                config_box: AudioConfigBoxLike::Dec3BoxLike(dec3.to_be_bytes()[3..].to_vec()),
+            },
+            sample_count,
+        ))
+    }
+
+    fn parse_mp3(header: &AudioTagHeader, body: &VecDeque<u8>) -> Result<AudioParseResult, FlvError> {
+        if body.len() < 4 {
+            return Err(FlvError::malformed("MP3 frame header is truncated."));
+        }
+
         let mut u16io = io::bit::U16BitIO::new(
             <u16>::from_be_bytes(
                 [
@@ -239,11 +602,11 @@ impl Parser {
         let sync_word = u16io.read_range(0, 10);
         if sync_word != MP3_SYNC_WORD {
             // dbg!(sync_word);
-            return Err("MP3 sync word mismatch!".into());
+            return Err(FlvError::malformed("MP3 sync word mismatch!"));
         }
 
-        let version = Mp3Version::from(u16io.read_range(11, 12) as u8);
-        let layer = Mp3Layer::from(u16io.read_range(13, 14) as u8);
+        let version = Mp3Version::try_from(u16io.read_range(11, 12) as u8)?;
+        let layer = Mp3Layer::try_from(u16io.read_range(13, 14) as u8)?;
         let protection_bit = u16io.read_at(15);
 
         let mut u16io = io::bit::U16BitIO::new(
@@ -265,18 +628,21 @@ impl Parser {
             Mp3Version::Mp25 => AUDIO_SAMPLE_RATE_TABLE_M25[sampling_rate_index as usize],
             Mp3Version::Mp20 => AUDIO_SAMPLE_RATE_TABLE_M20[sampling_rate_index as usize],
             Mp3Version::Mp10 => AUDIO_SAMPLE_RATE_TABLE_M10[sampling_rate_index as usize],
-            _ => panic!("Invalid mp3 version."),
+            _ => return Err(FlvError::malformed("Invalid mp3 version.")),
         };
+        if sample_rate == 0 {
+            return Err(FlvError::malformed("Invalid mp3 sampling rate index."));
+        }
 
         let bitrate = match layer {
             Mp3Layer::L1 => AUDIO_BITRATE_TABLE_L1[bitrate_index as usize],
             Mp3Layer::L2 => AUDIO_BITRATE_TABLE_L2[bitrate_index as usize],
             Mp3Layer::L3 => AUDIO_BITRATE_TABLE_L3[bitrate_index as usize],
-            _ => panic!("Invalid mp3 layer."),
+            _ => return Err(FlvError::malformed("Invalid mp3 layer.")),
         };
         // todo: is this okay?
 
-        let channel = Channel::from(channel_mode as u8);
+        let channel = Channel::try_from(channel_mode as u8)?;
         let channel_extended: u8;
         if let Channel::JointStereo = channel {
             channel_extended = u16io.read_range(10, 11) as u8;
@@ -295,19 +661,23 @@ impl Parser {
         }))
     }
 
-    fn parse_aac(header: &AudioTagHeader, body: &VecDeque<u8>) -> Result<AudioParseResult, Box<dyn std::error::Error>> {
+    fn parse_aac(header: &AudioTagHeader, body: &VecDeque<u8>) -> Result<AudioParseResult, FlvError> {
         if let Some(aac_pack_type) = header.aac_packet_type {
             match aac_pack_type {
                 0 => Self::parse_aac_seq_hdr(body),
                 1 => Self::parse_aac_raw(body),
-                _ => Err("Unsupported AAC packet type.".into()),
+                _ => Err(FlvError::malformed(format!("Unsupported AAC packet type {}.", aac_pack_type))),
             }
         } else {
-            Err("AAC packet type is not set.".into())
+            Err(FlvError::malformed("AAC packet type is not set."))
         }
     }
 
-    fn parse_aac_seq_hdr(body: &VecDeque<u8>) -> Result<AudioParseResult, Box<dyn std::error::Error>> {
+    fn parse_aac_seq_hdr(body: &VecDeque<u8>) -> Result<AudioParseResult, FlvError> {
+        if body.len() < 2 {
+            return Err(FlvError::malformed("AAC sequence header is truncated."));
+        }
+
         let mut u16io = io::bit::U16BitIO::new(
             <u16>::from_be_bytes(
                 [
@@ -330,50 +700,91 @@ impl Parser {
         }))
     }
 
-    fn parse_aac_raw(body: &VecDeque<u8>) -> Result<AudioParseResult, Box<dyn std::error::Error>> {
+    fn parse_aac_raw(body: &VecDeque<u8>) -> Result<AudioParseResult, FlvError> {
         Ok(AudioParseResult::AacRaw(body.clone()))
     }
 
-    pub fn parse_video(tag: &Tag) -> Result<VideoParseResult, Box<dyn std::error::Error>> {
+    pub fn parse_video(tag: &Tag) -> Result<VideoParseResult, FlvError> {
         let header = match tag.tag_header {
             TagHeader::Video(ref header) => header,
-            _ => return Err("Tag type mismatch.".into()),
+            _ => return Err(FlvError::internal("Tag type mismatch.")),
         };
 
         let body = match tag.tag_body {
             TagBody::Normal(ref body) => {
                 match body {
                     NormalTagBody::Video(body) => body,
-                    _ => return Err("Tag body type mismatch.".into()),
+                    _ => return Err(FlvError::internal("Tag body type mismatch.")),
                 }
             }
-            _ => return Err("Encrypted video is not supported.".into()),
+            _ => return Err(FlvError::unsupported_codec("encrypted video")),
         };
 
-        if header.codec_id == 7 {
+        if header.is_ex_header {
+            Self::parse_ex_video(header, body)
+        } else if header.codec_id == 7 {
             // h264 avc
             Self::parse_avc(header, body)
+        } else if header.codec_id == 12 {
+            // h265 hevc, not in the flv specification
+            Self::parse_legacy_hevc(header, body)
         } else {
-            Err("Unsupported video codec.".into())
+            Err(FlvError::unsupported_codec(format!("video codec id {}", header.codec_id)))
         }
     }
 
-    fn parse_avc(header: &VideoTagHeader, body: &VecDeque<u8>) -> Result<VideoParseResult, Box<dyn std::error::Error>> {
+    fn parse_ex_video(header: &VideoTagHeader, body: &VecDeque<u8>) -> Result<VideoParseResult, FlvError> {
+        let fourcc = header.fourcc.ok_or(FlvError::malformed("Video FourCC is not set."))?;
+        let wrap = match &fourcc {
+            b"hvc1" => VideoParseResult::Hvc1,
+            b"av01" => VideoParseResult::Av01,
+            b"vp09" => VideoParseResult::Vp09,
+            _ => return Err(FlvError::unsupported_codec(format!("video fourcc {}", String::from_utf8_lossy(&fourcc)))),
+        };
+
+        let parsed = match header.video_packet_type.ok_or(FlvError::malformed("Video packet type is not set."))? {
+            VideoPacketType::SequenceStart => ExVideoParseResult::SequenceStart(body.clone()),
+            VideoPacketType::CodedFrames | VideoPacketType::CodedFramesX => ExVideoParseResult::CodedFrames(AvcNalu {
+                keyframe_type: KeyframeType::try_from(header.frame_type)?,
+                composition_time_offset: header.composition_time_offset.unwrap_or(0),
+                payload: body.clone(),
+            }),
+            VideoPacketType::SequenceEnd => ExVideoParseResult::SequenceEnd,
+            VideoPacketType::Metadata => ExVideoParseResult::Metadata,
+            VideoPacketType::Mpeg2TsSequenceStart => ExVideoParseResult::Mpeg2TsSequenceStart(body.clone()),
+            VideoPacketType::Multitrack => return Err(FlvError::internal("Multitrack video tag parsed as a single track.")),
+        };
+        Ok(wrap(parsed))
+    }
+
+    fn parse_avc(header: &VideoTagHeader, body: &VecDeque<u8>) -> Result<VideoParseResult, FlvError> {
         match header.avc_packet_type {
-            None => Err("AVC packet type is not set.".into()),
+            None => Err(FlvError::malformed("AVC packet type is not set.")),
             Some(pack_type) => {
                 match pack_type {
                     // todo: use something instead of cloning.
                     0 => Ok(VideoParseResult::Avc1(Avc1ParseResult::AvcSequenceHeader(body.clone()))),
                     1 => Ok(VideoParseResult::Avc1(Avc1ParseResult::AvcNalu(Self::parse_avc_nalu(header, body.clone())?))),
                     2 => Ok(VideoParseResult::Avc1(Avc1ParseResult::AvcEndOfSequence)),
-                    _ => Err("Unsupported AVC packet type.".into()),
+                    _ => Err(FlvError::malformed(format!("Unsupported AVC packet type {}.", pack_type))),
                 }
             }
         }
     }
 
-    fn parse_avc_nalu(header: &VideoTagHeader, mut payload: VecDeque<u8>) -> Result<AvcNalu, Box<dyn std::error::Error>> {
+    /// The packet types are those of avc, the sequence header is an HEVCDecoderConfigurationRecord.
+    fn parse_legacy_hevc(header: &VideoTagHeader, body: &VecDeque<u8>) -> Result<VideoParseResult, FlvError> {
+        let parsed = match header.avc_packet_type {
+            None => return Err(FlvError::malformed("HEVC packet type is not set.")),
+            Some(0) => ExVideoParseResult::SequenceStart(body.clone()),
+            Some(1) => ExVideoParseResult::CodedFrames(Self::parse_avc_nalu(header, body.clone())?),
+            Some(2) => ExVideoParseResult::SequenceEnd,
+            Some(pack_type) => return Err(FlvError::malformed(format!("Unsupported HEVC packet type {}.", pack_type))),
+        };
+        Ok(VideoParseResult::LegacyHevc(parsed))
+    }
+
+    fn parse_avc_nalu(header: &VideoTagHeader, mut payload: VecDeque<u8>) -> Result<AvcNalu, FlvError> {
         // todo: [IMPORTANT] this is a simplified solution and requires further optimization.
         // although codec of most modern browsers can identify an fix the mismatch between the header and the actual data,
         // and simply use flv header to determine the keyframe type will not cause obvious problems,
@@ -382,11 +793,12 @@ impl Parser {
         // use flv header as a reference only.
 
         let size = payload.len() as u32;
-        let nalu_type = KeyframeType::from(header.frame_type);
+        let nalu_type = KeyframeType::try_from(header.frame_type)?;
 
-        if size != 0x00000001 { // start code not present
+        if size != 0x00000001 || payload.len() < 4 { // start code not present
             Ok(AvcNalu {
                 keyframe_type: nalu_type,
+                composition_time_offset: header.composition_time_offset.unwrap_or(0),
                 payload,
             })
         } else { // start code present
@@ -402,8 +814,94 @@ impl Parser {
 
             Ok(AvcNalu {
                 keyframe_type: nalu_type,
+                composition_time_offset: header.composition_time_offset.unwrap_or(0),
                 payload,
             })
         }
     }
+
+    /// Split the body of a multitrack tag into its tracks.
+    fn split_tracks(multitrack_type: AvMultitrackType, fourcc: Option<[u8; 4]>, body: &VecDeque<u8>) -> Result<Vec<TrackSlice>, FlvError> {
+        let body = Vec::from(body.clone());
+        let truncated = || FlvError::malformed("Multitrack tag is truncated.");
+
+        let mut tracks = vec![];
+        let mut offset = 0;
+        while offset < body.len() {
+            let fourcc = match fourcc {
+                Some(fourcc) => fourcc,
+                None => {
+                    let fourcc = body.get(offset..offset + 4).ok_or_else(truncated)?;
+                    offset += 4;
+                    [fourcc[0], fourcc[1], fourcc[2], fourcc[3]]
+                }
+            };
+            let track_id = *body.get(offset).ok_or_else(truncated)?;
+            offset += 1;
+
+            let size = if multitrack_type == AvMultitrackType::OneTrack {
+                body.len() - offset
+            } else {
+                let size = body.get(offset..offset + 3).ok_or_else(truncated)?;
+                offset += 3;
+                u32::from_be_bytes([0, size[0], size[1], size[2]]) as usize
+            };
+            let track_body = body.get(offset..offset + size).ok_or_else(truncated)?;
+            offset += size;
+            tracks.push((track_id, fourcc, VecDeque::from(track_body.to_vec())));
+        }
+        Ok(tracks)
+    }
+
+    /// Like `parse_audio`, but a multitrack tag gives one result per track.
+    /// Tags without tracks belong to track 0.
+    pub fn parse_audio_tracks(tag: &Tag) -> Result<Vec<(u8, AudioParseResult)>, FlvError> {
+        let (header, body) = match (&tag.tag_header, &tag.tag_body) {
+            (TagHeader::Audio(header), TagBody::Normal(NormalTagBody::Audio(body))) => (header, body),
+            _ => return Ok(vec![(0, Self::parse_audio(tag)?)]),
+        };
+        let Some(multitrack_type) = header.multitrack_type else {
+            return Ok(vec![(0, Self::parse_audio(tag)?)]);
+        };
+
+        Self::split_tracks(multitrack_type, header.fourcc, body)?
+            .into_iter()
+            .map(|(track_id, fourcc, body)| {
+                let mut header = header.clone();
+                header.multitrack_type = None;
+                header.fourcc = Some(fourcc);
+                Ok((track_id, Self::parse_ex_audio(&header, &body)?))
+            })
+            .collect()
+    }
+
+    /// Like `parse_video`, but a multitrack tag gives one result per track.
+    /// Tags without tracks belong to track 0.
+    pub fn parse_video_tracks(tag: &Tag) -> Result<Vec<(u8, VideoParseResult)>, FlvError> {
+        let (header, body) = match (&tag.tag_header, &tag.tag_body) {
+            (TagHeader::Video(header), TagBody::Normal(NormalTagBody::Video(body))) => (header, body),
+            _ => return Ok(vec![(0, Self::parse_video(tag)?)]),
+        };
+        let Some(multitrack_type) = header.multitrack_type else {
+            return Ok(vec![(0, Self::parse_video(tag)?)]);
+        };
+
+        Self::split_tracks(multitrack_type, header.fourcc, body)?
+            .into_iter()
+            .map(|(track_id, fourcc, mut body)| {
+                let mut header = header.clone();
+                header.multitrack_type = None;
+                header.fourcc = Some(fourcc);
+                // SI24, the same as in the header of a single track tag.
+                if header.video_packet_type == Some(VideoPacketType::CodedFrames) && fourcc == *b"hvc1" {
+                    if body.len() < 3 {
+                        return Err(FlvError::malformed("Multitrack tag is truncated."));
+                    }
+                    let cts = body.drain(..3).fold(0u32, |cts, byte| (cts << 8) | byte as u32);
+                    header.composition_time_offset = Some(((cts << 8) as i32) >> 8);
+                }
+                Ok((track_id, Self::parse_ex_video(&header, &body)?))
+            })
+            .collect()
+    }
 }
\ No newline at end of file
@@ -1,9 +1,14 @@
+use crate::error::FlvError;
 use crate::exchange::{AudioCodecConfig, VideoCodecConfig};
 use crate::flv::header::FlvHeader;
-use crate::flv::meta::RawMetaData;
+use crate::flv::meta::{MetaData, RawMetaData};
 use crate::fmpeg::mp4head::avc1_utils::AvcCBoxLike;
-use crate::fmpeg::parser::{AudioParseResult, Avc1ParseResult, Channel, VideoParseResult};
+use crate::fmpeg::mp4head::ex_audio_utils::AudioConfigBoxLike;
+use crate::fmpeg::parser::{AudioParseResult, Avc1ParseResult, Channel, ExAudioFrame, ExAudioParseResult, ExVideoParseResult, VideoParseResult};
+use crate::fmpeg::sps::SequenceParameterSet;
+use std::collections::{BTreeMap, VecDeque};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TrackType {
     Audio,
     Video,
@@ -14,6 +19,9 @@ pub struct TrackContext {
     pub sequence_number: u32,
 
     pub track_type: TrackType,
+
+    // samples held back until the next one gives their duration.
+    pub sequence_buffer: VecDeque<VideoSequenceBufferEntry>,
 }
 
 impl TrackContext {
@@ -22,6 +30,7 @@ impl TrackContext {
             track_id,
             sequence_number: 1,
             track_type,
+            sequence_buffer: VecDeque::new(),
         }
     }
 }
@@ -150,35 +159,69 @@ pub const TIME_SCALE: u32 = 30000;
 // Using 1000 is not accurate enough, and will lead to audio/video sync issue (e.g. flaws, time mismatch, etc.)
 // 30000 is big enough and will not cause overflow.
 
+/// Media tags looked at before a track which the flv header announces, but which does not show up, is given up on.
+const DEFAULT_PROBE_TAGS: u32 = 64;
+
+/// Where the flv header or the metadata disagree with the tags which actually arrive.
+/// The tags win, these are only reported.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProbeWarning {
+    /// The flv header announces the track type, but none of the probed tags is of it.
+    MissingTrack(TrackType),
+    /// Tags of a track type which the flv header does not announce.
+    UnannouncedTrack(TrackType),
+    /// The metadata declares another codec than track 0 has,
+    /// or frames of a configured track are of another codec, without a sequence header in front of them.
+    AudioCodecMismatch { declared: AudioCodecType, found: AudioCodecType },
+    VideoCodecMismatch { declared: VideoCodecType, found: VideoCodecType },
+}
+
 pub struct RemuxContext {
     pub fps: f64,
     pub fps_num: u32,
 
     pub duration_ms: u32,
 
+    // from the SPS of video track 0, the metadata is only a fallback.
     pub width: f64,
     pub height: f64,
+    pub video_profile_idc: u8,
+    pub video_level_idc: u8,
+    size_from_sps: bool,
+    frame_rate_from_sps: bool,
 
+    // from the flags of the flv header, then corrected by the probed tags.
     pub has_audio: bool,
     pub has_video: bool,
 
+    probe_limit: u32,
+    probed_tags: u32,
+    audio_seen: bool,
+    video_seen: bool,
+    warnings: Vec<ProbeWarning>,
+
+    // a disabled track is left out, even if the stream has it.
+    audio_enabled: bool,
+    video_enabled: bool,
+
+    // as declared in the metadata, which describes track 0.
     pub audio_codec_id: u8,
     pub audio_codec_type: AudioCodecType,
     pub audio_data_rate: u32,
 
     // --- must be initialized using audio tag data ---
-    pub audio_sample_rate: u32,
-    pub audio_channels: u8,
-    pub audio_channels_extended: u8,
-    pub audio_aac_info: Vec<u8>,
+    // keyed by the flv track id, 0 unless the stream is multitrack.
+    pub audio_tracks: BTreeMap<u8, AudioTrackConfig>,
     // ------------------------------------------------
 
+    // as declared in the metadata, which describes track 0.
     pub video_codec_id: u8,
     pub video_codec_type: VideoCodecType,
+    pub video_data_rate: u32,
 
     // --- must be initialized using video tag data ---
-    pub video_data_rate: u32,
-    pub video_avcc_info: AvcCBoxLike,
+    // keyed by the flv track id, 0 unless the stream is multitrack.
+    pub video_tracks: BTreeMap<u8, VideoTrackConfig>,
     // ------------------------------------------------
 
     pub major_brand: String,
@@ -188,14 +231,55 @@ pub struct RemuxContext {
     header_sent: bool,
     flv_header_configured: bool,
     metadata_configured: bool,
-    video_metadata_configured: bool,
-    audio_metadata_configured: bool,
 
     pub(crate) sequence_number: u32,
 }
 
+/// The sample entry of one audio track.
+pub struct AudioTrackConfig {
+    pub codec_type: AudioCodecType,
+    pub sample_rate: u32,
+    pub channels: u8,
+    pub channels_extended: u8,
+    pub aac_info: Vec<u8>,
+    // for the enhanced rtmp codecs
+    pub ex_config: Option<AudioConfigBoxLike>,
+}
+
+impl AudioTrackConfig {
+    pub fn new(codec_type: AudioCodecType, sample_rate: u32, channels: u8) -> Self {
+        Self {
+            codec_type,
+            sample_rate,
+            channels,
+            channels_extended: 0,
+            aac_info: vec![],
+            ex_config: None,
+        }
+    }
+}
+
+/// The sample entry of one video track.
+pub struct VideoTrackConfig {
+    pub codec_type: VideoCodecType,
+    pub avcc_info: AvcCBoxLike,
+    /// The picture size from the SPS of this track, None if the codec has none to read.
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+impl VideoTrackConfig {
+    pub fn new(codec_type: VideoCodecType, avcc_info: AvcCBoxLike) -> Self {
+        Self { codec_type, avcc_info, width: None, height: None }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum VideoCodecType {
     Avc1,
+    Hvc1,
+    Av01,
+    Vp09,
     None,
 }
 
@@ -203,14 +287,33 @@ impl From<u8> for VideoCodecType {
     fn from(value: u8) -> Self {
         match value {
             7 => VideoCodecType::Avc1,
+            12 => VideoCodecType::Hvc1,
+            _ => VideoCodecType::None
+        }
+    }
+}
+
+impl VideoCodecType {
+    /// For enhanced rtmp, where the codec is identified by a FourCC.
+    pub fn from_fourcc(fourcc: &[u8; 4]) -> Self {
+        match fourcc {
+            b"avc1" => VideoCodecType::Avc1,
+            b"hvc1" => VideoCodecType::Hvc1,
+            b"av01" => VideoCodecType::Av01,
+            b"vp09" => VideoCodecType::Vp09,
             _ => VideoCodecType::None
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AudioCodecType {
     Aac,
     Mp3,
+    Opus,
+    Flac,
+    Ac3,
+    Eac3,
     None,
 }
 
@@ -224,6 +327,21 @@ impl From<u8> for AudioCodecType {
     }
 }
 
+impl AudioCodecType {
+    /// For enhanced rtmp, where the codec is identified by a FourCC.
+    pub fn from_fourcc(fourcc: &[u8; 4]) -> Self {
+        match fourcc {
+            b".mp3" => AudioCodecType::Mp3,
+            b"mp4a" => AudioCodecType::Aac,
+            b"Opus" => AudioCodecType::Opus,
+            b"fLaC" => AudioCodecType::Flac,
+            b"ac-3" => AudioCodecType::Ac3,
+            b"ec-3" => AudioCodecType::Eac3,
+            _ => AudioCodecType::None
+        }
+    }
+}
+
 impl RemuxContext {
     pub fn new() -> Self {
         Self {
@@ -233,24 +351,34 @@ impl RemuxContext {
 
             width: 0.0,
             height: 0.0,
+            video_profile_idc: 0,
+            video_level_idc: 0,
+            size_from_sps: false,
+            frame_rate_from_sps: false,
 
             has_audio: false,
             has_video: false,
 
+            probe_limit: DEFAULT_PROBE_TAGS,
+            probed_tags: 0,
+            audio_seen: false,
+            video_seen: false,
+            warnings: vec![],
+
+            audio_enabled: true,
+            video_enabled: true,
+
             audio_codec_id: 0,
             audio_data_rate: 0,
-            audio_sample_rate: 0,
-            audio_channels: 0,
-            audio_channels_extended: 0,
-            audio_aac_info: vec![],
+            audio_tracks: BTreeMap::new(),
 
             video_codec_id: 0,
             video_data_rate: 0,
-            video_avcc_info: AvcCBoxLike::AvcCBoxLike(vec![]),
+            video_tracks: BTreeMap::new(),
 
             major_brand: String::from("isom"),
             minor_version: String::from("512"),
-            compatible_brands: vec![],
+            compatible_brands: Self::DEFAULT_COMPATIBLE_BRANDS.iter().map(|brand| brand.to_string()).collect(),
 
             video_codec_type: VideoCodecType::None,
             audio_codec_type: AudioCodecType::None,
@@ -258,8 +386,6 @@ impl RemuxContext {
             header_sent: false,
             flv_header_configured: false,
             metadata_configured: false,
-            video_metadata_configured: false,
-            audio_metadata_configured: false,
 
             sequence_number: 1,
         }
@@ -271,99 +397,108 @@ impl RemuxContext {
         self.flv_header_configured = true;
     }
 
+    /// Take the hints of onMetaData. An invalid property gives no hint, the others still do.
     pub fn parse_metadata(&mut self, metadata: &RawMetaData) {
-        if let Some(duration) = metadata.try_get_number("duration") {
+        let metadata = MetaData::from(metadata);
+
+        if let Some(duration) = metadata.duration {
             self.duration_ms = (duration * TIME_SCALE as f64) as u32;
         }
 
-        if let Some(width) = metadata.try_get_number("width") {
-            self.width = width;
+        if let Some(width) = metadata.width.filter(|_| !self.size_from_sps) {
+            self.width = width as f64;
         }
 
-        if let Some(height) = metadata.try_get_number("height") {
-            self.height = height;
+        if let Some(height) = metadata.height.filter(|_| !self.size_from_sps) {
+            self.height = height as f64;
         }
 
-        if let Some(frame_rate) = metadata.try_get_number("framerate") {
+        if let Some(frame_rate) = metadata.frame_rate.filter(|_| !self.frame_rate_from_sps) {
             self.fps = frame_rate;
             self.fps_num = (frame_rate * TIME_SCALE as f64) as u32;
         }
 
-        if let Some(audio_codec_id) = metadata.try_get_number("audiocodecid") {
-            self.audio_codec_id = audio_codec_id as u8;
-            self.audio_codec_type = AudioCodecType::from(self.audio_codec_id);
+        if let Some(audio_codec_id) = metadata.audio_codec_id {
+            if audio_codec_id > u8::MAX as u32 {
+                // enhanced rtmp writes the FourCC as a number.
+                self.audio_codec_type = AudioCodecType::from_fourcc(&audio_codec_id.to_be_bytes());
+            } else {
+                self.audio_codec_id = audio_codec_id as u8;
+                self.audio_codec_type = AudioCodecType::from(self.audio_codec_id);
+            }
         }
 
-        if let Some(audio_data_rate) = metadata.try_get_number("audiodatarate") {
+        if let Some(audio_data_rate) = metadata.audio_data_rate {
             self.audio_data_rate = audio_data_rate as u32;
         }
 
-        if let Some(video_codec_id) = metadata.try_get_number("videocodecid") {
-            self.video_codec_id = video_codec_id as u8;
-            self.video_codec_type = VideoCodecType::from(self.video_codec_id);
+        if let Some(video_codec_id) = metadata.video_codec_id {
+            if video_codec_id > u8::MAX as u32 {
+                // enhanced rtmp writes the FourCC as a number.
+                self.video_codec_type = VideoCodecType::from_fourcc(&video_codec_id.to_be_bytes());
+            } else {
+                self.video_codec_id = video_codec_id as u8;
+                self.video_codec_type = VideoCodecType::from(self.video_codec_id);
+            }
         }
 
-        if let Some(video_data_rate) = metadata.try_get_number("videodatarate") {
+        if let Some(video_data_rate) = metadata.video_data_rate {
             self.video_data_rate = video_data_rate as u32;
         }
 
-        if let Some(major_brand) = metadata.try_get_string("major_brand") {
-            self.major_brand = major_brand;
-        } else {
-            self.major_brand = String::from("isom");
-        }
+        self.major_brand = metadata.major_brand.unwrap_or_else(|| String::from("isom"));
 
-        if let Some(minor_version) = metadata.try_get_string("minor_version") {
+        if let Some(minor_version) = metadata.minor_version.filter(|v| v.parse::<u32>().is_ok()) {
             self.minor_version = minor_version;
         } else {
             self.minor_version = String::from("512");
         }
 
-        if let Some(mut compatible_brands) = metadata.try_get_string("compatible_brands") {
-            self.compatible_brands.push(String::from_iter(compatible_brands.drain(0..4)));
-            self.compatible_brands.push(String::from_iter(compatible_brands.drain(0..4)));
-            self.compatible_brands.push(String::from_iter(compatible_brands.drain(0..4)));
-            self.compatible_brands.push(String::from_iter(compatible_brands.drain(0..4)));
+        if let Some(compatible_brands) = metadata.compatible_brands.filter(|b| b.chars().count() >= 4) {
+            // brands are four characters each, a trailing partial brand is dropped.
+            let chars = compatible_brands.chars().collect::<Vec<_>>();
+            self.compatible_brands = chars.chunks_exact(4).map(String::from_iter).collect();
         } else {
-            self.compatible_brands.push(String::from("isom"));
-            self.compatible_brands.push(String::from("iso2"));
-            self.compatible_brands.push(String::from("avc1"));
-            self.compatible_brands.push(String::from("mp41"));
+            self.compatible_brands = Self::DEFAULT_COMPATIBLE_BRANDS.iter().map(|brand| brand.to_string()).collect();
         }
 
         self.metadata_configured = true;
     }
 
+    const DEFAULT_COMPATIBLE_BRANDS: [&'static str; 4] = ["isom", "iso2", "avc1", "mp41"];
+
     const AAC_SAMPLE_RATES: [u32; 13] = [
         96000, 88200, 64000, 48000,
         44100, 32000, 24000, 22050,
         16000, 12000, 11025, 8000,
         7350
     ];
-    pub fn configure_audio_metadata(&mut self, audio_metadata: &AudioParseResult) -> Option<AudioCodecConfig> {
-        match audio_metadata {
-            AudioParseResult::AacSequenceHeader(aac_info) => {
-                if self.audio_codec_id != 10 {
-                    panic!("audio type mismatch: expected aac.");
-                }
+    /// Configure track 0, the only track of a stream which is not multitrack.
+    pub fn configure_audio_metadata(&mut self, audio_metadata: &AudioParseResult) -> Result<Option<AudioCodecConfig>, FlvError> {
+        self.configure_audio_track(0, audio_metadata)
+    }
 
-                self.audio_channels = aac_info.channel_configuration;
+    pub fn configure_audio_track(&mut self, track_id: u8, audio_metadata: &AudioParseResult) -> Result<Option<AudioCodecConfig>, FlvError> {
+        let codec_conf = match audio_metadata {
+            AudioParseResult::AacSequenceHeader(aac_info) => {
+                self.check_declared_audio_codec(track_id, AudioCodecType::Aac);
                 if aac_info.sampling_frequency_index > 12 {
-                    panic!("invalid aac sample rate index");
+                    return Err(FlvError::malformed("invalid aac sample rate index"));
                 }
-                self.audio_sample_rate = Self::AAC_SAMPLE_RATES[aac_info.sampling_frequency_index as usize];
-                self.audio_aac_info = Vec::from(aac_info.raw.clone());
-
-                self.audio_metadata_configured = true;
+                let mut track = AudioTrackConfig::new(
+                    AudioCodecType::Aac,
+                    Self::AAC_SAMPLE_RATES[aac_info.sampling_frequency_index as usize],
+                    aac_info.channel_configuration,
+                );
+                track.aac_info = Vec::from(aac_info.raw.clone());
+                self.audio_tracks.insert(track_id, track);
 
                 Some(AudioCodecConfig::new(AudioCodecType::Aac, aac_info.audio_object_type))
             }
             AudioParseResult::Mp3(mp3_info) => {
-                if self.audio_codec_id != 2 {
-                    panic!("audio type mismatch: expected mp3.");
-                }
-
-                self.audio_channels = match mp3_info.channel {
+                self.check_declared_audio_codec(track_id, AudioCodecType::Mp3);
+                let mut track = AudioTrackConfig::new(AudioCodecType::Mp3, mp3_info.sample_rate, 0);
+                track.channels = match mp3_info.channel {
                     Channel::Mono => {
                         1
                     }
@@ -374,74 +509,303 @@ impl RemuxContext {
                         2
                     }
                     Channel::JointStereo => {
-                        self.audio_channels_extended = mp3_info.channel_extended;
+                        track.channels_extended = mp3_info.channel_extended;
                         2
                     }
                 };
-                self.audio_sample_rate = mp3_info.sample_rate;
-
-                self.audio_metadata_configured = true;
+                self.audio_tracks.insert(track_id, track);
 
                 Some(AudioCodecConfig::new(AudioCodecType::Mp3, 0))
             }
+            AudioParseResult::Opus(parsed) => self.configure_ex_audio(track_id, AudioCodecType::Opus, parsed),
+            AudioParseResult::Flac(parsed) => self.configure_ex_audio(track_id, AudioCodecType::Flac, parsed),
+            AudioParseResult::Ac3(parsed) => self.configure_ex_audio(track_id, AudioCodecType::Ac3, parsed),
+            AudioParseResult::Eac3(parsed) => self.configure_ex_audio(track_id, AudioCodecType::Eac3, parsed),
             _ => {
                 // raw data, do nothing.
                 None
             }
-        }
+        };
 
-        // self.audio_metadata_configured = true;
+        Ok(codec_conf.map(|conf| conf.with_track_id(track_id)))
     }
 
+    /// Configure track 0, the only track of a stream which is not multitrack.
     pub fn configure_video_metadata(&mut self, video_metadata: &VideoParseResult) -> Option<VideoCodecConfig> {
-        match video_metadata {
-            VideoParseResult::Avc1(h264_info) => {
-                match h264_info {
-                    Avc1ParseResult::AvcSequenceHeader(header) => {
-                        self.video_avcc_info = AvcCBoxLike::AvcCBoxLike(Vec::from(header.clone()));
-                        // note that raw data may contain some misleading stuff.
-                        // use dbg!() to check what's inside header: &VecDeque<u8>.
-                        let codec_conf = VideoCodecConfig::new(
-                            header[1],
-                            header[2],
-                            header[3],
-                        );
-
-                        self.video_metadata_configured = true;
-                        Some(codec_conf)
-                    }
-                    Avc1ParseResult::AvcEndOfSequence => {
-                        None
-                    }
-                    _ => {
-                        // raw data, do nothing.
-                        None
-                    }
+        self.configure_video_track(0, video_metadata)
+    }
+
+    pub fn configure_video_track(&mut self, track_id: u8, video_metadata: &VideoParseResult) -> Option<VideoCodecConfig> {
+        let (codec_type, config_box, codec_conf) = match video_metadata {
+            VideoParseResult::Avc1(Avc1ParseResult::AvcSequenceHeader(header)) => {
+                if header.len() < 4 {
+                    // not even the profile and level are there.
+                    return None;
                 }
+                // note that raw data may contain some misleading stuff.
+                // use dbg!() to check what's inside header: &VecDeque<u8>.
+                let codec_conf = VideoCodecConfig::new(
+                    header[1],
+                    header[2],
+                    header[3],
+                );
+                (VideoCodecType::Avc1, AvcCBoxLike::AvcCBoxLike(Vec::from(header.clone())), codec_conf)
+            }
+            VideoParseResult::Hvc1(ExVideoParseResult::SequenceStart(record)) => {
+                let record = Vec::from(record.clone());
+                (VideoCodecType::Hvc1, AvcCBoxLike::HvcCBoxLike(record.clone()), VideoCodecConfig::from_hvcc(&record)?)
+            }
+            VideoParseResult::LegacyHevc(ExVideoParseResult::SequenceStart(record)) => {
+                let record = Vec::from(record.clone());
+                // there is no FourCC to tell the sample entry, so it follows from where the parameter sets are.
+                let config_box = AvcCBoxLike::from_hvcc_record(record.clone());
+                let sample_entry_type = String::from_iter(config_box.sample_entry_type());
+                let codec_conf = VideoCodecConfig::from_hvcc_as(&record, &sample_entry_type)?;
+                (VideoCodecType::Hvc1, config_box, codec_conf)
+            }
+            VideoParseResult::Av01(ExVideoParseResult::SequenceStart(record)) => {
+                let record = Vec::from(record.clone());
+                (VideoCodecType::Av01, AvcCBoxLike::Av1CBoxLike(record.clone()), VideoCodecConfig::from_av1c(&record)?)
+            }
+            VideoParseResult::Vp09(ExVideoParseResult::SequenceStart(record)) => {
+                let record = Vec::from(record.clone());
+                (VideoCodecType::Vp09, AvcCBoxLike::VpcCBoxLike(record.clone()), VideoCodecConfig::from_vpcc(&record)?)
             }
             _ => {
+                // raw data and the end of sequence, do nothing.
+                return None;
+            }
+        };
+
+        let sps = match codec_type {
+            VideoCodecType::Avc1 => Self::parse_avc_sps(config_box.record()),
+            _ => None,
+        };
+        if track_id == 0 {
+            // a new sequence header on a configured track may change the codec, the metadata is about the first one.
+            let declared = self.video_codec_type;
+            if !self.video_tracks.contains_key(&track_id) && declared != VideoCodecType::None && declared != codec_type {
+                self.warn(ProbeWarning::VideoCodecMismatch { declared, found: codec_type });
+            }
+            if matches!(codec_type, VideoCodecType::Avc1) {
+                self.video_codec_id = 7;
+            }
+            if let Some(sps) = &sps {
+                self.apply_avc_sps(sps);
+            }
+        }
+        let mut track = VideoTrackConfig::new(codec_type, config_box);
+        if let Some(sps) = &sps {
+            track.width = Some(sps.width);
+            track.height = Some(sps.height);
+        }
+        self.video_tracks.insert(track_id, track);
+        Some(codec_conf.with_track_id(track_id))
+    }
+
+    /// Opus and FLAC are configured by their sequence start, ac-3 and e-ac-3 by their first frame.
+    fn configure_ex_audio(&mut self, track_id: u8, codec_type: AudioCodecType, parsed: &ExAudioParseResult) -> Option<AudioCodecConfig> {
+        let config = match parsed {
+            ExAudioParseResult::SequenceStart(config) => config,
+            ExAudioParseResult::CodedFrames(ExAudioFrame { config: Some(config), .. }) if !self.audio_tracks.contains_key(&track_id) => config,
+            _ => return None,
+        };
+        self.check_declared_audio_codec(track_id, codec_type);
+        let mut track = AudioTrackConfig::new(codec_type, config.sample_rate, config.channels);
+        track.ex_config = Some(config.config_box.clone());
+        self.audio_tracks.insert(track_id, track);
+        Some(AudioCodecConfig::new(codec_type, 0))
+    }
+
+    /// Leave the audio or the video out of the output, or put it back in.
+    /// Only has an effect before the initialization segment is sent.
+    pub fn set_track_enabled(&mut self, track_type: TrackType, enabled: bool) {
+        match track_type {
+            TrackType::Audio => self.audio_enabled = enabled,
+            TrackType::Video => self.video_enabled = enabled,
+        }
+    }
+
+    #[inline]
+    pub fn is_track_enabled(&self, track_type: &TrackType) -> bool {
+        match track_type {
+            TrackType::Audio => self.audio_enabled,
+            TrackType::Video => self.video_enabled,
+        }
+    }
+
+    /// Take the dimensions, the frame rate, the profile and the level from the SPS in `record`,
+    /// over whatever the metadata says. A broken SPS leaves everything as it is.
+    fn parse_avc_sps(record: &[u8]) -> Option<SequenceParameterSet> {
+        match SequenceParameterSet::from_avcc_record(record) {
+            Ok(sps) => Some(sps),
+            Err(e) => {
+                println!("[Remuxer] Ignored the SPS: {}", e);
                 None
             }
         }
     }
 
+    fn apply_avc_sps(&mut self, sps: &SequenceParameterSet) {
+        self.width = sps.width as f64;
+        self.height = sps.height as f64;
+        self.size_from_sps = true;
+        if let Some(frame_rate) = sps.frame_rate {
+            self.fps = frame_rate;
+            self.fps_num = (frame_rate * TIME_SCALE as f64) as u32;
+            self.frame_rate_from_sps = true;
+        }
+        self.video_profile_idc = sps.profile_idc;
+        self.video_level_idc = sps.level_idc;
+    }
+
+    /// The picture size of a video track, from its own SPS, or else from the metadata.
+    pub fn video_size(&self, track_id: u8) -> (f64, f64) {
+        match self.video_tracks.get(&track_id) {
+            Some(VideoTrackConfig { width: Some(width), height: Some(height), .. }) => (*width as f64, *height as f64),
+            _ => (self.width, self.height),
+        }
+    }
+
+    /// The codec configuration of a track as it goes into the sample entry, to tell whether a new one differs.
+    /// None if the track is not configured, or if its codec has no configuration record, like mp3.
+    pub fn track_record(&self, track_type: &TrackType, track_id: u8) -> Option<Vec<u8>> {
+        match track_type {
+            TrackType::Audio => {
+                let track = self.audio_tracks.get(&track_id)?;
+                match track.codec_type {
+                    AudioCodecType::Aac => Some(track.aac_info.clone()),
+                    _ => track.ex_config.as_ref().map(|config| config.payload().clone()),
+                }
+            }
+            TrackType::Video => {
+                let track = self.video_tracks.get(&track_id)?;
+                Some(track.avcc_info.record().clone())
+            }
+        }
+    }
+
+    /// The metadata only describes track 0, and is only a hint. The tags decide.
+    /// A new sequence header on a configured track may change the codec, the metadata is about the first one.
+    fn check_declared_audio_codec(&mut self, track_id: u8, found: AudioCodecType) {
+        if track_id == 0 && !self.audio_tracks.contains_key(&track_id)
+            && self.audio_codec_type != AudioCodecType::None && self.audio_codec_type != found {
+            self.warn(ProbeWarning::AudioCodecMismatch { declared: self.audio_codec_type, found });
+        }
+    }
+
+    /// Frames of a configured track are remuxed into its sample entry,
+    /// so a codec change without a new sequence header is worth a warning.
+    pub fn check_audio_frame_codec(&mut self, track_id: u8, found: AudioCodecType) {
+        if let Some(declared) = self.audio_tracks.get(&track_id).map(|track| track.codec_type) {
+            if declared != found {
+                self.warn(ProbeWarning::AudioCodecMismatch { declared, found });
+            }
+        }
+    }
+
+    /// See `check_audio_frame_codec`.
+    pub fn check_video_frame_codec(&mut self, track_id: u8, found: VideoCodecType) {
+        if let Some(declared) = self.video_tracks.get(&track_id).map(|track| track.codec_type) {
+            if declared != found {
+                self.warn(ProbeWarning::VideoCodecMismatch { declared, found });
+            }
+        }
+    }
+
+    /// Every warning is kept once, a broken stream repeats the same one with every tag.
+    fn warn(&mut self, warning: ProbeWarning) {
+        if self.warnings.contains(&warning) {
+            return;
+        }
+        println!("[Remuxer] Warning: {:?}", warning);
+        self.warnings.push(warning);
+    }
+
+    /// What the flv header and the metadata got wrong about the stream so far.
+    #[inline]
+    pub fn warnings(&self) -> &[ProbeWarning] {
+        &self.warnings
+    }
+
+    /// Set how many media tags are probed before a track which the flv header announces is given up on.
+    /// 64 by default. Unless both track types show up earlier, the initialization segment waits for them.
+    #[inline]
+    pub fn set_probe_limit(&mut self, tags: u32) {
+        self.probe_limit = tags.max(1);
+    }
+
+    /// Whether the track types are settled: both have shown up, or the probe limit has passed.
+    /// A disabled track type is not waited for.
+    fn is_probed(&self) -> bool {
+        self.probed_tags >= self.probe_limit ||
+            ((self.audio_seen || !self.audio_enabled) && (self.video_seen || !self.video_enabled))
+    }
+
+    /// A tag of this type has arrived, so the stream has such a track, whatever the flv header says.
+    /// After the probe limit, an announced track without any tag is not waited for anymore.
+    /// Once the initialization segment is out, the tracks are fixed.
+    pub fn probe_track(&mut self, track_type: &TrackType) {
+        if self.header_sent || self.probed_tags >= self.probe_limit {
+            return;
+        }
+        let (has_track, seen) = match track_type {
+            TrackType::Audio => (&mut self.has_audio, &mut self.audio_seen),
+            TrackType::Video => (&mut self.has_video, &mut self.video_seen),
+        };
+        *seen = true;
+        if !*has_track {
+            *has_track = true;
+            self.warn(ProbeWarning::UnannouncedTrack(*track_type));
+        }
+
+        self.probed_tags += 1;
+        if self.probed_tags == self.probe_limit {
+            if self.has_audio && !self.audio_seen {
+                self.has_audio = false;
+                self.warn(ProbeWarning::MissingTrack(TrackType::Audio));
+            }
+            if self.has_video && !self.video_seen {
+                self.has_video = false;
+                self.warn(ProbeWarning::MissingTrack(TrackType::Video));
+            }
+        }
+    }
+
+    /// Whether the initialization segment has to wait for a track of this type.
+    #[inline]
+    pub fn expects_track(&self, track_type: &TrackType) -> bool {
+        match track_type {
+            TrackType::Audio => self.audio_enabled && self.has_audio,
+            TrackType::Video => self.video_enabled && self.has_video,
+        }
+    }
+
     pub fn is_metadata_complete(&self) -> bool {
         self.flv_header_configured && self.metadata_configured
     }
 
+    #[inline]
+    pub fn is_flv_header_configured(&self) -> bool {
+        self.flv_header_configured
+    }
+
     pub fn is_configured(&self) -> bool {
+        // an audio-only or a video-only stream has a single track type to wait for.
+        // the metadata is only a hint, so it is not waited for.
+        // the flv header flags are only a hint as well, so a track they leave out may still show up while probing.
         self.flv_header_configured &&
-            self.metadata_configured &&
-            self.video_metadata_configured &&
-            self.audio_metadata_configured
+            self.is_probed() &&
+            (!self.expects_track(&TrackType::Video) || !self.video_tracks.is_empty()) &&
+            (!self.expects_track(&TrackType::Audio) || !self.audio_tracks.is_empty()) &&
+            (!self.video_tracks.is_empty() || !self.audio_tracks.is_empty())
     }
 
     /// for testing only!!
+    /// the tracks still have to be configured.
     pub fn _set_configured(&mut self, flag: bool) {
         self.metadata_configured = flag;
         self.flv_header_configured = flag;
-        self.video_metadata_configured = flag;
-        self.audio_metadata_configured = flag;
     }
 
     pub fn is_header_sent(&self) -> bool {
@@ -1,28 +1,34 @@
 use crate::core::Core;
+use crate::error::FlvError;
 use crate::exchange::PackedContentToCore::Data;
 use crate::exchange::{Destination, EndOfSequenceType, MseDecoderConfig, Packed, PackedContent, PackedContentToCore, PackedContentToRemuxer, RemuxedData};
-use crate::flv::header::{FlvHeader, TagHeader};
+use crate::flv::header::FlvHeader;
 use crate::flv::meta::RawMetaData;
-use crate::flv::tag::{Tag, TagType};
-use crate::fmpeg::encoder::{Encoder, DEFAULT_AUDIO_TRACK_ID, DEFAULT_VIDEO_TRACK_ID};
+use crate::flv::event::ScriptEvent;
+use crate::flv::tag::{NormalTagBody, Tag, TagBody, TagType};
+use crate::flv::timestamp::TimestampDiscontinuity;
+use crate::fmpeg::encoder::Encoder;
 use crate::fmpeg::mp4head::ISerializable;
-use crate::fmpeg::parser::{parse_aac_timescale, parse_avc_timescale, parse_mp3_timescale, parse_timescale, parse_timescale_signed, AudioParseResult, Avc1ParseResult, KeyframeType, Parser, VideoParseResult};
-use crate::fmpeg::remux_context::{RemuxContext, SampleContextBuilder, TrackContext, TrackType, VideoSequenceBufferEntry};
+use crate::fmpeg::parser::{parse_aac_timescale, parse_avc_timescale, parse_mp3_timescale, parse_samples_timescale, parse_timescale, parse_timescale_signed, AudioParseResult, Avc1ParseResult, ExAudioFrame, ExAudioParseResult, KeyframeType, Parser, VideoParseResult};
+use crate::fmpeg::remux_context::{ProbeWarning, RemuxContext, SampleContextBuilder, TrackContext, TrackType, VideoSequenceBufferEntry};
 use std::cmp::PartialEq;
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, VecDeque};
 use std::thread::JoinHandle;
 
+/// At most this many samples are held back while the initialization segment waits for another track.
+const MAX_HELD_SAMPLES: usize = 1024;
+
+/// A sample of a configured track, which arrived while another track was still missing.
+enum HeldSample {
+    Audio(u32, u8, AudioParseResult),
+    Video(u32, u8, VideoParseResult),
+}
+
 pub struct Remuxer {
     remuxing: bool,
     pack_buffer: VecDeque<Packed>,
     pub core: Core,
 
-    video_sequence_buffer: VecDeque<VideoSequenceBufferEntry>,
-    video_dts_adjust: Option<u32>,
-
-    audio_sequence_buffer: VecDeque<VideoSequenceBufferEntry>,
-    audio_dts_adjust: Option<u32>,
-
     frame_count: u32,
 
     tags: VecDeque<Tag>,
@@ -31,8 +37,10 @@ pub struct Remuxer {
 
     ctx: RemuxContext,
 
-    audio_track: TrackContext,
-    video_track: TrackContext,
+    // keyed by the flv track id.
+    audio_tracks: BTreeMap<u8, TrackContext>,
+    video_tracks: BTreeMap<u8, TrackContext>,
+    held_samples: VecDeque<HeldSample>,
 
     _temp: Option<Vec<u8>>,
 }
@@ -54,12 +62,6 @@ impl Remuxer {
             pack_buffer: VecDeque::new(),
             core: Core::new(),
 
-            video_sequence_buffer: VecDeque::new(),
-            video_dts_adjust: None,
-
-            audio_sequence_buffer: VecDeque::new(),
-            audio_dts_adjust: None,
-
             frame_count: 0,
 
             tags: VecDeque::new(),
@@ -67,8 +69,9 @@ impl Remuxer {
             flv_header: None,
             ctx: RemuxContext::new(),
 
-            audio_track: TrackContext::new(DEFAULT_AUDIO_TRACK_ID, TrackType::Audio),
-            video_track: TrackContext::new(DEFAULT_VIDEO_TRACK_ID, TrackType::Video),
+            audio_tracks: BTreeMap::new(),
+            video_tracks: BTreeMap::new(),
+            held_samples: VecDeque::new(),
 
             _temp: None,
         }
@@ -79,14 +82,14 @@ impl Remuxer {
         self.remuxing = flag;
     }
 
-    fn send(&mut self, pack: Packed) -> Result<(), Box<dyn std::error::Error>> {
+    fn send(&mut self, pack: Packed) -> Result<(), FlvError> {
         self.core.push_pack(pack);
         Ok(())
     }
 
-    fn send_mpeg4_header(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    fn send_mpeg4_header(&mut self) -> Result<(), FlvError> {
         let mut header = Encoder::encode_ftyp(&self.ctx).serialize();
-        header.append(&mut Encoder::encode_moov(&self.ctx).serialize());
+        header.append(&mut Encoder::encode_moov(&self.ctx)?.serialize());
         self.ctx.set_header_sent(true);
 
         self.send(
@@ -97,7 +100,40 @@ impl Remuxer {
         )
     }
 
-    fn send_raw_data(&mut self, data: RemuxedData) -> Result<(), Box<dyn std::error::Error>> {
+    /// Send the initialization segment as soon as every expected track is configured,
+    /// followed by the samples held back until then. Returns whether it is out.
+    fn send_header_if_ready(&mut self) -> Result<bool, FlvError> {
+        if self.ctx.is_header_sent() {
+            return Ok(true);
+        }
+        if !self.ctx.is_configured() {
+            return Ok(false);
+        }
+        self.send_mpeg4_header()?;
+        if let Some(tmp) = self._temp.take() {
+            self.send_raw_data(RemuxedData::Audio(tmp))?;
+        }
+        while let Some(held) = self.held_samples.pop_front() {
+            let remuxed = match held {
+                HeldSample::Audio(timestamp, track_id, parsed) => self.remux_audio(timestamp, track_id, parsed),
+                HeldSample::Video(timestamp, track_id, parsed) => self.remux_video(timestamp, track_id, parsed),
+            };
+            if let Err(e) = remuxed {
+                println!("[Remuxer] Remux error: {}", e);
+            }
+        }
+        Ok(true)
+    }
+
+    fn hold_sample(&mut self, sample: HeldSample) {
+        if self.held_samples.len() >= MAX_HELD_SAMPLES {
+            println!("[Remuxer] Dropped a held sample, the initialization segment is still waiting for a track.");
+            self.held_samples.pop_front();
+        }
+        self.held_samples.push_back(sample);
+    }
+
+    fn send_raw_data(&mut self, data: RemuxedData) -> Result<(), FlvError> {
         self.send(
             Packed {
                 packed_routing: Destination::Core,
@@ -106,301 +142,470 @@ impl Remuxer {
         )
     }
 
-    fn remux(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        if self.ctx.is_configured() && !self.ctx.is_header_sent() {
-            self.send_mpeg4_header()?;
-            if let Some(tmp) = self._temp.take() {
-                self.send_raw_data(RemuxedData::Audio(tmp))?;
-            }
-        }
+    /// Every enhanced rtmp audio frame is a single sample, like mp3.
+    fn encode_ex_audio_frame(&mut self, track: &mut TrackContext, sample_rate: u32, timestamp: u32, frame: ExAudioFrame) -> Vec<u8> {
+        let mut sample_ctx = SampleContextBuilder::new()
+            .set_decode_time(parse_timescale(timestamp))
+            .set_sample_size(frame.payload.len() as u32)
+            .set_sample_duration(parse_samples_timescale(frame.sample_count, sample_rate))
+            .set_composition_time_offset(0)
+            .build();
+
+        let mut data = Encoder::encode_moof(&mut self.ctx, track, &mut sample_ctx).serialize();
+        data.append(&mut Encoder::encode_mdat(frame.payload).serialize());
+        data
+    }
+
+    fn remux(&mut self) -> Result<(), FlvError> {
+        self.send_header_if_ready()?;
 
         while let Some(tag) = self.tags.pop_front() {
-            match tag.tag_type {
-                TagType::Audio => {
-                    let parsed: AudioParseResult = Parser::parse_audio(&tag)?;
-                    if self.ctx.is_configured() {
-                        if !self.ctx.is_header_sent() {
-                            self.send_mpeg4_header()?;
-                            if let Some(tmp) = self._temp.take() {
-                                self.send_raw_data(RemuxedData::Audio(tmp))?;
-                            }
+            let timestamp = tag.timestamp;
+            self.remux_tag(tag).map_err(|e| e.at_timestamp(timestamp))?;
+        }
+
+        Ok(())
+    }
+
+    fn remux_tag(&mut self, mut tag: Tag) -> Result<(), FlvError> {
+        if let Some(discontinuity) = tag.discontinuity.take() {
+            self.flush_for_discontinuity(discontinuity)?;
+        }
+        let track_type = match tag.tag_type {
+            TagType::Audio => Some(TrackType::Audio),
+            TagType::Video => Some(TrackType::Video),
+            _ => None,
+        };
+        if let Some(track_type) = track_type {
+            if !self.ctx.is_track_enabled(&track_type) {
+                return Ok(());
+            }
+            self.ctx.probe_track(&track_type);
+        }
+        match tag.tag_type {
+            TagType::Audio => {
+                for (track_id, parsed) in Parser::parse_audio_tracks(&tag)? {
+                    self.remux_audio(tag.timestamp, track_id, parsed)?;
+                }
+            }
+            TagType::Video => {
+                for (track_id, parsed) in Parser::parse_video_tracks(&tag)? {
+                    self.remux_video(tag.timestamp, track_id, parsed)?;
+                }
+            }
+            TagType::Script => {
+                if let TagBody::Normal(NormalTagBody::Script(body)) = tag.tag_body {
+                    self.send(
+                        Packed {
+                            packed_routing: Destination::Core,
+                            packed_content: PackedContent::ToCore(PackedContentToCore::ScriptEvent(ScriptEvent::new(tag.timestamp, body))),
                         }
-                        match parsed {
-                            AudioParseResult::AacRaw(raw) => {
-                                if !self.audio_sequence_buffer.is_empty() {
-                                    let mut prev_sample = self.audio_sequence_buffer.iter_mut().last().unwrap();
-
-                                    let prev_dts = prev_sample.sample_ctx.decode_time;
-                                    let current_dts = parse_timescale(tag.timestamp) - self.audio_dts_adjust.unwrap_or(0);
-
-                                    let prev_duration_corrected = current_dts - prev_dts;
-
-                                    prev_sample.sample_ctx.sample_duration = prev_duration_corrected;
-
-                                    let mut sample_ctx = SampleContextBuilder::new()
-                                        .set_decode_time(parse_timescale(tag.timestamp))
-                                        .set_sample_size(raw.len() as u32)
-                                        .set_sample_duration(parse_aac_timescale(self.ctx.audio_sample_rate))
-                                        .set_composition_time_offset(0)
-                                        .build();
-
-                                    self.audio_sequence_buffer.push_back(VideoSequenceBufferEntry::new(Vec::from(raw), sample_ctx));
-
-                                    let front = self.audio_sequence_buffer.pop_front();
-                                    if let Some(mut front) = front {
-                                        let mut data = Encoder::encode_moof(&mut self.ctx, &mut self.audio_track, &mut front.sample_ctx).serialize();
-                                        data.append(&mut Encoder::encode_mdat(front.payload).serialize());
-                                        self.send_raw_data(RemuxedData::Audio(data))?;
-                                    }
-                                } else {
-                                    let sample_ctx = SampleContextBuilder::new()
-                                        .set_decode_time(parse_timescale(tag.timestamp))
-                                        .set_sample_size(raw.len() as u32)
-                                        .set_sample_duration(parse_aac_timescale(self.ctx.audio_sample_rate))
-                                        .set_composition_time_offset(0)
-                                        .build();
-
-                                    self.audio_sequence_buffer.push_back(VideoSequenceBufferEntry::new(Vec::from(raw), sample_ctx));
+                    )?;
+                }
+            }
+            TagType::Encryption => {}
+        }
+
+        Ok(())
+    }
+
+    /// Write out every buffered sample with the duration it has so far,
+    /// since the durations are taken from the timestamp of the next sample, which lies across the break.
+    /// Then tell the core, so that the consumer can place the following fragments.
+    fn flush_for_discontinuity(&mut self, discontinuity: TimestampDiscontinuity) -> Result<(), FlvError> {
+        if !self.ctx.is_header_sent() {
+            return Ok(());
+        }
+        self.flush_sequence_buffers()?;
+        self.send_raw_data(RemuxedData::Discontinuity(discontinuity))
+    }
+
+    /// Write out the samples of every track which are waiting for the next one, with the duration they have so far.
+    fn flush_sequence_buffers(&mut self) -> Result<(), FlvError> {
+        for track_type in [TrackType::Video, TrackType::Audio] {
+            let mut tracks = match track_type {
+                TrackType::Video => std::mem::take(&mut self.video_tracks),
+                TrackType::Audio => std::mem::take(&mut self.audio_tracks),
+            };
+            for track in tracks.values_mut() {
+                while let Some(entry) = track.sequence_buffer.pop_front() {
+                    let mut sample_ctx = entry.sample_ctx;
+                    let mut data = Encoder::encode_moof(&mut self.ctx, track, &mut sample_ctx).serialize();
+                    data.append(&mut Encoder::encode_mdat(entry.payload).serialize());
+                    self.send_raw_data(match track_type {
+                        TrackType::Video => RemuxedData::Video(data),
+                        TrackType::Audio => RemuxedData::Audio(data),
+                    })?;
+                }
+            }
+            match track_type {
+                TrackType::Video => self.video_tracks = tracks,
+                TrackType::Audio => self.audio_tracks = tracks,
+            }
+        }
+        Ok(())
+    }
+
+    /// A track got a new sequence header after the initialization segment was sent.
+    /// A repeated one is ignored. For a changed one, everything so far is flushed,
+    /// and the consumer gets `RemuxedData::Reinitialization` followed by a new initialization segment.
+    fn reconfigure_track(&mut self, track_type: TrackType, conf: Option<MseDecoderConfig>, record_before: Option<Vec<u8>>, track_id: u8) -> Result<(), FlvError> {
+        if self.ctx.track_record(&track_type, track_id) == record_before {
+            return Ok(());
+        }
+        println!("[Remuxer] The configuration of {:?} track {} changed, reinitialising.", track_type, track_id);
+        self.flush_sequence_buffers()?;
+        if let Some(conf) = conf {
+            self.send(Packed {
+                packed_routing: Destination::Core,
+                packed_content: PackedContent::ToCore(PackedContentToCore::DecoderConfig(conf)),
+            })?;
+        }
+        self.send(Packed {
+            packed_routing: Destination::Core,
+            packed_content: PackedContent::ToCore(PackedContentToCore::Reinitialize),
+        })?;
+        self.send_mpeg4_header()
+    }
+
+    /// Take the remux state of a track out of `tracks`, or start a new one.
+    /// It is put back once the sample is written, so that `self` stays free to send.
+    fn take_track(tracks: &mut BTreeMap<u8, TrackContext>, track_type: TrackType, track_id: u8) -> TrackContext {
+        tracks.remove(&track_id)
+            .unwrap_or_else(|| TrackContext::new(Encoder::mp4_track_id(&track_type, track_id), track_type))
+    }
+
+    fn remux_audio(&mut self, timestamp: u32, track_id: u8, parsed: AudioParseResult) -> Result<(), FlvError> {
+        let configured = self.ctx.audio_tracks.contains_key(&track_id);
+        if configured && !self.send_header_if_ready()? && !parsed.is_sequence_header() {
+            // the track is configured, but the initialization segment still waits for another one.
+            self.hold_sample(HeldSample::Audio(timestamp, track_id, parsed));
+            return Ok(());
+        }
+        if configured && self.ctx.is_header_sent() && parsed.is_sequence_header() {
+            let record_before = self.ctx.track_record(&TrackType::Audio, track_id);
+            let conf = self.ctx.configure_audio_track(track_id, &parsed)?;
+            self.reconfigure_track(TrackType::Audio, conf.map(MseDecoderConfig::AudioCodec), record_before, track_id)
+        } else if configured && self.ctx.is_header_sent() {
+            self.ctx.check_audio_frame_codec(track_id, parsed.codec_type());
+            let mut track = Self::take_track(&mut self.audio_tracks, TrackType::Audio, track_id);
+            let sample_rate = self.ctx.audio_tracks[&track_id].sample_rate;
+            let remuxed = self.remux_audio_sample(&mut track, sample_rate, timestamp, parsed);
+            self.audio_tracks.insert(track_id, track);
+            remuxed
+        } else if self.ctx.is_header_sent() {
+            // the initialization segment is out, a track can no longer be added to it.
+            println!("[Remuxer] Dropped a tag of audio track {}, which is not in the initialization segment.", track_id);
+            Ok(())
+        } else {
+            let audio_codec_conf = self.ctx.configure_audio_track(track_id, &parsed)?;
+
+            match parsed {
+                AudioParseResult::Mp3(parsed) => {
+                    let mut sample_ctx = SampleContextBuilder::new()
+                        .set_decode_time(parse_timescale(timestamp))
+                        .set_sample_size(parsed.body.len() as u32)
+                        .set_sample_duration(parse_mp3_timescale(parsed.sample_rate, parsed.version)?)
+                        .set_composition_time_offset(0)
+                        .build();
+
+                    let mut track = Self::take_track(&mut self.audio_tracks, TrackType::Audio, track_id);
+                    let mut data = Encoder::encode_moof(&mut self.ctx, &mut track, &mut sample_ctx).serialize();
+                    data.append(&mut Encoder::encode_mdat(parsed.body).serialize());
+                    self.audio_tracks.insert(track_id, track);
+                    self._temp = Some(data);
+                }
+                AudioParseResult::Opus(ExAudioParseResult::CodedFrames(frame)) |
+                AudioParseResult::Flac(ExAudioParseResult::CodedFrames(frame)) |
+                AudioParseResult::Ac3(ExAudioParseResult::CodedFrames(frame)) |
+                AudioParseResult::Eac3(ExAudioParseResult::CodedFrames(frame)) if self.ctx.audio_tracks.contains_key(&track_id) => {
+                    let mut track = Self::take_track(&mut self.audio_tracks, TrackType::Audio, track_id);
+                    let sample_rate = self.ctx.audio_tracks[&track_id].sample_rate;
+                    self._temp = Some(self.encode_ex_audio_frame(&mut track, sample_rate, timestamp, frame));
+                    self.audio_tracks.insert(track_id, track);
+                }
+                _ => {}
+            }
+
+            if let Some(conf) = audio_codec_conf {
+                self.send(Packed {
+                    packed_routing: Destination::Core,
+                    packed_content: PackedContent::ToCore(
+                        PackedContentToCore::DecoderConfig(
+                            MseDecoderConfig::AudioCodec(conf)
+                        )
+                    ),
+                })?;
+            }
+            self.send_header_if_ready()?;
+            Ok(())
+        }
+    }
+
+    fn remux_audio_sample(&mut self, track: &mut TrackContext, sample_rate: u32, timestamp: u32, parsed: AudioParseResult) -> Result<(), FlvError> {
+        match parsed {
+            AudioParseResult::AacRaw(raw) => {
+                if !track.sequence_buffer.is_empty() {
+                    let mut prev_sample = track.sequence_buffer.iter_mut().last().unwrap();
+
+                    let prev_dts = prev_sample.sample_ctx.decode_time;
+                    let current_dts = parse_timescale(timestamp);
+
+                    let prev_duration_corrected = current_dts.saturating_sub(prev_dts);
+
+                    prev_sample.sample_ctx.sample_duration = prev_duration_corrected;
+
+                    let mut sample_ctx = SampleContextBuilder::new()
+                        .set_decode_time(parse_timescale(timestamp))
+                        .set_sample_size(raw.len() as u32)
+                        .set_sample_duration(parse_aac_timescale(sample_rate))
+                        .set_composition_time_offset(0)
+                        .build();
+
+                    track.sequence_buffer.push_back(VideoSequenceBufferEntry::new(Vec::from(raw), sample_ctx));
+
+                    let front = track.sequence_buffer.pop_front();
+                    if let Some(mut front) = front {
+                        let mut data = Encoder::encode_moof(&mut self.ctx, track, &mut front.sample_ctx).serialize();
+                        data.append(&mut Encoder::encode_mdat(front.payload).serialize());
+                        self.send_raw_data(RemuxedData::Audio(data))?;
+                    }
+                } else {
+                    let sample_ctx = SampleContextBuilder::new()
+                        .set_decode_time(parse_timescale(timestamp))
+                        .set_sample_size(raw.len() as u32)
+                        .set_sample_duration(parse_aac_timescale(sample_rate))
+                        .set_composition_time_offset(0)
+                        .build();
+
+                    track.sequence_buffer.push_back(VideoSequenceBufferEntry::new(Vec::from(raw), sample_ctx));
+                }
+            }
+            AudioParseResult::Mp3(parsed) => {
+                let mut sample_ctx = SampleContextBuilder::new()
+                    .set_decode_time(parse_timescale(timestamp))
+                    .set_sample_size(parsed.body.len() as u32)
+                    .set_sample_duration(parse_mp3_timescale(parsed.sample_rate, parsed.version)?)
+                    .set_composition_time_offset(0)
+                    .build();
+
+                let mut data = Encoder::encode_moof(&mut self.ctx, track, &mut sample_ctx).serialize();
+                data.append(&mut Encoder::encode_mdat(parsed.body).serialize());
+                self.send_raw_data(RemuxedData::Audio(data))?;
+            }
+            AudioParseResult::Opus(ExAudioParseResult::CodedFrames(frame)) |
+            AudioParseResult::Flac(ExAudioParseResult::CodedFrames(frame)) |
+            AudioParseResult::Ac3(ExAudioParseResult::CodedFrames(frame)) |
+            AudioParseResult::Eac3(ExAudioParseResult::CodedFrames(frame)) => {
+                let data = self.encode_ex_audio_frame(track, sample_rate, timestamp, frame);
+                self.send_raw_data(RemuxedData::Audio(data))?;
+            }
+            AudioParseResult::Opus(_) | AudioParseResult::Flac(_) | AudioParseResult::Ac3(_) | AudioParseResult::Eac3(_) => {
+                // sequence start, end and the channel layout have nothing to remux once configured.
+            }
+            _ => {
+                return Err(FlvError::internal("[Remuxer] Unexpected AAC sequence header after configuration."));
+            }
+        }
+        Ok(())
+    }
+
+    fn remux_video(&mut self, timestamp: u32, track_id: u8, parsed: VideoParseResult) -> Result<(), FlvError> {
+        let configured = self.ctx.video_tracks.contains_key(&track_id);
+        if configured && !self.send_header_if_ready()? && !parsed.is_sequence_header() {
+            // the track is configured, but the initialization segment still waits for another one.
+            self.hold_sample(HeldSample::Video(timestamp, track_id, parsed));
+            return Ok(());
+        }
+        if configured && self.ctx.is_header_sent() && parsed.is_sequence_header() {
+            let record_before = self.ctx.track_record(&TrackType::Video, track_id);
+            let conf = self.ctx.configure_video_track(track_id, &parsed);
+            self.reconfigure_track(TrackType::Video, conf.map(MseDecoderConfig::VideoCodec), record_before, track_id)
+        } else if configured && self.ctx.is_header_sent() {
+            self.ctx.check_video_frame_codec(track_id, parsed.codec_type());
+            let mut track = Self::take_track(&mut self.video_tracks, TrackType::Video, track_id);
+            let remuxed = self.remux_video_sample(&mut track, timestamp, parsed);
+            self.video_tracks.insert(track_id, track);
+            remuxed
+        } else if self.ctx.is_header_sent() {
+            // the initialization segment is out, a track can no longer be added to it.
+            println!("[Remuxer] Dropped a tag of video track {}, which is not in the initialization segment.", track_id);
+            Ok(())
+        } else {
+            println!("[Remuxer] Parsed video tag.");
+            if let Some(conf) = self.ctx.configure_video_track(track_id, &parsed) {
+                self.send(
+                    Packed {
+                        packed_routing: Destination::Core,
+                        packed_content: PackedContent::ToCore(
+                            PackedContentToCore::DecoderConfig(
+                                MseDecoderConfig::VideoCodec(conf)
+                            )
+                        ),
+                    }
+                )?;
+            }
+            self.send_header_if_ready()?;
+            Ok(())
+        }
+    }
+
+    fn remux_video_sample(&mut self, track: &mut TrackContext, timestamp: u32, parsed: VideoParseResult) -> Result<(), FlvError> {
+        if let Some(parsed) = parsed.into_avc_like() {
+            match parsed {
+                Avc1ParseResult::AvcNalu(data) => {
+                        /*if data.keyframe_type == KeyframeType::Keyframe {
+                            if self.video_sequence_buffer.is_empty() {
+                                // if this frame is a keyframe, and there's no existing keyframe,
+                                // then buffer it.
+                                println!("No keyframe found, buffering keyframe");
+                                self.frame_count += 1;
+                                let sample_ctx = SampleContextBuilder::new()
+                                    .set_decode_time(parse_timescale(tag.timestamp))
+                                    .set_sample_size(data.payload.len() as u32)
+                                    .set_sample_duration(parse_avc_timescale(self.ctx.fps as f32))
+                                    .set_composition_time_offset(0)
+                                    .set_has_redundancy(false)
+                                    .set_is_leading(self.video_track.sequence_number == 1)
+                                    .set_is_keyframe(data.keyframe_type == KeyframeType::Keyframe)
+                                    .set_is_non_sync(data.keyframe_type == KeyframeType::Interframe)
+                                    .build();
+                                self.video_sequence_buffer.push_back(VideoSequenceBufferEntry::new(Vec::from(data.payload), sample_ctx));
+                            } else {
+                                // if this frame is a keyframe, and there's existing keyframe,
+                                // then drain the buffer, and push this frame to the buffer.
+                                println!("drain buffer");
+                                println!("existing keyframe: {:?}", self.video_sequence_buffer.len());
+                                self.frame_count += self.video_sequence_buffer.len() as u32;
+                                let mut entries = self.video_sequence_buffer.drain(..).collect::<Vec<_>>();
+                                let mut contexts = Vec::new();
+                                let mut data_mdat = Vec::new();
+                                for entry in entries {
+                                    contexts.push(entry.sample_ctx);
+                                    data_mdat.push(entry.payload);
                                 }
+                                let mut send_data = Encoder::encode_moof_merged(&mut self.ctx, &mut self.video_track, &mut contexts).serialize();
+                                send_data.append(&mut Encoder::encode_mdat_merged(data_mdat).serialize());
+                                self.send_raw_data(RemuxedData::Video(send_data))?;
+
+                                let sample_ctx = SampleContextBuilder::new()
+                                    .set_decode_time(parse_timescale(tag.timestamp))
+                                    .set_sample_size(data.payload.len() as u32)
+                                    .set_sample_duration(parse_avc_timescale(self.ctx.fps as f32))
+                                    .set_composition_time_offset(0)
+                                    .set_has_redundancy(false)
+                                    .set_is_leading(self.video_track.sequence_number == 1)
+                                    .set_is_keyframe(data.keyframe_type == KeyframeType::Keyframe)
+                                    .set_is_non_sync(data.keyframe_type == KeyframeType::Interframe)
+                                    .build();
+                                self.video_sequence_buffer.push_back(VideoSequenceBufferEntry::new(Vec::from(data.payload), sample_ctx));
                             }
-                            AudioParseResult::Mp3(parsed) => {
+                        } else {
+                            if self.video_sequence_buffer.is_empty() {
+                                // if this frame is not a keyframe, and there's no existing keyframe,
+                                // then directly send it.
+                                self.frame_count += 1;
+                                println!("No keyframe found, sending interframe");
                                 let mut sample_ctx = SampleContextBuilder::new()
                                     .set_decode_time(parse_timescale(tag.timestamp))
-                                    .set_sample_size(parsed.body.len() as u32)
-                                    .set_sample_duration(parse_mp3_timescale(parsed.sample_rate, parsed.version))
+                                    .set_sample_size(data.payload.len() as u32)
+                                    .set_sample_duration(parse_avc_timescale(self.ctx.fps as f32))
                                     .set_composition_time_offset(0)
+                                    .set_has_redundancy(false)
+                                    .set_is_leading(self.video_track.sequence_number == 1)
+                                    .set_is_keyframe(data.keyframe_type == KeyframeType::Keyframe)
+                                    .set_is_non_sync(data.keyframe_type == KeyframeType::Interframe)
                                     .build();
 
-                                let mut data = Encoder::encode_moof(&mut self.ctx, &mut self.audio_track, &mut sample_ctx).serialize();
-                                data.append(&mut Encoder::encode_mdat(parsed.body).serialize());
-                                self.send_raw_data(RemuxedData::Audio(data))?;
-                            }
-                            _ => {
-                                panic!("[Remuxer] Aac format header not set!")
+                                let mut send_data = Encoder::encode_moof(&mut self.ctx, &mut self.video_track, &mut sample_ctx).serialize();
+                                send_data.append(&mut Encoder::encode_mdat(Vec::from(data.payload)).serialize());
+                                self.send_raw_data(RemuxedData::Video(send_data))?;
+                            } else {
+                                // if this frame is not a keyframe, and there's an existing keyframe,
+                                // then push this frame to the end of the buffer.
+                                println!("Push interframe to buffer");
+                                let sample_ctx = SampleContextBuilder::new()
+                                    .set_decode_time(parse_timescale(tag.timestamp))
+                                    .set_sample_size(data.payload.len() as u32)
+                                    .set_sample_duration(parse_avc_timescale(self.ctx.fps as f32))
+                                    .set_composition_time_offset(0)
+                                    .set_has_redundancy(false)
+                                    .set_is_leading(self.video_track.sequence_number == 1)
+                                    .set_is_keyframe(data.keyframe_type == KeyframeType::Keyframe)
+                                    .set_is_non_sync(data.keyframe_type == KeyframeType::Interframe)
+                                    .build();
+                                self.video_sequence_buffer.push_back(VideoSequenceBufferEntry::new(Vec::from(data.payload), sample_ctx))
                             }
+                        }*/
+                    let cts = data.composition_time_offset;
+
+                    if !track.sequence_buffer.is_empty() {
+                        let mut prev_sample = track.sequence_buffer.iter_mut().last().unwrap();
+
+                        let prev_dts = prev_sample.sample_ctx.decode_time;
+                        let current_dts = parse_timescale(timestamp);
+
+                        let prev_duration_correction = current_dts.saturating_sub(prev_dts);
+                        prev_sample.sample_ctx.sample_duration = prev_duration_correction;
+
+                        let sample_ctx = SampleContextBuilder::new()
+                            .set_decode_time(current_dts)
+                            .set_sample_size(data.payload.len() as u32)
+                            .set_sample_duration(parse_avc_timescale(self.ctx.fps as f32))
+                            .set_composition_time_offset(parse_timescale_signed(cts))
+                            .set_has_redundancy(false)
+                            .set_is_leading(track.sequence_number == 1)
+                            .set_is_keyframe(data.keyframe_type == KeyframeType::Keyframe)
+                            .set_is_non_sync(data.keyframe_type == KeyframeType::Interframe)
+                            .build();
+
+                        track.sequence_buffer.push_back(VideoSequenceBufferEntry::new(Vec::from(data.payload), sample_ctx));
+
+                        let front = track.sequence_buffer.pop_front();
+                        if let Some(mut front) = front {
+                            let mut send_data = Encoder::encode_moof(&mut self.ctx, track, &mut front.sample_ctx).serialize();
+                            send_data.append(&mut Encoder::encode_mdat(Vec::from(front.payload)).serialize());
+                            self.send_raw_data(RemuxedData::Video(send_data))?;
                         }
                     } else {
-                        let audio_codec_conf = self.ctx.configure_audio_metadata(&parsed);
-
-                        if let AudioParseResult::Mp3(parsed) = parsed {
-                            let mut sample_ctx = SampleContextBuilder::new()
-                                .set_decode_time(parse_timescale(tag.timestamp))
-                                .set_sample_size(parsed.body.len() as u32)
-                                .set_sample_duration(parse_mp3_timescale(parsed.sample_rate, parsed.version))
-                                .set_composition_time_offset(0)
-                                .build();
-
-                            let mut data = Encoder::encode_moof(&mut self.ctx, &mut self.audio_track, &mut sample_ctx).serialize();
-                            data.append(&mut Encoder::encode_mdat(parsed.body).serialize());
-                            self._temp = Some(data);
-                        }
-
-                        if let Some(conf) = audio_codec_conf {
-                            self.send(Packed {
-                                packed_routing: Destination::Core,
-                                packed_content: PackedContent::ToCore(
-                                    PackedContentToCore::DecoderConfig(
-                                        MseDecoderConfig::AudioCodec(conf)
-                                    )
-                                ),
-                            })?;
-                        }
+                        let sample_ctx = SampleContextBuilder::new()
+                            .set_decode_time(parse_timescale(timestamp))
+                            .set_sample_size(data.payload.len() as u32)
+                            .set_sample_duration(parse_avc_timescale(self.ctx.fps as f32))
+                            .set_composition_time_offset(0)
+                            .set_has_redundancy(false)
+                            .set_is_leading(track.sequence_number == 1)
+                            .set_is_keyframe(data.keyframe_type == KeyframeType::Keyframe)
+                            .set_is_non_sync(data.keyframe_type == KeyframeType::Interframe)
+                            .build();
+
+                        track.sequence_buffer.push_back(VideoSequenceBufferEntry::new(Vec::from(data.payload), sample_ctx));
                     }
                 }
-                TagType::Video => {
-                    let parsed: VideoParseResult = Parser::parse_video(&tag)?;
-                    if self.ctx.is_configured() {
-                        if !self.ctx.is_header_sent() {
-                            self.send_mpeg4_header()?;
-                            if let Some(tmp) = self._temp.take() {
-                                self.send_raw_data(RemuxedData::Video(tmp))?;
-                            }
-                        }
-                        if let VideoParseResult::Avc1(parsed) = parsed {
-                            match parsed {
-                                Avc1ParseResult::AvcNalu(data) => {
-                                    /*if data.keyframe_type == KeyframeType::Keyframe {
-                                        if self.video_sequence_buffer.is_empty() {
-                                            // if this frame is a keyframe, and there's no existing keyframe,
-                                            // then buffer it.
-                                            println!("No keyframe found, buffering keyframe");
-                                            self.frame_count += 1;
-                                            let sample_ctx = SampleContextBuilder::new()
-                                                .set_decode_time(parse_timescale(tag.timestamp))
-                                                .set_sample_size(data.payload.len() as u32)
-                                                .set_sample_duration(parse_avc_timescale(self.ctx.fps as f32))
-                                                .set_composition_time_offset(0)
-                                                .set_has_redundancy(false)
-                                                .set_is_leading(self.video_track.sequence_number == 1)
-                                                .set_is_keyframe(data.keyframe_type == KeyframeType::Keyframe)
-                                                .set_is_non_sync(data.keyframe_type == KeyframeType::Interframe)
-                                                .build();
-                                            self.video_sequence_buffer.push_back(VideoSequenceBufferEntry::new(Vec::from(data.payload), sample_ctx));
-                                        } else {
-                                            // if this frame is a keyframe, and there's existing keyframe,
-                                            // then drain the buffer, and push this frame to the buffer.
-                                            println!("drain buffer");
-                                            println!("existing keyframe: {:?}", self.video_sequence_buffer.len());
-                                            self.frame_count += self.video_sequence_buffer.len() as u32;
-                                            let mut entries = self.video_sequence_buffer.drain(..).collect::<Vec<_>>();
-                                            let mut contexts = Vec::new();
-                                            let mut data_mdat = Vec::new();
-                                            for entry in entries {
-                                                contexts.push(entry.sample_ctx);
-                                                data_mdat.push(entry.payload);
-                                            }
-                                            let mut send_data = Encoder::encode_moof_merged(&mut self.ctx, &mut self.video_track, &mut contexts).serialize();
-                                            send_data.append(&mut Encoder::encode_mdat_merged(data_mdat).serialize());
-                                            self.send_raw_data(RemuxedData::Video(send_data))?;
-
-                                            let sample_ctx = SampleContextBuilder::new()
-                                                .set_decode_time(parse_timescale(tag.timestamp))
-                                                .set_sample_size(data.payload.len() as u32)
-                                                .set_sample_duration(parse_avc_timescale(self.ctx.fps as f32))
-                                                .set_composition_time_offset(0)
-                                                .set_has_redundancy(false)
-                                                .set_is_leading(self.video_track.sequence_number == 1)
-                                                .set_is_keyframe(data.keyframe_type == KeyframeType::Keyframe)
-                                                .set_is_non_sync(data.keyframe_type == KeyframeType::Interframe)
-                                                .build();
-                                            self.video_sequence_buffer.push_back(VideoSequenceBufferEntry::new(Vec::from(data.payload), sample_ctx));
-                                        }
-                                    } else {
-                                        if self.video_sequence_buffer.is_empty() {
-                                            // if this frame is not a keyframe, and there's no existing keyframe,
-                                            // then directly send it.
-                                            self.frame_count += 1;
-                                            println!("No keyframe found, sending interframe");
-                                            let mut sample_ctx = SampleContextBuilder::new()
-                                                .set_decode_time(parse_timescale(tag.timestamp))
-                                                .set_sample_size(data.payload.len() as u32)
-                                                .set_sample_duration(parse_avc_timescale(self.ctx.fps as f32))
-                                                .set_composition_time_offset(0)
-                                                .set_has_redundancy(false)
-                                                .set_is_leading(self.video_track.sequence_number == 1)
-                                                .set_is_keyframe(data.keyframe_type == KeyframeType::Keyframe)
-                                                .set_is_non_sync(data.keyframe_type == KeyframeType::Interframe)
-                                                .build();
-
-                                            let mut send_data = Encoder::encode_moof(&mut self.ctx, &mut self.video_track, &mut sample_ctx).serialize();
-                                            send_data.append(&mut Encoder::encode_mdat(Vec::from(data.payload)).serialize());
-                                            self.send_raw_data(RemuxedData::Video(send_data))?;
-                                        } else {
-                                            // if this frame is not a keyframe, and there's an existing keyframe,
-                                            // then push this frame to the end of the buffer.
-                                            println!("Push interframe to buffer");
-                                            let sample_ctx = SampleContextBuilder::new()
-                                                .set_decode_time(parse_timescale(tag.timestamp))
-                                                .set_sample_size(data.payload.len() as u32)
-                                                .set_sample_duration(parse_avc_timescale(self.ctx.fps as f32))
-                                                .set_composition_time_offset(0)
-                                                .set_has_redundancy(false)
-                                                .set_is_leading(self.video_track.sequence_number == 1)
-                                                .set_is_keyframe(data.keyframe_type == KeyframeType::Keyframe)
-                                                .set_is_non_sync(data.keyframe_type == KeyframeType::Interframe)
-                                                .build();
-                                            self.video_sequence_buffer.push_back(VideoSequenceBufferEntry::new(Vec::from(data.payload), sample_ctx))
-                                        }
-                                    }*/
-                                    let cts = if let TagHeader::Video(ref header) = tag.tag_header {
-                                        header.composition_time_offset.unwrap_or(0)
-                                    } else {
-                                        0
-                                    };
-
-                                    if !self.video_sequence_buffer.is_empty() {
-                                        let mut prev_sample = self.video_sequence_buffer.iter_mut().last().unwrap();
-
-                                        let prev_dts = prev_sample.sample_ctx.decode_time;
-                                        let current_dts = parse_timescale(tag.timestamp);
-
-                                        let prev_duration_correction = current_dts - prev_dts;
-                                        prev_sample.sample_ctx.sample_duration = prev_duration_correction;
-
-                                        let dts_correction = parse_timescale(tag.timestamp) - self.video_dts_adjust.unwrap_or(0);
-
-                                        let sample_ctx = SampleContextBuilder::new()
-                                            .set_decode_time(dts_correction)
-                                            .set_sample_size(data.payload.len() as u32)
-                                            .set_sample_duration(parse_avc_timescale(self.ctx.fps as f32))
-                                            .set_composition_time_offset(parse_timescale_signed(cts))
-                                            .set_has_redundancy(false)
-                                            .set_is_leading(self.video_track.sequence_number == 1)
-                                            .set_is_keyframe(data.keyframe_type == KeyframeType::Keyframe)
-                                            .set_is_non_sync(data.keyframe_type == KeyframeType::Interframe)
-                                            .build();
-
-                                        self.video_sequence_buffer.push_back(VideoSequenceBufferEntry::new(Vec::from(data.payload), sample_ctx));
-
-                                        let front = self.video_sequence_buffer.pop_front();
-                                        if let Some(mut front) = front {
-                                            let mut send_data = Encoder::encode_moof(&mut self.ctx, &mut self.video_track, &mut front.sample_ctx).serialize();
-                                            send_data.append(&mut Encoder::encode_mdat(Vec::from(front.payload)).serialize());
-                                            self.send_raw_data(RemuxedData::Video(send_data))?;
-                                        }
-                                    } else {
-                                        self.video_dts_adjust = Some(parse_timescale(tag.timestamp));
-
-                                        let sample_ctx = SampleContextBuilder::new()
-                                            .set_decode_time(parse_timescale(tag.timestamp))
-                                            .set_sample_size(data.payload.len() as u32)
-                                            .set_sample_duration(parse_avc_timescale(self.ctx.fps as f32))
-                                            .set_composition_time_offset(0)
-                                            .set_has_redundancy(false)
-                                            .set_is_leading(self.video_track.sequence_number == 1)
-                                            .set_is_keyframe(data.keyframe_type == KeyframeType::Keyframe)
-                                            .set_is_non_sync(data.keyframe_type == KeyframeType::Interframe)
-                                            .build();
-
-                                        self.video_sequence_buffer.push_back(VideoSequenceBufferEntry::new(Vec::from(data.payload), sample_ctx));
-                                    }
-                                }
-                                Avc1ParseResult::AvcSequenceHeader(_) => {
-                                    panic!("[Remuxer] Avc sequence header not set!")
-                                }
-                                Avc1ParseResult::AvcEndOfSequence => {
-                                    // handle all the remaining frames in the buffer.
-                                    while !self.video_sequence_buffer.is_empty() {
-                                        let entry = self.video_sequence_buffer.pop_front().unwrap();
-                                        let mut sample_ctx = entry.sample_ctx;
-                                        let mut send_data = Encoder::encode_moof(&mut self.ctx, &mut self.video_track, &mut sample_ctx).serialize();
-                                        send_data.append(&mut Encoder::encode_mdat(entry.payload).serialize());
-                                        self.send_raw_data(RemuxedData::Video(send_data))?;
-                                        self.frame_count += 1;
-                                    }
-                                    println!("[Remuxer] End of sequence.");
-                                    println!("[Remuxer] Frame count: {}", self.frame_count);
-                                    // todo: note that the end of sequence type is set to both, because the audio track is also ended.
-                                    self.send(Packed {
-                                        packed_routing: Destination::Core,
-                                        packed_content: PackedContent::ToCore(
-                                            PackedContentToCore::Data(RemuxedData::EndOfSequence(EndOfSequenceType::Both))
-                                        ),
-                                    })?;
-                                }
-                            }
-                        }
-                    } else {
-                        println!("[Remuxer] Parsed video tag.");
-                        if let Some(conf) = self.ctx.configure_video_metadata(&parsed) {
-                            self.send(
-                                Packed {
-                                    packed_routing: Destination::Core,
-                                    packed_content: PackedContent::ToCore(
-                                        PackedContentToCore::DecoderConfig(
-                                            MseDecoderConfig::VideoCodec(conf)
-                                        )
-                                    ),
-                                }
-                            )?;
-                        }
+                Avc1ParseResult::AvcSequenceHeader(_) => {
+                    return Err(FlvError::internal("[Remuxer] Unexpected video sequence header after configuration."));
+                }
+                Avc1ParseResult::AvcEndOfSequence => {
+                    // handle all the remaining frames in the buffer.
+                    while let Some(entry) = track.sequence_buffer.pop_front() {
+                        let mut sample_ctx = entry.sample_ctx;
+                        let mut send_data = Encoder::encode_moof(&mut self.ctx, track, &mut sample_ctx).serialize();
+                        send_data.append(&mut Encoder::encode_mdat(entry.payload).serialize());
+                        self.send_raw_data(RemuxedData::Video(send_data))?;
+                        self.frame_count += 1;
                     }
+                    println!("[Remuxer] End of sequence.");
+                    println!("[Remuxer] Frame count: {}", self.frame_count);
+                    // todo: note that the end of sequence type is set to both, because the audio track is also ended.
+                    self.send(Packed {
+                        packed_routing: Destination::Core,
+                        packed_content: PackedContent::ToCore(
+                            PackedContentToCore::Data(RemuxedData::EndOfSequence(EndOfSequenceType::Both))
+                        ),
+                    })?;
                 }
-                TagType::Script => {}
-                TagType::Encryption => {}
             }
         }
-
         Ok(())
     }
 
@@ -408,7 +613,39 @@ impl Remuxer {
         self.pack_buffer.push_back(pack);
     }
 
-    pub fn run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    /// See `RemuxContext::warnings`.
+    #[inline]
+    pub fn probe_warnings(&self) -> &[ProbeWarning] {
+        self.ctx.warnings()
+    }
+
+    /// See `RemuxContext::set_probe_limit`.
+    #[inline]
+    pub fn set_probe_limit(&mut self, tags: u32) {
+        self.ctx.set_probe_limit(tags);
+    }
+
+    /// See `RemuxContext::set_track_enabled`.
+    #[inline]
+    pub fn set_track_enabled(&mut self, track_type: TrackType, enabled: bool) {
+        self.ctx.set_track_enabled(track_type, enabled);
+    }
+
+    /// Drop every tag and sample which has not been written out yet, after the input jumped.
+    /// The codec configuration and the sequence numbers are kept, and as the decode times are the
+    /// timestamps of the tags, the fragments after the jump continue the same track with a matching `tfdt`.
+    pub(crate) fn reset_for_seek(&mut self) {
+        self.pack_buffer.retain(|pack| !matches!(pack.packed_content, PackedContent::ToRemuxer(PackedContentToRemuxer::PushTag(_))));
+        self.tags.clear();
+        self.held_samples.clear();
+        for track in self.video_tracks.values_mut().chain(self.audio_tracks.values_mut()) {
+            track.sequence_buffer.clear();
+        }
+        self._temp = None;
+        self.core.discard_media();
+    }
+
+    pub fn run(&mut self) -> Result<(), FlvError> {
         while let Some(received) = self.pack_buffer.pop_front() {
             if received.packed_routing != Destination::Remuxer {
                 self.core.push_pack(received);
@@ -451,9 +688,10 @@ impl Remuxer {
             return Ok(());
         }
 
-        if self.ctx.is_metadata_complete() {
-            if self.remux().is_err() {
-                println!("[Remuxer] Remux error.");
+        // the metadata is only a hint, the tracks are probed from the tags themselves.
+        if self.ctx.is_flv_header_configured() {
+            if let Err(e) = self.remux() {
+                println!("[Remuxer] Remux error: {}", e);
                 return Ok(());
             }
         } else {
@@ -0,0 +1,209 @@
+use crate::error::FlvError;
+use crate::io::bit::BitReader;
+
+/// Sample aspect ratios for `aspect_ratio_idc` 1 to 16, table E-1 of the h.264 specification.
+const SAMPLE_ASPECT_RATIOS: [(u16, u16); 16] = [
+    (1, 1), (12, 11), (10, 11), (16, 11),
+    (40, 33), (24, 11), (20, 11), (32, 11),
+    (80, 33), (18, 11), (15, 11), (64, 33),
+    (160, 99), (4, 3), (3, 2), (2, 1),
+];
+const EXTENDED_SAR: u32 = 255;
+
+/// What the remuxer needs from an h.264 sequence parameter set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SequenceParameterSet {
+    pub profile_idc: u8,
+    /// constraint_set0_flag to constraint_set5_flag and the two reserved bits, as in the codec string.
+    pub constraint_flags: u8,
+    pub level_idc: u8,
+    pub chroma_format_idc: u32,
+    pub bit_depth_luma: u8,
+    pub bit_depth_chroma: u8,
+    /// The displayed size in pixels, after the cropping.
+    pub width: u32,
+    pub height: u32,
+    /// From the timing info of the VUI, if there is one.
+    pub frame_rate: Option<f64>,
+    /// Width and height of a sample, from the VUI, if there is one.
+    pub sample_aspect_ratio: Option<(u16, u16)>,
+}
+
+impl SequenceParameterSet {
+    /// Parse the first SPS of an AVCDecoderConfigurationRecord, i.e. the body of `avcC`.
+    pub fn from_avcc_record(record: &[u8]) -> Result<Self, FlvError> {
+        // configurationVersion, the profile, compatibility and level, lengthSizeMinusOne, then numOfSequenceParameterSets.
+        if record.len() < 8 || record[5] & 0x1F == 0 {
+            return Err(FlvError::malformed("AVC decoder configuration record has no SPS."));
+        }
+        let length = u16::from_be_bytes([record[6], record[7]]) as usize;
+        let nal_unit = record.get(8..8 + length).ok_or(FlvError::malformed("SPS in the AVC decoder configuration record is truncated."))?;
+        Self::parse(nal_unit)
+    }
+
+    /// Parse an SPS NAL unit, including its one byte header.
+    pub fn parse(nal_unit: &[u8]) -> Result<Self, FlvError> {
+        match nal_unit.first() {
+            Some(header) if header & 0x1F == 7 => {}
+            _ => return Err(FlvError::malformed("Not an SPS NAL unit.")),
+        }
+        let rbsp = Self::unescape(&nal_unit[1..]);
+        let mut bits = BitReader::new(&rbsp);
+
+        let profile_idc = bits.read_bits(8)? as u8;
+        let constraint_flags = bits.read_bits(8)? as u8;
+        let level_idc = bits.read_bits(8)? as u8;
+        bits.read_ue()?; // seq_parameter_set_id
+
+        let mut chroma_format_idc = 1;
+        let mut separate_colour_plane = false;
+        let mut bit_depth_luma = 8;
+        let mut bit_depth_chroma = 8;
+        if matches!(profile_idc, 100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135) {
+            chroma_format_idc = bits.read_ue()?;
+            if chroma_format_idc > 3 {
+                return Err(FlvError::malformed(format!("Invalid chroma_format_idc {}.", chroma_format_idc)));
+            }
+            if chroma_format_idc == 3 {
+                separate_colour_plane = bits.read_bit()?;
+            }
+            bit_depth_luma = 8 + bits.read_ue()?.min(6) as u8;
+            bit_depth_chroma = 8 + bits.read_ue()?.min(6) as u8;
+            bits.skip_bits(1)?; // qpprime_y_zero_transform_bypass_flag
+            if bits.read_bit()? {
+                // seq_scaling_matrix_present_flag
+                let count = if chroma_format_idc == 3 { 12 } else { 8 };
+                for index in 0..count {
+                    if bits.read_bit()? {
+                        Self::skip_scaling_list(&mut bits, if index < 6 { 16 } else { 64 })?;
+                    }
+                }
+            }
+        }
+
+        bits.read_ue()?; // log2_max_frame_num_minus4
+        match bits.read_ue()? {
+            0 => {
+                bits.read_ue()?; // log2_max_pic_order_cnt_lsb_minus4
+            }
+            1 => {
+                bits.skip_bits(1)?; // delta_pic_order_always_zero_flag
+                bits.read_se()?; // offset_for_non_ref_pic
+                bits.read_se()?; // offset_for_top_to_bottom_field
+                for _ in 0..bits.read_ue()? {
+                    bits.read_se()?; // offset_for_ref_frame
+                }
+            }
+            _ => {}
+        }
+        bits.read_ue()?; // max_num_ref_frames
+        bits.skip_bits(1)?; // gaps_in_frame_num_value_allowed_flag
+
+        let width_in_mbs = bits.read_ue()? as u64 + 1;
+        let height_in_map_units = bits.read_ue()? as u64 + 1;
+        let frame_mbs_only = bits.read_bit()?;
+        if !frame_mbs_only {
+            bits.skip_bits(1)?; // mb_adaptive_frame_field_flag
+        }
+        bits.skip_bits(1)?; // direct_8x8_inference_flag
+
+        let mut crop = [0u64; 4];
+        if bits.read_bit()? {
+            for offset in crop.iter_mut() {
+                *offset = bits.read_ue()? as u64;
+            }
+        }
+
+        // the cropping is in chroma samples, and in field pairs for interlaced video.
+        let field_factor = if frame_mbs_only { 1 } else { 2 };
+        let chroma_array_type = if separate_colour_plane { 0 } else { chroma_format_idc };
+        let (crop_unit_x, crop_unit_y) = match chroma_array_type {
+            1 => (2, 2 * field_factor),
+            2 => (2, field_factor),
+            _ => (1, field_factor),
+        };
+        let [left, right, top, bottom] = crop;
+        let width = (width_in_mbs * 16).saturating_sub(crop_unit_x * (left + right));
+        let height = (field_factor * height_in_map_units * 16).saturating_sub(crop_unit_y * (top + bottom));
+
+        let mut frame_rate = None;
+        let mut sample_aspect_ratio = None;
+        if bits.read_bit()? {
+            // vui_parameters_present_flag
+            if bits.read_bit()? {
+                let aspect_ratio_idc = bits.read_bits(8)?;
+                sample_aspect_ratio = match aspect_ratio_idc {
+                    EXTENDED_SAR => Some((bits.read_bits(16)? as u16, bits.read_bits(16)? as u16)),
+                    1..=16 => Some(SAMPLE_ASPECT_RATIOS[aspect_ratio_idc as usize - 1]),
+                    _ => None,
+                };
+            }
+            if bits.read_bit()? {
+                bits.skip_bits(1)?; // overscan_appropriate_flag
+            }
+            if bits.read_bit()? {
+                bits.skip_bits(3 + 1)?; // video_format, video_full_range_flag
+                if bits.read_bit()? {
+                    bits.skip_bits(8 * 3)?; // colour_primaries, transfer_characteristics, matrix_coefficients
+                }
+            }
+            if bits.read_bit()? {
+                bits.read_ue()?; // chroma_sample_loc_type_top_field
+                bits.read_ue()?; // chroma_sample_loc_type_bottom_field
+            }
+            if bits.read_bit()? {
+                let num_units_in_tick = bits.read_bits(32)?;
+                let time_scale = bits.read_bits(32)?;
+                // a frame takes two ticks, one per field.
+                if num_units_in_tick > 0 && time_scale > 0 {
+                    frame_rate = Some(time_scale as f64 / (2.0 * num_units_in_tick as f64));
+                }
+            }
+        }
+
+        if width == 0 || height == 0 || width > u32::MAX as u64 || height > u32::MAX as u64 {
+            return Err(FlvError::malformed(format!("Implausible SPS picture size {}x{}.", width, height)));
+        }
+        Ok(Self {
+            profile_idc,
+            constraint_flags,
+            level_idc,
+            chroma_format_idc,
+            bit_depth_luma,
+            bit_depth_chroma,
+            width: width as u32,
+            height: height as u32,
+            frame_rate,
+            sample_aspect_ratio,
+        })
+    }
+
+    /// Remove the emulation prevention bytes, i.e. every 0x03 after two zero bytes.
+    fn unescape(data: &[u8]) -> Vec<u8> {
+        let mut rbsp = Vec::with_capacity(data.len());
+        let mut zeros = 0;
+        for &byte in data {
+            if zeros >= 2 && byte == 0x03 {
+                zeros = 0;
+                continue;
+            }
+            zeros = if byte == 0 { zeros + 1 } else { 0 };
+            rbsp.push(byte);
+        }
+        rbsp
+    }
+
+    fn skip_scaling_list(bits: &mut BitReader, size: usize) -> Result<(), FlvError> {
+        let mut last_scale = 8i64;
+        let mut next_scale = 8i64;
+        for _ in 0..size {
+            if next_scale != 0 {
+                next_scale = (last_scale + bits.read_se()? as i64).rem_euclid(256);
+            }
+            if next_scale != 0 {
+                last_scale = next_scale;
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,3 +1,5 @@
+use crate::error::FlvError;
+
 pub struct BitIO {
     byte: u8,
 }
@@ -22,9 +24,9 @@ impl BitIO {
     }
 
     #[inline]
-    pub fn read_bit_safe(&self, index: usize) -> Result<bool, Box<dyn std::error::Error>> {
+    pub fn read_bit_safe(&self, index: usize) -> Result<bool, FlvError> {
         if index > 7 {
-            Err("Index out of range".into())
+            Err(FlvError::internal(format!("Bit index {} out of range.", index)))
         } else {
             Ok((self.byte & (1 << index)) != 0)
         }
@@ -35,9 +37,8 @@ impl BitIO {
     /// Why? because it's easier to read.
     #[inline]
     pub fn read_range(&self, start: usize, end: usize) -> u8 {
-        let mut mask: u8 = 0b11111111u8;
-        mask >>= start;
-        mask <<= 7 - end;
+        // shifting a single mask right and then left would bring the bits before `start` back.
+        let mask = (0b11111111u8 >> start) & (0b11111111u8 << (7 - end));
         (self.byte & mask) >> (7 - end)
     }
 }
@@ -179,4 +180,74 @@ impl U32BitIO {
             self.write_at(i, (value & (1 << (end - i))) != 0);
         }
     }
-}
\ No newline at end of file
+}
+
+/// Reads bits from a byte slice, most significant bit first,
+/// for headers whose fields are not aligned to bytes.
+pub struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    #[inline]
+    pub fn new(data: &'a [u8]) -> BitReader<'a> {
+        Self { data, position: 0 }
+    }
+
+    /// Number of bits read so far.
+    #[inline]
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    #[inline]
+    pub fn read_bit(&mut self) -> Result<bool, FlvError> {
+        let byte = self.data.get(self.position / 8)
+            .ok_or(FlvError::malformed(format!("Bit field is truncated after {} bits.", self.position)))?;
+        let bit = byte & (1 << (7 - self.position % 8)) != 0;
+        self.position += 1;
+        Ok(bit)
+    }
+
+    /// Read `count` bits, at most 32, as a big-endian number.
+    pub fn read_bits(&mut self, count: usize) -> Result<u32, FlvError> {
+        if count > 32 {
+            return Err(FlvError::internal(format!("Can not read {} bits at once.", count)));
+        }
+        let mut result = 0u32;
+        for _ in 0..count {
+            result = (result << 1) | self.read_bit()? as u32;
+        }
+        Ok(result)
+    }
+
+    #[inline]
+    pub fn skip_bits(&mut self, count: usize) -> Result<(), FlvError> {
+        if self.position + count > self.data.len() * 8 {
+            return Err(FlvError::malformed(format!("Bit field is truncated after {} bits.", self.data.len() * 8)));
+        }
+        self.position += count;
+        Ok(())
+    }
+
+    /// Read an unsigned exp-Golomb code, ue(v) in the h.264 and h.265 specifications.
+    pub fn read_ue(&mut self) -> Result<u32, FlvError> {
+        let mut leading_zeros = 0;
+        while !self.read_bit()? {
+            leading_zeros += 1;
+            if leading_zeros > 31 {
+                return Err(FlvError::malformed("Exp-Golomb code is longer than 32 bits."));
+            }
+        }
+        // 2^leading_zeros - 1 + the bits after the marker, which fits as long as leading_zeros <= 31.
+        Ok(((1u64 << leading_zeros) - 1 + self.read_bits(leading_zeros)? as u64) as u32)
+    }
+
+    /// Read a signed exp-Golomb code, se(v): 1, -1, 2, -2, ... for the codes 1, 2, 3, 4, ...
+    pub fn read_se(&mut self) -> Result<i32, FlvError> {
+        let code = self.read_ue()? as i64;
+        let value = if code % 2 == 1 { (code + 1) / 2 } else { -(code / 2) };
+        Ok(value as i32)
+    }
+}
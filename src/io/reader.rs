@@ -1,38 +1,169 @@
+use crate::error::FlvError;
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Seek, SeekFrom};
 
+/// Default number of bytes pulled from a source at a time.
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// A source of flv data which is read in bounded chunks.
+/// Only `read_into` has to be implemented, everything else is built on it.
 pub trait IReader {
-    fn read_bit(&mut self) -> bool;
-    fn read_u8(&mut self) -> u8;
-    fn read_u16(&mut self) -> u16;
-    fn read_u32(&mut self) -> u32;
-    fn read_u64(&mut self) -> u64;
-    fn read_float32(&mut self) -> f32;
-    fn read_float64(&mut self) -> f64;
-    fn read_bytes(&mut self, size: usize) -> Vec<u8>;
-    fn read_string(&mut self) -> String;
+    /// Read at most `buffer.len()` bytes into `buffer`, and return how many were read.
+    /// `Ok(0)` means the source is exhausted.
+    fn read_into(&mut self, buffer: &mut [u8]) -> Result<usize, FlvError>;
+
+    /// Number of bytes the decoder pulls at a time.
+    fn chunk_size(&self) -> usize {
+        DEFAULT_CHUNK_SIZE
+    }
+
+    /// Jump to `position` bytes from the start of the source.
+    /// Sources which can not seek keep this default, which fails.
+    fn seek_to(&mut self, _position: u64) -> Result<(), FlvError> {
+        Err(FlvError::internal("The source does not support seeking."))
+    }
+
+    /// Fill the whole `buffer`.
+    /// Fails with `FlvError::InsufficientData` if the source ends first.
+    fn read_exact_into(&mut self, buffer: &mut [u8]) -> Result<(), FlvError> {
+        let mut filled = 0;
+        while filled < buffer.len() {
+            let read = self.read_into(&mut buffer[filled..])?;
+            if read == 0 {
+                return Err(FlvError::insufficient_data(buffer.len(), filled));
+            }
+            filled += read;
+        }
+        Ok(())
+    }
+
+    fn read_u8(&mut self) -> Result<u8, FlvError> {
+        let mut buffer = [0; 1];
+        self.read_exact_into(&mut buffer)?;
+        Ok(buffer[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16, FlvError> {
+        let mut buffer = [0; 2];
+        self.read_exact_into(&mut buffer)?;
+        Ok(u16::from_be_bytes(buffer))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, FlvError> {
+        let mut buffer = [0; 4];
+        self.read_exact_into(&mut buffer)?;
+        Ok(u32::from_be_bytes(buffer))
+    }
+
+    fn read_u64(&mut self) -> Result<u64, FlvError> {
+        let mut buffer = [0; 8];
+        self.read_exact_into(&mut buffer)?;
+        Ok(u64::from_be_bytes(buffer))
+    }
+
+    fn read_float32(&mut self) -> Result<f32, FlvError> {
+        Ok(f32::from_bits(self.read_u32()?))
+    }
+
+    fn read_float64(&mut self) -> Result<f64, FlvError> {
+        Ok(f64::from_bits(self.read_u64()?))
+    }
+
+    fn read_bytes(&mut self, size: usize) -> Result<Vec<u8>, FlvError> {
+        let mut buffer = vec![0; size];
+        self.read_exact_into(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Read a string prefixed with its u16 length, like the strings in script data.
+    fn read_string(&mut self) -> Result<String, FlvError> {
+        let length = self.read_u16()?;
+        Ok(String::from_utf8(self.read_bytes(length as usize)?)?)
+    }
 }
 
-pub struct LocalReader {
-    data: Vec<u8>,
-    file: File,
+/// Reads from any `std::io::Read` implementation.
+pub struct StreamReader<R: Read> {
+    source: R,
+    chunk_size: usize,
 }
 
-impl LocalReader {
-    pub fn new(filename: &str) -> LocalReader {
-        LocalReader {
-            data: Vec::new(),
-            file: File::open(filename).unwrap(),
+impl<R: Read> StreamReader<R> {
+    pub fn new(source: R) -> Self {
+        Self { source, chunk_size: DEFAULT_CHUNK_SIZE }
+    }
+
+    #[inline]
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    pub fn into_inner(self) -> R {
+        self.source
+    }
+}
+
+impl<R: Read> IReader for StreamReader<R> {
+    fn read_into(&mut self, buffer: &mut [u8]) -> Result<usize, FlvError> {
+        loop {
+            match self.source.read(buffer) {
+                Ok(read) => return Ok(read),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
         }
     }
 
-    pub fn read_file(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        self.file.read_to_end(&mut self.data)?;
-        Ok(())
+    #[inline]
+    fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+}
+
+/// Reads from any `std::io::Read` implementation which can also seek.
+pub struct SeekableReader<R: Read + Seek> {
+    inner: StreamReader<R>,
+}
+
+impl<R: Read + Seek> SeekableReader<R> {
+    pub fn new(source: R) -> Self {
+        Self { inner: StreamReader::new(source) }
+    }
+
+    #[inline]
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.inner = self.inner.with_chunk_size(chunk_size);
+        self
     }
 
-    pub fn read_bytes(&mut self, buffer: &mut [u8]) -> Result<(), std::io::Error> {
-        self.file.read_exact(buffer)?;
+    pub fn into_inner(self) -> R {
+        self.inner.into_inner()
+    }
+}
+
+impl<R: Read + Seek> IReader for SeekableReader<R> {
+    #[inline]
+    fn read_into(&mut self, buffer: &mut [u8]) -> Result<usize, FlvError> {
+        self.inner.read_into(buffer)
+    }
+
+    #[inline]
+    fn chunk_size(&self) -> usize {
+        self.inner.chunk_size()
+    }
+
+    fn seek_to(&mut self, position: u64) -> Result<(), FlvError> {
+        self.inner.source.seek(SeekFrom::Start(position))?;
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// Reads a local file chunk by chunk, without loading it into memory.
+pub type LocalReader = SeekableReader<File>;
+
+impl LocalReader {
+    pub fn open(filename: &str) -> Result<LocalReader, FlvError> {
+        Ok(SeekableReader::new(File::open(filename)?))
+    }
+}
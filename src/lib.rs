@@ -3,21 +3,55 @@ pub mod io;
 pub mod core;
 pub mod exchange;
 pub mod fmpeg;
+pub mod error;
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::core::IConsumable;
     use crate::exchange::RemuxedData;
+    use crate::error::FlvError;
     use crate::flv::decoder::Decoder;
     use crate::flv::tag::TagType;
     use crate::fmpeg::encoder::Encoder;
     use crate::fmpeg::mp4head::{ISerializable, U24};
-    use crate::fmpeg::remux_context::{AudioCodecType, RemuxContext, VideoCodecType};
+    use crate::fmpeg::mp4head::avc1_utils::AvcCBoxLike;
+    use crate::fmpeg::remux_context::{AudioCodecType, AudioTrackConfig, RemuxContext, VideoCodecType, VideoTrackConfig};
     use crate::io::bit::UIntParserEndian;
+    use crate::io::reader::StreamReader;
     use std::collections::{HashMap, VecDeque};
     use std::io::Write;
 
+    /// Append a tag and its PreviousTagSize to `flv`.
+    /// `tag_type` is the whole first byte, so the filter bit can be set as well.
+    fn push_tag(flv: &mut Vec<u8>, tag_type: u8, timestamp: u32, body: &[u8]) {
+        flv.push(tag_type);
+        flv.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+        flv.extend_from_slice(&timestamp.to_be_bytes()[1..]);
+        flv.extend_from_slice(&[(timestamp >> 24) as u8, 0, 0, 0]);
+        flv.extend_from_slice(body);
+        flv.extend_from_slice(&(body.len() as u32 + 11).to_be_bytes());
+    }
+
+    /// A 9 byte flv header with the given type flags, followed by the first PreviousTagSize.
+    fn flv_header(flags: u8) -> Vec<u8> {
+        vec![b'F', b'L', b'V', 1, flags, 0, 0, 0, 9, 0, 0, 0, 0]
+    }
+
+    /// Decode the flv header and the next `count` tags, without passing them on to the demuxer.
+    fn decode_tags(decoder: &mut Decoder, count: usize) -> Vec<crate::flv::tag::Tag> {
+        decoder.decode_header().unwrap();
+        (0..count).map(|_| {
+            decoder.drain_u32().unwrap();
+            decoder.decode_tag().unwrap()
+        }).collect()
+    }
+
+    /// A script data string, with its length filled in.
+    fn script_string(data: &str) -> crate::flv::script::ScriptDataString {
+        crate::flv::script::ScriptDataString { length: data.len() as u16, data: data.to_string() }
+    }
+
     #[test]
     fn it_works() {
         let byte = 0b10101011;
@@ -87,7 +121,7 @@ mod tests {
         let mut decoder = Decoder::new(VecDeque::from(buf));
         dbg!(decoder.decode_header().unwrap());
         for _ in 0..1 {
-            decoder.drain_u32();
+            decoder.drain_u32().unwrap();
             dbg!(decoder.decode_tag().unwrap());
         } /**/
 
@@ -163,21 +197,21 @@ mod tests {
         remux_context.has_video = true;
         remux_context.audio_codec_id = 2;
         remux_context.audio_codec_type = AudioCodecType::Aac;
-        remux_context.audio_sample_rate = 48000;
         remux_context.audio_data_rate = 128;
-        remux_context.audio_channels = 2;
+        remux_context.audio_tracks.insert(0, AudioTrackConfig::new(AudioCodecType::Aac, 48000, 2));
         remux_context.video_codec_id = 7;
         remux_context.video_codec_type = VideoCodecType::Avc1;
+        remux_context.video_tracks.insert(0, VideoTrackConfig::new(VideoCodecType::Avc1, AvcCBoxLike::AvcCBoxLike(vec![])));
         remux_context.major_brand = "isom".to_string();
         remux_context.minor_version = 512.to_string();
         remux_context.compatible_brands = vec!["isom".to_string(), "iso6".to_string(), "avc1".to_string(), "mp41".to_string()];
 
         Encoder::encode_ftyp(&remux_context);
-        Encoder::encode_moov(&remux_context);
+        Encoder::encode_moov(&remux_context).unwrap();
 
         let mut vec = vec![];
         vec.append(&mut Encoder::encode_ftyp(&remux_context).serialize());
-        vec.append(&mut Encoder::encode_moov(&remux_context).serialize());
+        vec.append(&mut Encoder::encode_moov(&remux_context).unwrap().serialize());
 
         let mut write_file = std::fs::File::create("D:/out.mp4").unwrap();
         write_file.write(&vec).unwrap();
@@ -206,6 +240,12 @@ mod tests {
                 RemuxedData::EndOfSequence(_) => {
                     break;
                 }
+                RemuxedData::Discontinuity(_) => {
+                    continue;
+                }
+                RemuxedData::Reinitialization(_) => {
+                    continue;
+                }
             };
             let size = output_file.write(&data).unwrap();
             buf_written += size;
@@ -216,4 +256,1362 @@ mod tests {
 
         println!("Done.");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn decoder_survives_truncated_and_garbage_input() {
+        let mut flv = vec![b'F', b'L', b'V', 1, 0b101, 0, 0, 0, 9];
+        flv.extend_from_slice(&[0, 0, 0, 0]);
+        // an aac tag declaring 5 bytes of data, of which only 2 have arrived.
+        flv.extend_from_slice(&[8, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 0xAF, 0x01]);
+
+        let mut decoder = Decoder::new(VecDeque::from(flv));
+        decoder.decode_header().unwrap();
+        let err = decoder.decode_body_once().unwrap_err();
+        assert!(err.is_insufficient_data());
+
+        // nothing was drained, so the tag decodes as soon as the rest is pushed.
+        decoder.push_bytes(&[0x12, 0x34, 0x56]);
+        decoder.decode_body_once().unwrap();
+
+        // a script tag whose string claims more bytes than the tag holds.
+        decoder.push_bytes(&[0, 0, 0, 16]);
+        decoder.push_bytes(&[18, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, 2, 0xFF, 0xFF, b'o']);
+        let err = decoder.decode_body_once().unwrap_err();
+        assert!(matches!(err, FlvError::MalformedFlv { .. }));
+        assert_eq!(err.context().tag_index, Some(1));
+        assert_eq!(err.context().timestamp, Some(0));
+        // the string length is read 3 bytes into the body of the second tag.
+        assert_eq!(err.context().byte_offset, Some(13 + 16 + 4 + 11 + 3));
+
+        // well-framed tags carrying random bodies, so the garbage reaches the tag parsers.
+        let mut seed = 0x2545F491u32;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed
+        };
+        for _ in 0..256 {
+            let mut stream = vec![b'F', b'L', b'V', 1, 0b101, 0, 0, 0, 9, 0, 0, 0, 0];
+            for _ in 0..8 {
+                let tag_type = [8u8, 9, 18][(next() % 3) as usize];
+                let size = next() % 64;
+                stream.push(tag_type);
+                stream.extend_from_slice(&size.to_be_bytes()[1..]);
+                stream.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0]);
+                stream.extend((0..size).map(|_| next() as u8));
+                stream.extend_from_slice(&(size + 11).to_be_bytes());
+            }
+            let mut decoder = Decoder::new(VecDeque::from(stream));
+            decoder.start().unwrap();
+            let _ = decoder.run();
+        }
+    }
+
+    #[test]
+    fn decoder_resyncs_after_corrupt_tag() {
+        // an mp3 audio tag, 2 bytes of data.
+        let tag = [8u8, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0x2F, 0xFF];
+        let mut flv = vec![b'F', b'L', b'V', 1, 0b101, 0, 0, 0, 9, 0, 0, 0, 0];
+        flv.extend_from_slice(&tag);
+        flv.extend_from_slice(&13u32.to_be_bytes());
+        // garbage in the middle of the stream, including a damaged previous tag size.
+        flv.extend_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF, 0x12, 0x09, 0x00]);
+        flv.extend_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF]);
+        flv.extend_from_slice(&tag);
+        flv.extend_from_slice(&13u32.to_be_bytes());
+        flv.extend_from_slice(&tag);
+        flv.extend_from_slice(&13u32.to_be_bytes());
+
+        let mut decoder = Decoder::new(VecDeque::from(flv));
+        decoder.decode_header().unwrap();
+        decoder.start().unwrap();
+        decoder.continue_decoding().unwrap();
+        assert_eq!(decoder.tag_index(), 3);
+        // the 7 garbage bytes plus the damaged previous tag size.
+        assert_eq!(decoder.skipped_bytes(), 7 + 4);
+    }
+
+
+    #[test]
+    fn decoder_pulls_from_reader_in_chunks() {
+        let tag = [8u8, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0x2F, 0xFF];
+        let mut flv = vec![b'F', b'L', b'V', 1, 0b101, 0, 0, 0, 9, 0, 0, 0, 0];
+        for _ in 0..10 {
+            flv.extend_from_slice(&tag);
+            flv.extend_from_slice(&13u32.to_be_bytes());
+        }
+
+        // chunks smaller than a tag, so tags are split across pulls.
+        let mut decoder = Decoder::new(VecDeque::new());
+        decoder.set_source(StreamReader::new(std::io::Cursor::new(flv)).with_chunk_size(5));
+        decoder.start().unwrap();
+        decoder.run().unwrap();
+        while !decoder.is_source_exhausted() {
+            decoder.continue_decoding().unwrap();
+        }
+        assert_eq!(decoder.tag_index(), 10);
+        assert_eq!(decoder.skipped_bytes(), 0);
+    }
+
+
+    #[test]
+    fn decoder_seeks_to_preceding_keyframe() {
+        fn amf_string(out: &mut Vec<u8>, value: &str) {
+            out.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            out.extend_from_slice(value.as_bytes());
+        }
+        fn amf_numbers(out: &mut Vec<u8>, values: &[f64]) {
+            out.push(10);
+            out.extend_from_slice(&(values.len() as u32).to_be_bytes());
+            for value in values {
+                out.push(0);
+                out.extend_from_slice(&value.to_be_bytes());
+            }
+        }
+
+        // the metadata size does not depend on the positions, so lay the file out once to find them.
+        let build = |positions: &[f64]| {
+            let mut script = vec![2];
+            amf_string(&mut script, "onMetaData");
+            script.extend_from_slice(&[8, 0, 0, 0, 1]);
+            amf_string(&mut script, "keyframes");
+            script.push(3);
+            amf_string(&mut script, "times");
+            amf_numbers(&mut script, &[0.0, 1.0]);
+            amf_string(&mut script, "filepositions");
+            amf_numbers(&mut script, positions);
+            script.extend_from_slice(&[0, 0, 9, 0, 0, 9]);
+
+            let mut flv = vec![b'F', b'L', b'V', 1, 0b101, 0, 0, 0, 9, 0, 0, 0, 0];
+            push_tag(&mut flv, 18, 0, &script);
+            let mut positions = vec![];
+            for timestamp in [0, 500, 1000, 1500] {
+                positions.push(flv.len() as f64);
+                push_tag(&mut flv, 8, timestamp, &[0x2F, 0xFF]);
+            }
+            (flv, positions)
+        };
+        let (_, positions) = build(&[0.0, 0.0]);
+        let (flv, _) = build(&[positions[0], positions[2]]);
+
+        let mut decoder = Decoder::from_seekable_reader(std::io::Cursor::new(flv));
+        decoder.start().unwrap();
+        decoder.run().unwrap();
+        while !decoder.is_source_exhausted() {
+            decoder.continue_decoding().unwrap();
+        }
+        assert_eq!(decoder.tag_index(), 5);
+        assert_eq!(decoder.keyframes().unwrap().file_positions, vec![positions[0] as u64, positions[2] as u64]);
+
+        // 1.2s falls between the keyframes, so decoding resumes at the one at 1s.
+        assert_eq!(decoder.seek_to(1200).unwrap(), 1000);
+        while !decoder.is_source_exhausted() {
+            decoder.continue_decoding().unwrap();
+        }
+        assert_eq!(decoder.tag_index(), 7);
+        assert_eq!(decoder.skipped_bytes(), 0);
+    }
+
+    #[test]
+    fn indexer_finds_keyframes_without_metadata() {
+        use crate::flv::indexer::{IndexedKeyframes, KeyframeEntry, KeyframeIndexer};
+
+        let mut flv = vec![b'F', b'L', b'V', 1, 0b101, 0, 0, 0, 9, 0, 0, 0, 0];
+        let mut expected = vec![];
+        for (i, timestamp) in [0u32, 40, 80, 120, 160, 0x0100_0000].into_iter().enumerate() {
+            push_tag(&mut flv, 8, timestamp, &[0x2F, 0xFF]);
+            let keyframe = i % 3 != 1;
+            if keyframe {
+                expected.push(KeyframeEntry { timestamp, offset: flv.len() as u64, frame_type: 1 });
+            }
+            push_tag(&mut flv, 9, timestamp, &[if keyframe { 0x17 } else { 0x27 }, 1, 0, 0, 0]);
+        }
+        // a truncated trailing keyframe must not be indexed.
+        let full_size = flv.len();
+        flv.extend_from_slice(&[9, 0, 0, 32, 0, 0, 0, 0, 0, 0, 0, 0x17]);
+
+        let indexed = KeyframeIndexer::scan(&mut std::io::Cursor::new(&flv)).unwrap();
+        assert_eq!(indexed.source_size, full_size as u64 + 12);
+        assert_eq!(indexed.entries, expected);
+        assert_eq!(indexed.nearest_preceding(130).unwrap().timestamp, 120);
+        assert_eq!(indexed.nearest_preceding(0x0100_0001).unwrap().timestamp, 0x0100_0000);
+
+        let mut sidecar = vec![];
+        indexed.write_to(&mut sidecar).unwrap();
+        assert_eq!(IndexedKeyframes::read_from(&mut sidecar.as_slice()).unwrap(), indexed);
+        assert!(matches!(IndexedKeyframes::deserialize(&sidecar[..sidecar.len() - 1]), Err(FlvError::MalformedFlv { .. })));
+
+        // the scanned index drives seeking just like one from onMetaData.
+        flv.truncate(full_size);
+        let mut decoder = Decoder::from_seekable_reader(std::io::Cursor::new(flv));
+        decoder.set_keyframes((&indexed).into());
+        decoder.start().unwrap();
+        decoder.run().unwrap();
+        assert_eq!(decoder.seek_to(100).unwrap(), 80);
+        assert_eq!(decoder.seek_to(130).unwrap(), 120);
+        let before = decoder.tag_index();
+        while !decoder.is_source_exhausted() {
+            decoder.continue_decoding().unwrap();
+        }
+        // the keyframe at 120 and the four tags after it.
+        assert_eq!(decoder.tag_index() - before, 5);
+        assert_eq!(decoder.skipped_bytes(), 0);
+    }
+
+    #[test]
+    fn decoder_decrypts_filtered_tags() {
+        use crate::flv::crypto::{Aes128, Cbc};
+        use crate::flv::header::FilterParameters;
+        use crate::flv::tag::{EncryptedTagBody, NormalTagBody, TagBody};
+
+        // the AES-128 example from FIPS-197, appendix C.1.
+        let key: [u8; 16] = std::array::from_fn(|i| i as u8);
+        let mut block: [u8; 16] = std::array::from_fn(|i| (i as u8) * 0x11);
+        let cipher = Aes128::new(&key);
+        cipher.encrypt_block(&mut block);
+        assert_eq!(block, [0x69, 0xc4, 0xe0, 0xd8, 0x6a, 0x7b, 0x04, 0x30, 0xd8, 0xcd, 0xb7, 0x80, 0x70, 0xb4, 0xc5, 0x5a]);
+        cipher.decrypt_block(&mut block);
+        assert_eq!(block, std::array::from_fn(|i| (i as u8) * 0x11));
+
+        let iv = [0x42u8; 16];
+        let frame = [0x21u8, 0x10, 0x04, 0x60, 0x8C, 0x1C];
+        let encrypted = Cbc::encrypt(&key, &iv, &frame);
+        assert_eq!(encrypted.len(), 16);
+
+        let mut flv = vec![b'F', b'L', b'V', 1, 0b101, 0, 0, 0, 9, 0, 0, 0, 0];
+        // a raw aac frame under the "Encryption" filter, the audio header stays in the clear.
+        let mut body = vec![0xAF, 0x01, 1, 0, 10];
+        body.extend_from_slice(b"Encryption");
+        body.extend_from_slice(&[0, 0, 16]);
+        body.extend_from_slice(&iv);
+        body.extend_from_slice(&encrypted);
+        push_tag(&mut flv, 0x20 | 8, 0, &body);
+        // an avc frame under the "SE" filter, which leaves this access unit unencrypted.
+        let mut body = vec![0x17, 0x01, 0, 0, 0, 1, 0, 2, b'S', b'E', 0, 0, 1, 0x00];
+        body.extend_from_slice(&frame);
+        push_tag(&mut flv, 0x20 | 9, 40, &body);
+
+        let decode = |key: Option<[u8; 16]>| {
+            let mut decoder = Decoder::new(VecDeque::from(flv.clone()));
+            if let Some(key) = key {
+                decoder.set_decryption_key(key);
+            }
+            decoder.decode_header().unwrap();
+            decoder.drain_u32().unwrap();
+            let audio = decoder.decode_tag();
+            decoder.drain_u32().unwrap();
+            let video = decoder.decode_tag().unwrap();
+            (audio, video)
+        };
+
+        let (audio, video) = decode(Some(key));
+        let audio = audio.unwrap();
+        assert!(audio.filter);
+        assert_eq!(audio.encryption_tag_header.as_ref().unwrap().filter_name, "Encryption");
+        assert_eq!(audio.filter_parameters.as_ref().unwrap().iv(), Some(&iv));
+        assert!(matches!(audio.tag_body, TagBody::Normal(NormalTagBody::Audio(ref data)) if data.iter().eq(frame.iter())));
+        assert!(matches!(video.filter_parameters, Some(FilterParameters::SelectiveEncryptionFilter(ref params)) if !params.encrypted_au));
+        assert!(matches!(video.tag_body, TagBody::Normal(NormalTagBody::Video(ref data)) if data.iter().eq(frame.iter())));
+
+        // without a key the body is kept as it is.
+        let (audio, _) = decode(None);
+        assert!(matches!(audio.unwrap().tag_body, TagBody::Encrypted(EncryptedTagBody::Audio(ref data)) if *data == encrypted));
+
+        // a wrong key shows up as broken padding.
+        let (audio, _) = decode(Some([0x24; 16]));
+        assert!(matches!(audio.unwrap_err(), FlvError::MalformedFlv { .. }));
+    }
+
+    #[test]
+    fn enhanced_video_tags_configure_their_sample_entries() {
+        use crate::exchange::VideoCodecConfig;
+        use crate::flv::header::{TagHeader, VideoPacketType};
+        use crate::fmpeg::mp4head::HandlerType;
+        use crate::fmpeg::parser::{Avc1ParseResult, ExVideoParseResult, KeyframeType, Parser, VideoParseResult};
+
+        let hvcc = [1u8, 0x01, 0x60, 0, 0, 0, 0x90, 0, 0, 0, 0, 0, 93, 0xF0, 0, 0xFC, 0xFD, 0xF8, 0xF8, 0, 0, 0x0F, 0];
+        let mut flv = vec![b'F', b'L', b'V', 1, 0b101, 0, 0, 0, 9, 0, 0, 0, 0];
+        // keyframe + SequenceStart, then keyframe + CodedFrames with a cts, then inter frame + CodedFramesX.
+        let mut body = vec![0x90];
+        body.extend_from_slice(b"hvc1");
+        body.extend_from_slice(&hvcc);
+        push_tag(&mut flv, 9, 0, &body);
+        let mut body = vec![0x91];
+        body.extend_from_slice(b"hvc1");
+        body.extend_from_slice(&[0, 0, 40, 0, 0, 0, 2, 0x26, 0x01]);
+        push_tag(&mut flv, 9, 0, &body);
+        let mut body = vec![0xA3];
+        body.extend_from_slice(b"hvc1");
+        body.extend_from_slice(&[0, 0, 0, 2, 0x02, 0x01]);
+        push_tag(&mut flv, 9, 40, &body);
+
+        let mut decoder = Decoder::new(VecDeque::from(flv));
+        decoder.decode_header().unwrap();
+        let mut tags = vec![];
+        for _ in 0..3 {
+            decoder.drain_u32().unwrap();
+            tags.push(decoder.decode_tag().unwrap());
+        }
+
+        let TagHeader::Video(ref header) = tags[1].tag_header else { panic!("not a video header") };
+        assert!(header.is_ex_header);
+        assert_eq!(header.frame_type, 1);
+        assert_eq!(header.fourcc, Some(*b"hvc1"));
+        assert_eq!(header.video_packet_type, Some(VideoPacketType::CodedFrames));
+        assert_eq!(header.composition_time_offset, Some(40));
+
+        let mut ctx = RemuxContext::new();
+        let parsed = Parser::parse_video(&tags[0]).unwrap();
+        assert!(matches!(parsed, VideoParseResult::Hvc1(ExVideoParseResult::SequenceStart(_))));
+        let mut conf = ctx.configure_video_metadata(&parsed).unwrap();
+        assert_eq!(conf.video_conf(), "hvc1.1.6.L93.90");
+        assert!(matches!(ctx.video_tracks[&0].codec_type, VideoCodecType::Hvc1));
+
+        let minf = Encoder::encode_minf(&ctx, HandlerType::Video, 0).unwrap().serialize();
+        let has = |needle: &[u8]| minf.windows(4).any(|window| window == needle);
+        assert!(has(b"hvc1") && has(b"hvcC") && !has(b"avc1"));
+
+        // frames of every codec are remuxed like avc ones.
+        for (tag, keyframe, size) in [(&tags[1], true, 6), (&tags[2], false, 6)] {
+            let Some(Avc1ParseResult::AvcNalu(frame)) = Parser::parse_video(tag).unwrap().into_avc_like() else { panic!("not a frame") };
+            assert_eq!(frame.keyframe_type == KeyframeType::Keyframe, keyframe);
+            assert_eq!(frame.payload.len(), size);
+        }
+
+        assert_eq!(VideoCodecConfig::from_av1c(&[0x81, 0x04, 0x0C, 0x00]).unwrap().video_conf(), "av01.0.04M.08");
+        assert_eq!(VideoCodecConfig::from_av1c(&[0x81, 0x48, 0xC0, 0x00]).unwrap().video_conf(), "av01.2.08H.10");
+        assert_eq!(VideoCodecConfig::from_vpcc(&[1, 0, 0, 0, 2, 31, 0xA2, 1, 1, 1, 0, 0]).unwrap().video_conf(), "vp09.02.31.10");
+    }
+
+    #[test]
+    fn enhanced_audio_tags_configure_their_sample_entries() {
+        use crate::exchange::AudioCodecConfig;
+        use crate::flv::header::{AudioPacketType, TagHeader};
+        use crate::fmpeg::mp4head::HandlerType;
+        use crate::fmpeg::parser::{AudioParseResult, ExAudioParseResult, Parser};
+
+        let mut flv = vec![b'F', b'L', b'V', 1, 0b101, 0, 0, 0, 9, 0, 0, 0, 0];
+        // opus SequenceStart with a stereo OpusHead, then a 20ms celt frame.
+        let mut body = vec![0x90];
+        body.extend_from_slice(b"Opus");
+        body.extend_from_slice(b"OpusHead");
+        body.extend_from_slice(&[1, 2, 0x38, 0x01, 0x80, 0xBB, 0, 0, 0, 0, 0]);
+        push_tag(&mut flv, 8, 0, &body);
+        let mut body = vec![0x91];
+        body.extend_from_slice(b"Opus");
+        body.extend_from_slice(&[0xF8, 0xFF, 0xFE]);
+        push_tag(&mut flv, 8, 0, &body);
+        // a 48kHz 5.1 ac-3 syncframe header, and a flac frame of 4096 samples.
+        let mut body = vec![0x91];
+        body.extend_from_slice(b"ac-3");
+        body.extend_from_slice(&[0x0B, 0x77, 0, 0, 0x1C, 0x40, 0xE1, 0]);
+        push_tag(&mut flv, 8, 0, &body);
+        let mut body = vec![0x91];
+        body.extend_from_slice(b"fLaC");
+        body.extend_from_slice(&[0xFF, 0xF8, 0xC9, 0x18, 0x00, 0]);
+        push_tag(&mut flv, 8, 0, &body);
+
+        let mut decoder = Decoder::new(VecDeque::from(flv));
+        decoder.decode_header().unwrap();
+        let mut tags = vec![];
+        for _ in 0..4 {
+            decoder.drain_u32().unwrap();
+            tags.push(decoder.decode_tag().unwrap());
+        }
+
+        let TagHeader::Audio(ref header) = tags[0].tag_header else { panic!("not an audio header") };
+        assert!(header.is_ex_header());
+        assert_eq!(header.fourcc, Some(*b"Opus"));
+        assert_eq!(header.audio_packet_type, Some(AudioPacketType::SequenceStart));
+
+        let mut ctx = RemuxContext::new();
+        let mut conf = ctx.configure_audio_metadata(&Parser::parse_audio(&tags[0]).unwrap()).unwrap().unwrap();
+        assert_eq!(conf.audio_conf().unwrap(), "opus");
+        assert_eq!((ctx.audio_tracks[&0].sample_rate, ctx.audio_tracks[&0].channels), (48000, 2));
+        let minf = Encoder::encode_minf(&ctx, HandlerType::Audio, 0).unwrap().serialize();
+        assert!(minf.windows(4).any(|window| window == b"Opus") && minf.windows(4).any(|window| window == b"dOps"));
+
+        let AudioParseResult::Opus(ExAudioParseResult::CodedFrames(frame)) = Parser::parse_audio(&tags[1]).unwrap() else { panic!("not an opus frame") };
+        assert_eq!(frame.sample_count, 960);
+        assert_eq!(frame.payload.len(), 3);
+
+        // ac-3 has no sequence start, its first frame configures the track.
+        let mut ctx = RemuxContext::new();
+        let parsed = Parser::parse_audio(&tags[2]).unwrap();
+        let mut conf = ctx.configure_audio_metadata(&parsed).unwrap().unwrap();
+        assert_eq!(conf.audio_conf().unwrap(), "ac-3");
+        assert!(matches!(ctx.audio_tracks[&0].codec_type, AudioCodecType::Ac3));
+        assert_eq!((ctx.audio_tracks[&0].sample_rate, ctx.audio_tracks[&0].channels), (48000, 6));
+        let AudioParseResult::Ac3(ExAudioParseResult::CodedFrames(frame)) = parsed else { panic!("not an ac-3 frame") };
+        assert_eq!(frame.sample_count, 1536);
+        let minf = Encoder::encode_minf(&ctx, HandlerType::Audio, 0).unwrap().serialize();
+        let dac3 = minf.windows(4).position(|window| window == b"dac3").unwrap();
+        assert_eq!(minf[dac3 + 4..dac3 + 7], [0x10, 0x3D, 0xC0]);
+
+        let AudioParseResult::Flac(ExAudioParseResult::CodedFrames(frame)) = Parser::parse_audio(&tags[3]).unwrap() else { panic!("not a flac frame") };
+        assert_eq!(frame.sample_count, 4096);
+        assert_eq!(AudioCodecConfig::new(AudioCodecType::Eac3, 0).audio_conf().unwrap(), "ec-3");
+    }
+
+    #[test]
+    fn truncated_ac3_syncframes_are_rejected() {
+        use crate::fmpeg::parser::Parser;
+
+        // the sync word, and then the stream ends in the middle of crc1.
+        let mut body = vec![0x91];
+        body.extend_from_slice(b"ac-3");
+        body.extend_from_slice(&[0x0B, 0x77, 0x00]);
+        let mut flv = flv_header(0b100);
+        push_tag(&mut flv, 8, 0, &body);
+
+        let tag = decode_tags(&mut Decoder::new(VecDeque::from(flv.clone())), 1).remove(0);
+        assert!(matches!(Parser::parse_audio(&tag), Err(FlvError::MalformedFlv { .. })));
+
+        // neither the remuxer nor the demux-only iterator panics on it.
+        let mut decoder = Decoder::new(VecDeque::from(flv.clone()));
+        decoder.start().unwrap();
+        let _ = decoder.run();
+        assert!(Decoder::new(VecDeque::from(flv)).into_frames().any(|item| item.is_err()));
+    }
+
+    #[test]
+    fn legacy_hevc_tags_pick_their_sample_entry() {
+        use crate::flv::header::TagHeader;
+        use crate::fmpeg::mp4head::HandlerType;
+        use crate::fmpeg::parser::{Avc1ParseResult, Parser};
+
+        let hvcc = [1u8, 0x01, 0x60, 0, 0, 0, 0x90, 0, 0, 0, 0, 0, 93, 0xF0, 0, 0xFC, 0xFD, 0xF8, 0xF8, 0, 0, 0x0F];
+        // one array each for the VPS, SPS and PPS, with a 2 byte nal unit.
+        let mut with_parameter_sets = hvcc.to_vec();
+        with_parameter_sets.push(3);
+        for nal_unit_type in [32u8, 33, 34] {
+            with_parameter_sets.extend_from_slice(&[0x80 | nal_unit_type, 0, 1, 0, 2, nal_unit_type << 1, 1]);
+        }
+        let mut without_parameter_sets = hvcc.to_vec();
+        without_parameter_sets.push(0);
+
+        for (record, sample_entry_type) in [(with_parameter_sets, b"hvc1"), (without_parameter_sets, b"hev1")] {
+            let mut flv = vec![b'F', b'L', b'V', 1, 0b101, 0, 0, 0, 9, 0, 0, 0, 0];
+            // keyframe + codec id 12, a sequence header and then a nalu with a cts of 40.
+            let mut body = vec![0x1C, 0, 0, 0, 0];
+            body.extend_from_slice(&record);
+            push_tag(&mut flv, 9, 0, &body);
+            push_tag(&mut flv, 9, 0, &[0x1C, 1, 0, 0, 40, 0, 0, 0, 2, 0x26, 0x01]);
+
+            let mut decoder = Decoder::new(VecDeque::from(flv));
+            decoder.decode_header().unwrap();
+            let mut tags = vec![];
+            for _ in 0..2 {
+                decoder.drain_u32().unwrap();
+                tags.push(decoder.decode_tag().unwrap());
+            }
+            let TagHeader::Video(ref header) = tags[1].tag_header else { panic!("not a video header") };
+            assert_eq!((header.codec_id, header.avc_packet_type, header.composition_time_offset), (12, Some(1), Some(40)));
+
+            let mut ctx = RemuxContext::new();
+            let mut conf = ctx.configure_video_metadata(&Parser::parse_video(&tags[0]).unwrap()).unwrap();
+            assert_eq!(conf.video_conf(), format!("{}.1.6.L93.90", String::from_utf8_lossy(sample_entry_type)));
+            let minf = Encoder::encode_minf(&ctx, HandlerType::Video, 0).unwrap().serialize();
+            assert!(minf.windows(4).any(|window| window == sample_entry_type) && minf.windows(4).any(|window| window == b"hvcC"));
+
+            let Some(Avc1ParseResult::AvcNalu(frame)) = Parser::parse_video(&tags[1]).unwrap().into_avc_like() else { panic!("not a frame") };
+            assert_eq!((frame.composition_time_offset, frame.payload.len()), (40, 6));
+        }
+    }
+
+    #[test]
+    fn multitrack_tags_get_a_trak_per_track() {
+        use crate::flv::header::{AvMultitrackType, TagHeader};
+        use crate::fmpeg::parser::{Avc1ParseResult, Parser};
+        use crate::fmpeg::remux_context::TrackType;
+
+        let opus_head = |channels: u8| {
+            let mut head = b"OpusHead".to_vec();
+            head.extend_from_slice(&[1, channels, 0x38, 0x01, 0x80, 0xBB, 0, 0, 0, 0, 0]);
+            head
+        };
+        let hvcc = [1u8, 0x01, 0x60, 0, 0, 0, 0x90, 0, 0, 0, 0, 0, 93, 0xF0, 0, 0xFC, 0xFD, 0xF8, 0xF8, 0, 0, 0x0F, 0];
+
+        let mut flv = vec![b'F', b'L', b'V', 1, 0b101, 0, 0, 0, 9, 0, 0, 0, 0];
+        // ManyTracks + SequenceStart, opus tracks 0 and 1, each with its size.
+        let mut body = vec![0x95, 0x10];
+        body.extend_from_slice(b"Opus");
+        for (track_id, channels) in [(0u8, 2u8), (1, 1)] {
+            let head = opus_head(channels);
+            body.push(track_id);
+            body.extend_from_slice(&(head.len() as u32).to_be_bytes()[1..]);
+            body.extend_from_slice(&head);
+        }
+        push_tag(&mut flv, 8, 0, &body);
+        // OneTrack + SequenceStart, then OneTrack + CodedFrames with the cts inside the track, hevc track 2.
+        let mut body = vec![0x96, 0x00];
+        body.extend_from_slice(b"hvc1");
+        body.push(2);
+        body.extend_from_slice(&hvcc);
+        push_tag(&mut flv, 9, 0, &body);
+        let mut body = vec![0x96, 0x01];
+        body.extend_from_slice(b"hvc1");
+        body.extend_from_slice(&[2, 0, 0, 40, 0, 0, 0, 2, 0x26, 0x01]);
+        push_tag(&mut flv, 9, 0, &body);
+
+        let mut decoder = Decoder::new(VecDeque::from(flv.clone()));
+        let flv_header = decoder.decode_header().unwrap();
+        let mut tags = vec![];
+        for _ in 0..3 {
+            decoder.drain_u32().unwrap();
+            tags.push(decoder.decode_tag().unwrap());
+        }
+
+        let TagHeader::Audio(ref header) = tags[0].tag_header else { panic!("not an audio header") };
+        assert_eq!(header.multitrack_type, Some(AvMultitrackType::ManyTracks));
+
+        // the sequence starts alone configure every track, once both track types have shown up.
+        let mut ctx = RemuxContext::new();
+        ctx.parse_flv_header(&flv_header);
+        ctx.probe_track(&TrackType::Audio);
+        ctx.probe_track(&TrackType::Video);
+        let audio = Parser::parse_audio_tracks(&tags[0]).unwrap();
+        assert_eq!(audio.iter().map(|(track_id, _)| *track_id).collect::<Vec<_>>(), [0, 1]);
+        for (track_id, parsed) in &audio {
+            assert_eq!(ctx.configure_audio_track(*track_id, parsed).unwrap().unwrap().track_id, *track_id);
+        }
+        assert_eq!(ctx.audio_tracks[&1].channels, 1);
+        assert!(!ctx.is_configured());
+
+        let (track_id, parsed) = Parser::parse_video_tracks(&tags[1]).unwrap().pop().unwrap();
+        assert_eq!(track_id, 2);
+        ctx.configure_video_track(track_id, &parsed).unwrap();
+        assert!(ctx.is_configured());
+
+        let (_, parsed) = Parser::parse_video_tracks(&tags[2]).unwrap().pop().unwrap();
+        let Some(Avc1ParseResult::AvcNalu(frame)) = parsed.into_avc_like() else { panic!("not a frame") };
+        assert_eq!(frame.composition_time_offset, 40);
+        assert_eq!(frame.payload.len(), 6);
+
+        // video track 2 and audio tracks 0 and 1, each with its own trex.
+        let moov = Encoder::encode_moov(&ctx).unwrap();
+        assert_eq!(moov.tracks.iter().map(|track| track.track_id()).collect::<Vec<_>>(), [5, 2, 4]);
+
+        // the whole pipeline sends the same initialization segment, once all three are configured.
+        let mut decoder = Decoder::new(VecDeque::from(flv));
+        decoder.start().unwrap();
+        decoder.run().unwrap();
+        let RemuxedData::Header(header) = decoder.consume().unwrap() else { panic!("no initialization segment") };
+        let count = |needle: &[u8]| header.windows(4).filter(|window| *window == needle).count();
+        assert_eq!((count(b"trak"), count(b"trex"), count(b"dOps")), (3, 3, 2));
+    }
+
+    #[test]
+    fn timestamp_discontinuities_are_rebased_or_signalled() {
+        use crate::flv::timestamp::{DiscontinuityKind, TimestampPolicy, TimestampTracker};
+
+        // the publisher reconnects, and starts over from 0.
+        let stream = [(TagType::Video, 10000), (TagType::Audio, 10020), (TagType::Video, 10040), (TagType::Audio, 10060), (TagType::Video, 10080),
+            (TagType::Video, 0), (TagType::Audio, 10), (TagType::Video, 40)];
+
+        let mut tracker = TimestampTracker::new(TimestampPolicy::Rebase);
+        let rebased: Vec<_> = stream.iter().map(|(tag_type, ts)| tracker.track(tag_type, *ts)).collect();
+        assert!(rebased.iter().all(|(_, discontinuity)| discontinuity.is_none()));
+        // the video steps 40ms past the latest tag, and the audio keeps its distance to it.
+        assert_eq!(rebased.iter().map(|(ts, _)| *ts).collect::<Vec<_>>(), [10000, 10020, 10040, 10060, 10080, 10120, 10130, 10160]);
+
+        let mut tracker = TimestampTracker::new(TimestampPolicy::Signal);
+        let signalled: Vec<_> = stream.iter().map(|(tag_type, ts)| tracker.track(tag_type, *ts)).collect();
+        assert_eq!(signalled.iter().map(|(ts, _)| *ts).collect::<Vec<_>>(), stream.iter().map(|(_, ts)| *ts).collect::<Vec<_>>());
+        let breaks: Vec<_> = signalled.iter().filter_map(|(_, discontinuity)| discontinuity.as_ref()).collect();
+        assert_eq!(breaks.len(), 2);
+        assert!(breaks.iter().all(|discontinuity| discontinuity.kind == DiscontinuityKind::BackwardJump));
+        assert_eq!((breaks[0].previous, breaks[0].current), (10080, 0));
+
+        // a wrap is reported, but the timestamps simply go on.
+        let mut tracker = TimestampTracker::new(TimestampPolicy::Signal);
+        tracker.track(&TagType::Video, u32::MAX - 20);
+        let (ts, discontinuity) = tracker.track(&TagType::Video, 20);
+        assert_eq!((ts, discontinuity.unwrap().kind), (20, DiscontinuityKind::Wrap));
+        assert!(tracker.track(&TagType::Video, 60).1.is_none());
+
+        // nothing is touched by default.
+        let mut tracker = TimestampTracker::default();
+        tracker.track(&TagType::Video, 10080);
+        let (ts, discontinuity) = tracker.track(&TagType::Video, 0);
+        assert!(ts == 0 && discontinuity.is_none());
+    }
+
+    #[test]
+    fn decoder_validates_and_skips_the_flv_header() {
+        // a header declaring 4 extra bytes, arriving in pieces.
+        let mut decoder = Decoder::new(VecDeque::from(vec![b'F', b'L']));
+        decoder.start().unwrap();
+        decoder.run().unwrap();
+        assert!(decoder.decode_header().unwrap_err().is_insufficient_data());
+        decoder.push_bytes(&[b'V', 1, 0b101, 0, 0, 0, 13, 0xDE, 0xAD]);
+        assert!(decoder.decode_header().unwrap_err().is_insufficient_data());
+        assert_eq!(decoder.stream_offset(), 0);
+
+        let mut rest = vec![0xBE, 0xEF, 0, 0, 0, 0];
+        push_tag(&mut rest, 8, 0, &[0x2F, 0xFF, 0xFB, 0x90, 0x00]);
+        decoder.push_bytes(&rest);
+        decoder.continue_decoding().unwrap();
+        assert_eq!(decoder.tag_index(), 1);
+        // the header, the first PreviousTagSize and the tag, the trailing PreviousTagSize goes with the next one.
+        assert_eq!(decoder.stream_offset(), 13 + 4 + 11 + 5);
+
+        // anything else is turned away as soon as the signature is off.
+        let mut unknown_version = flv_header(0b101);
+        unknown_version[3] = 0;
+        for input in [b"FLX".to_vec(), b"G".to_vec(), unknown_version] {
+            let mut decoder = Decoder::new(VecDeque::from(input));
+            assert!(matches!(decoder.decode_header().unwrap_err(), FlvError::MalformedFlv { .. }));
+            decoder.start().unwrap();
+            assert!(decoder.run().is_err());
+        }
+    }
+
+    #[test]
+    fn tag_observers_pass_drop_and_replace_tags() {
+        use crate::flv::observer::{ITagObserver, TagAction};
+        use crate::flv::tag::Tag;
+        use std::sync::{Arc, Mutex};
+
+        struct Rewriter;
+        impl ITagObserver for Rewriter {
+            fn observe(&mut self, tag: &mut Tag, _: u64) -> TagAction {
+                match tag.tag_type {
+                    TagType::Script => TagAction::Drop,
+                    TagType::Video => {
+                        tag.timestamp += 1000;
+                        TagAction::Pass
+                    }
+                    _ => TagAction::Replace(vec![tag.clone(), tag.clone()]),
+                }
+            }
+        }
+        struct Recorder(Arc<Mutex<Vec<(TagType, u32, u64)>>>);
+        impl ITagObserver for Recorder {
+            fn observe(&mut self, tag: &mut Tag, byte_offset: u64) -> TagAction {
+                self.0.lock().unwrap().push((tag.tag_type.clone(), tag.timestamp, byte_offset));
+                TagAction::Pass
+            }
+        }
+
+        let mut flv = flv_header(0b101);
+        push_tag(&mut flv, 18, 0, &[2, 0, 5, b'o', b'n', b'C', b'u', b'e', 8, 0, 0, 0, 0, 0, 0, 9]);
+        let video_offset = flv.len() as u64;
+        push_tag(&mut flv, 9, 40, &[0x27, 1, 0, 0, 0]);
+        let audio_offset = flv.len() as u64;
+        push_tag(&mut flv, 8, 60, &[0x2F, 0xFF]);
+
+        let seen = Arc::new(Mutex::new(vec![]));
+        let mut decoder = Decoder::new(VecDeque::from(flv));
+        decoder.add_tag_observer(Rewriter);
+        decoder.add_tag_observer(Recorder(seen.clone()));
+        decoder.start().unwrap();
+        decoder.run().unwrap();
+
+        assert_eq!(decoder.tag_index(), 3);
+        assert_eq!(*seen.lock().unwrap(), [
+            (TagType::Video, 1040, video_offset),
+            (TagType::Audio, 60, audio_offset),
+            (TagType::Audio, 60, audio_offset),
+        ]);
+    }
+
+    #[test]
+    fn script_data_covers_all_amf0_types() {
+        use crate::flv::script::ScriptData;
+        use crate::flv::tag::{NormalTagBody, TagBody};
+
+        fn key(out: &mut Vec<u8>, value: &str) {
+            out.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            out.extend_from_slice(value.as_bytes());
+        }
+
+        let mut script = vec![2];
+        key(&mut script, "onMetaData");
+        // the count is 0, the end marker is what counts.
+        script.extend_from_slice(&[8, 0, 0, 0, 0]);
+        key(&mut script, "null");
+        script.push(5);
+        key(&mut script, "undefined");
+        script.push(6);
+        key(&mut script, "unsupported");
+        script.push(13);
+        key(&mut script, "xml");
+        script.extend_from_slice(&[15, 0, 0, 0, 4]);
+        script.extend_from_slice(b"<a/>");
+        key(&mut script, "cue");
+        script.push(16);
+        key(&mut script, "CuePoint");
+        key(&mut script, "time");
+        script.push(0);
+        script.extend_from_slice(&1.5f64.to_be_bytes());
+        script.extend_from_slice(&[0, 0, 9]);
+        // the metadata array itself is object 0, the typed object is object 1.
+        key(&mut script, "again");
+        script.extend_from_slice(&[7, 0, 1]);
+        key(&mut script, "itself");
+        script.extend_from_slice(&[7, 0, 0]);
+        key(&mut script, "after");
+        script.push(1);
+        script.push(1);
+        script.extend_from_slice(&[0, 0, 9]);
+
+        let mut flv = vec![b'F', b'L', b'V', 1, 0b101, 0, 0, 0, 9, 0, 0, 0, 0];
+        push_tag(&mut flv, 18, 0, &script);
+        let mut decoder = Decoder::new(VecDeque::from(flv));
+        decoder.decode_header().unwrap();
+        decoder.drain_u32().unwrap();
+        let TagBody::Normal(NormalTagBody::Script(body)) = decoder.decode_tag().unwrap().tag_body else { panic!("not a script tag") };
+
+        let props = &body.value.properties;
+        assert_eq!(props.len(), 9);
+        assert!(matches!(props[0].value, ScriptData::Null));
+        assert!(matches!(props[1].value, ScriptData::Undefined));
+        assert!(matches!(props[2].value, ScriptData::Unsupported));
+        assert!(matches!(&props[3].value, ScriptData::XmlDocument(xml) if xml.data == "<a/>"));
+        for cue in [&props[4].value, &props[5].value] {
+            let ScriptData::TypedObject(cue) = cue else { panic!("not a typed object") };
+            assert_eq!(cue.class_name.data, "CuePoint");
+            assert!(matches!(cue.properties[0].value, ScriptData::Number(time) if time == 1.5));
+        }
+        assert!(matches!(props[6].value, ScriptData::Reference(0)));
+        // nothing after them was thrown off.
+        assert!(matches!(props[7].value, ScriptData::Boolean(1)));
+
+        // reserved markers have no known size, so the tag is rejected rather than read wrong.
+        let mut script = vec![2];
+        key(&mut script, "onMetaData");
+        script.extend_from_slice(&[8, 0, 0, 0, 1]);
+        key(&mut script, "clip");
+        script.extend_from_slice(&[4, 0, 0, 9]);
+        let mut flv = vec![];
+        push_tag(&mut flv, 18, 0, &script);
+        let mut decoder = Decoder::new(VecDeque::from(flv));
+        assert!(matches!(decoder.decode_tag().unwrap_err(), FlvError::AmfDecode { .. }));
+    }
+
+    #[test]
+    fn amf3_script_data_maps_onto_amf0_values() {
+        use crate::flv::meta::RawMetaData;
+        use crate::flv::script::ScriptData;
+        use crate::flv::tag::{NormalTagBody, TagBody};
+
+        // an inline string, short enough for a single byte U29.
+        fn string(out: &mut Vec<u8>, value: &str) {
+            out.push(((value.len() as u8) << 1) | 1);
+            out.extend_from_slice(value.as_bytes());
+        }
+
+        let mut script = vec![17, 6];
+        string(&mut script, "onMetaData");
+        // an anonymous dynamic object, without sealed members.
+        script.extend_from_slice(&[17, 0x0A, 0x0B, 0x01]);
+        string(&mut script, "duration");
+        script.extend_from_slice(&[4, 0x82, 0x2C]);
+        string(&mut script, "copy");
+        script.extend_from_slice(&[6, 0x00]);
+        string(&mut script, "list");
+        script.extend_from_slice(&[9, 0x05, 0x01, 3, 5]);
+        script.extend_from_slice(&1.5f64.to_be_bytes());
+        string(&mut script, "mixed");
+        script.extend_from_slice(&[9, 0x03]);
+        string(&mut script, "a");
+        script.extend_from_slice(&[1, 0x01, 0]);
+        string(&mut script, "bytes");
+        script.extend_from_slice(&[12, 0x07, 1, 2, 3]);
+        string(&mut script, "ints");
+        script.extend_from_slice(&[13, 0x05, 0]);
+        script.extend_from_slice(&(-1i32).to_be_bytes());
+        script.extend_from_slice(&2i32.to_be_bytes());
+        // the object itself is object 0, the list is object 1.
+        string(&mut script, "same");
+        script.extend_from_slice(&[9, 0x02]);
+        string(&mut script, "dict");
+        script.extend_from_slice(&[17, 0x03, 0, 4, 7, 6]);
+        string(&mut script, "seven");
+        string(&mut script, "cue");
+        script.extend_from_slice(&[10, 0x13]);
+        string(&mut script, "Cue");
+        string(&mut script, "time");
+        script.push(5);
+        script.extend_from_slice(&2.0f64.to_be_bytes());
+        script.push(0x01);
+
+        let mut flv = flv_header(0b101);
+        push_tag(&mut flv, 18, 0, &script);
+        let TagBody::Normal(NormalTagBody::Script(body)) = decode_tags(&mut Decoder::new(VecDeque::from(flv)), 1).remove(0).tag_body else { panic!("not a script tag") };
+        assert_eq!(body.name.data, "onMetaData");
+
+        let meta = RawMetaData::new(body);
+        assert_eq!(meta.try_get_number("duration"), Some(300.0));
+        assert_eq!(meta.try_get_string("copy").as_deref(), Some("duration"));
+        for key in ["list", "same"] {
+            let Some(ScriptData::StrictArray(list)) = meta.try_get(key) else { panic!("not a strict array") };
+            assert!(matches!(list.values[..], [ScriptData::Boolean(1), ScriptData::Number(value)] if value == 1.5));
+        }
+        let Some(ScriptData::EcmaArray(mixed)) = meta.try_get("mixed") else { panic!("not an ecma array") };
+        assert_eq!(mixed.properties.iter().map(|prop| prop.name.data.as_str()).collect::<Vec<_>>(), ["0", "a", ""]);
+        assert!(matches!(meta.try_get("bytes"), Some(ScriptData::ByteArray(bytes)) if bytes == [1, 2, 3]));
+        let Some(ScriptData::StrictArray(ints)) = meta.try_get("ints") else { panic!("not a vector") };
+        assert!(matches!(ints.values[..], [ScriptData::Number(a), ScriptData::Number(b)] if a == -1.0 && b == 2.0));
+        let Some(ScriptData::EcmaArray(dict)) = meta.try_get("dict") else { panic!("not a dictionary") };
+        assert_eq!(dict.properties[0].name.data, "7");
+        let Some(ScriptData::TypedObject(cue)) = meta.try_get("cue") else { panic!("not a typed object") };
+        assert_eq!((cue.class_name.data.as_str(), cue.properties[0].name.data.as_str()), ("Cue", "time"));
+    }
+
+    #[test]
+    fn script_data_round_trips_through_amf0() {
+        use crate::flv::script::*;
+        use crate::flv::tag::{NormalTagBody, TagBody};
+
+        let prop = |key: &str, value: ScriptData| ScriptDataObjectProp { name: script_string(key), value };
+        let end = || prop("", ScriptData::ObjectEndMarker);
+
+        let body = ScriptTagBody {
+            name: script_string("onMetaData"),
+            value: ScriptDataEcmaArray {
+                length: 12,
+                properties: vec![
+                    prop("duration", ScriptData::Number(12.5)),
+                    prop("stereo", ScriptData::Boolean(1)),
+                    prop("encoder", ScriptData::String(script_string("flv-rs"))),
+                    prop("keyframes", ScriptData::Object(ScriptDataObject { properties: vec![
+                        prop("times", ScriptData::StrictArray(ScriptStrictArray { length: 2, values: vec![ScriptData::Number(0.0), ScriptData::Number(2.0)] })),
+                        end(),
+                    ] })),
+                    prop("nothing", ScriptData::Null),
+                    prop("unknown", ScriptData::Undefined),
+                    prop("nested", ScriptData::EcmaArray(ScriptDataEcmaArray { length: 1, properties: vec![prop("a", ScriptData::Unsupported), end()] })),
+                    prop("created", ScriptData::Date(ScriptDataDate { date: 1.6e12, local_time_offset: -60 })),
+                    prop("description", ScriptData::LongString(ScriptDataLongString { length: 70000, data: "x".repeat(70000) })),
+                    prop("xmp", ScriptData::XmlDocument(ScriptDataLongString { length: 4, data: "<a/>".to_string() })),
+                    prop("cue", ScriptData::TypedObject(ScriptDataTypedObject { class_name: script_string("CuePoint"), properties: vec![prop("time", ScriptData::Number(1.0)), end()] })),
+                    prop("blob", ScriptData::ByteArray(vec![0xFF; 300])),
+                    end(),
+                ],
+            },
+        };
+
+        let mut flv = flv_header(0b101);
+        push_tag(&mut flv, 18, 0, &body.serialize().unwrap());
+        let TagBody::Normal(NormalTagBody::Script(parsed)) = decode_tags(&mut Decoder::new(VecDeque::from(flv)), 1).remove(0).tag_body else { panic!("not a script tag") };
+        assert_eq!(parsed, body);
+        assert_eq!(parsed.serialize().unwrap(), body.serialize().unwrap());
+
+        // the end marker is written even if it was left out.
+        let object = ScriptData::Object(ScriptDataObject { properties: vec![prop("a", ScriptData::Null)] });
+        assert_eq!(object.serialize().unwrap(), [3, 0, 1, b'a', 5, 0, 0, 9]);
+        assert!(ScriptData::MovieClip.serialize().is_err());
+    }
+
+    #[test]
+    fn decoder_exposes_typed_metadata() {
+        use crate::flv::meta::{MetaData, RawMetaData};
+        use crate::flv::script::*;
+
+        let name = |data: &str| ScriptDataString { length: data.len() as u16, data: data.to_string() };
+        let number = |key: &str, value: f64| ScriptDataObjectProp { name: name(key), value: ScriptData::Number(value) };
+        let string = |key: &str, value: &str| ScriptDataObjectProp { name: name(key), value: ScriptData::String(name(value)) };
+        let boolean = |key: &str, value: bool| ScriptDataObjectProp { name: name(key), value: ScriptData::Boolean(value as u8) };
+        let keyframes = ScriptDataObjectProp {
+            name: name("keyframes"),
+            value: ScriptData::Object(ScriptDataObject { properties: ["times", "filepositions"].iter().map(|key| ScriptDataObjectProp {
+                name: name(key),
+                value: ScriptData::StrictArray(ScriptStrictArray { length: 1, values: vec![ScriptData::Number(0.0)] }),
+            }).collect() }),
+        };
+        let script = |properties: Vec<ScriptDataObjectProp>| ScriptTagBody {
+            name: name("onMetaData"),
+            value: ScriptDataEcmaArray { length: properties.len() as u32, properties },
+        };
+
+        let metadata = script(vec![
+            number("duration", 12.5), number("width", 1280.0), number("height", 720.0), number("framerate", 30.0),
+            number("videocodecid", 7.0), number("audiocodecid", 10.0), number("audiosamplesize", 16.0),
+            number("filesize", 1234.0), number("lasttimestamp", 12.46), boolean("stereo", true), boolean("hasKeyframes", true),
+            string("encoder", "Lavf60.3.100"), string("metadatacreator", "flvmeta"), string("creationdate", "Mon Jan 1 00:00:00 2024"),
+            keyframes,
+        ]);
+        let mut flv = vec![b'F', b'L', b'V', 1, 0b101, 0, 0, 0, 9, 0, 0, 0, 0];
+        push_tag(&mut flv, 18, 0, &metadata.serialize().unwrap());
+        let mut decoder = Decoder::new(VecDeque::from(flv));
+        assert!(decoder.metadata().is_none());
+        decoder.start().unwrap();
+        decoder.run().unwrap();
+
+        let metadata = decoder.metadata().unwrap();
+        assert_eq!((metadata.width, metadata.height, metadata.duration), (Some(1280), Some(720), Some(12.5)));
+        assert_eq!((metadata.video_codec_id, metadata.audio_codec_id, metadata.audio_samples_size), (Some(7), Some(10), Some(16)));
+        assert_eq!((metadata.file_size, metadata.stereo, metadata.has_keyframes), (Some(1234), Some(true), Some(true)));
+        assert_eq!(metadata.encoder.as_deref(), Some("Lavf60.3.100"));
+        assert_eq!(metadata.metadata_creator.as_deref(), Some("flvmeta"));
+        assert_eq!(metadata.keyframes.as_ref().unwrap().len(), 1);
+        assert_eq!(metadata.audio_delay, None);
+
+        // a negative width, a fractional codec id or a number where a flag belongs is rejected.
+        for (invalid, key) in [(number("width", -1.0), "width"), (number("videocodecid", 7.5), "videocodecid"), (number("stereo", 1.0), "stereo")] {
+            let metadata = MetaData::from(&RawMetaData::new(script(vec![invalid, number("duration", 12.5)])));
+            assert_eq!((metadata.width, metadata.video_codec_id, metadata.stereo), (None, None, None));
+            assert_eq!((metadata.invalid_properties, metadata.duration), (vec![key.to_string()], Some(12.5)));
+        }
+
+        // the remuxer goes through the same validation, so it still takes the valid hints.
+        let mut ctx = RemuxContext::new();
+        ctx.parse_metadata(&RawMetaData::new(script(vec![number("height", 720.0), number("framerate", f64::NAN)])));
+        assert_eq!((ctx.height, ctx.fps), (720.0, 0.0));
+        ctx.parse_metadata(&RawMetaData::new(script(vec![number("height", 720.0), number("framerate", 30.0)])));
+        assert_eq!((ctx.height, ctx.fps), (720.0, 30.0));
+    }
+
+    #[test]
+    fn a_wrongly_typed_metadata_property_leaves_the_others() {
+        use crate::flv::script::*;
+
+        let number = |key: &str, value: f64| ScriptDataObjectProp { name: script_string(key), value: ScriptData::Number(value) };
+        let properties = vec![
+            number("duration", 12.5), number("width", 1280.0), number("height", 720.0), number("framerate", 30.0),
+            ScriptDataObjectProp { name: script_string("audiocodecid"), value: ScriptData::String(script_string("mp4a")) },
+        ];
+        let metadata = ScriptTagBody { name: script_string("onMetaData"), value: ScriptDataEcmaArray { length: 5, properties } };
+
+        let mut flv = flv_header(0b101);
+        push_tag(&mut flv, 18, 0, &metadata.serialize().unwrap());
+        let mut decoder = Decoder::new(VecDeque::from(flv));
+        decoder.start().unwrap();
+        decoder.run().unwrap();
+
+        let metadata = decoder.metadata().unwrap();
+        assert_eq!((metadata.duration, metadata.width, metadata.height, metadata.frame_rate), (Some(12.5), Some(1280), Some(720), Some(30.0)));
+        assert_eq!(metadata.audio_codec_id, None);
+        assert_eq!(metadata.invalid_properties, ["audiocodecid"]);
+    }
+
+    #[test]
+    fn script_tags_come_out_as_timed_events() {
+        use crate::flv::event::ScriptEventKind;
+        use crate::flv::script::*;
+
+        let name = |data: &str| ScriptDataString { length: data.len() as u16, data: data.to_string() };
+        let string = |key: &str, value: &str| ScriptDataObjectProp { name: name(key), value: ScriptData::String(name(value)) };
+        let script = |handler: &str, properties: Vec<ScriptDataObjectProp>| ScriptTagBody {
+            name: name(handler),
+            value: ScriptDataEcmaArray { length: properties.len() as u32, properties },
+        }.serialize().unwrap();
+
+        let mut flv = vec![b'F', b'L', b'V', 1, 0b101, 0, 0, 0, 9, 0, 0, 0, 0];
+        push_tag(&mut flv, 18, 0, &script("onMetaData", vec![]));
+        push_tag(&mut flv, 18, 1500, &script("onCuePoint", vec![
+            string("name", "ad-break"),
+            string("type", "event"),
+            ScriptDataObjectProp { name: name("time"), value: ScriptData::Number(1.5) },
+            ScriptDataObjectProp { name: name("parameters"), value: ScriptData::Object(ScriptDataObject { properties: vec![string("duration", "30")] }) },
+        ]));
+        push_tag(&mut flv, 18, 2000, &script("onFI", vec![string("sd", "01-01-2024"), string("st", "12:00:00.000")]));
+        push_tag(&mut flv, 18, 2500, &script("onTextData", vec![string("text", "hello"), string("language", "en")]));
+
+        let mut decoder = Decoder::new(VecDeque::from(flv));
+        decoder.start().unwrap();
+        decoder.run().unwrap();
+
+        let cue = decoder.consume_event().unwrap().unwrap();
+        assert_eq!((cue.name(), cue.presentation_time), ("onCuePoint", 1500));
+        let ScriptEventKind::CuePoint { name, cue_type, time, parameters } = cue.kind else { panic!("not a cue point") };
+        assert_eq!((name.as_deref(), cue_type.as_deref(), time), (Some("ad-break"), Some("event"), Some(1.5)));
+        assert_eq!(parameters[0].name.data, "duration");
+
+        let timecode = decoder.consume_event().unwrap().unwrap();
+        assert_eq!(timecode.presentation_time, 2000);
+        assert_eq!(timecode.kind, ScriptEventKind::FrameInfo { date: Some("01-01-2024".into()), time: Some("12:00:00.000".into()) });
+
+        let text = decoder.consume_event().unwrap().unwrap();
+        assert!(matches!(text.kind, ScriptEventKind::TextData { text: Some(ref text), .. } if text == "hello"));
+        assert!(decoder.consume_event().unwrap().is_none());
+    }
+
+    #[test]
+    fn xmp_data_is_read_from_on_xmp_data() {
+        use crate::flv::script::*;
+
+        let xmp = r#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+<rdf:Description rdf:about="" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:xmp="http://ns.adobe.com/xap/1.0/" xmp:CreateDate="2024-01-01T12:00:00Z">
+<dc:title><rdf:Alt><rdf:li xml:lang="de">Titel</rdf:li><rdf:li xml:lang="x-default">Title &amp; more</rdf:li></rdf:Alt></dc:title>
+<dc:creator><rdf:Seq><rdf:li>Alice</rdf:li><rdf:li>Bob &#x26; Co</rdf:li></rdf:Seq></dc:creator>
+<dc:rights><rdf:Alt><rdf:li xml:lang="x-default">&#169; 2024 Example</rdf:li></rdf:Alt></dc:rights>
+</rdf:Description></rdf:RDF></x:xmpmeta>"#;
+
+        let body = ScriptTagBody {
+            name: script_string("onXMPData"),
+            value: ScriptDataEcmaArray { length: 1, properties: vec![ScriptDataObjectProp { name: script_string("liveXML"), value: ScriptData::String(script_string(xmp)) }] },
+        };
+        let mut flv = flv_header(0b101);
+        push_tag(&mut flv, 18, 0, &body.serialize().unwrap());
+        let mut decoder = Decoder::new(VecDeque::from(flv));
+        decoder.start().unwrap();
+        decoder.run().unwrap();
+
+        let data = decoder.xmp().unwrap();
+        assert_eq!(data.xmp, xmp);
+        assert_eq!(data.title.as_deref(), Some("Title & more"));
+        assert_eq!(data.creator, ["Alice", "Bob & Co"]);
+        assert_eq!(data.create_date.as_deref(), Some("2024-01-01T12:00:00Z"));
+        assert_eq!(data.rights.as_deref(), Some("\u{a9} 2024 Example"));
+    }
+
+    #[test]
+    fn elementary_frames_are_yielded_without_remuxing() {
+        use crate::flv::frames::{CodecConfig, ElementaryItem, FrameCodec};
+
+        let avc_record = [1, 0x64, 0, 0x1F, 0xFF, 0xE0, 0];
+        let aac_config = [0x12, 0x10];
+        let mut flv = vec![b'F', b'L', b'V', 1, 0b101, 0, 0, 0, 9, 0, 0, 0, 0];
+        push_tag(&mut flv, 9, 0, &[&[0x17, 0, 0, 0, 0][..], &avc_record].concat());
+        push_tag(&mut flv, 8, 0, &[&[0xAF, 0][..], &aac_config].concat());
+        push_tag(&mut flv, 9, 0, &[0x17, 1, 0, 0, 80, 0, 0, 0, 1, 0x65]);
+        push_tag(&mut flv, 8, 0, &[0xAF, 1, 0x21, 0x10]);
+        push_tag(&mut flv, 9, 40, &[0x27, 1, 0xFF, 0xFF, 0xD8, 0, 0, 0, 1, 0x41]);
+        // the same record again is not repeated.
+        push_tag(&mut flv, 9, 80, &[&[0x17, 0, 0, 0, 0][..], &avc_record].concat());
+
+        let items = Decoder::new(VecDeque::from(flv)).into_frames().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(items.len(), 5);
+        assert_eq!(items[0], ElementaryItem::Config(CodecConfig { track_id: 0, codec: FrameCodec::Video(VideoCodecType::Avc1), record: avc_record.to_vec() }));
+        assert_eq!(items[1], ElementaryItem::Config(CodecConfig { track_id: 0, codec: FrameCodec::Audio(AudioCodecType::Aac), record: aac_config.to_vec() }));
+
+        let ElementaryItem::Frame(ref keyframe) = items[2] else { panic!("not a frame") };
+        assert_eq!((keyframe.dts, keyframe.pts, keyframe.keyframe), (0, 80, true));
+        assert_eq!(keyframe.payload, [0, 0, 0, 1, 0x65]);
+        let ElementaryItem::Frame(ref audio) = items[3] else { panic!("not a frame") };
+        assert_eq!((audio.codec, audio.payload.as_slice()), (FrameCodec::Audio(AudioCodecType::Aac), &[0x21, 0x10][..]));
+        let ElementaryItem::Frame(ref interframe) = items[4] else { panic!("not a frame") };
+        assert_eq!((interframe.dts, interframe.pts, interframe.keyframe), (40, 0, false));
+    }
+
+    #[test]
+    fn audio_only_and_video_only_outputs_have_a_single_track() {
+        use crate::flv::script::*;
+        use crate::fmpeg::remux_context::TrackType;
+
+        let properties = vec![ScriptDataObjectProp { name: script_string("audiocodecid"), value: ScriptData::Number(10.0) }];
+        let metadata = ScriptTagBody { name: script_string("onMetaData"), value: ScriptDataEcmaArray { length: 1, properties } };
+
+        // an aac radio stream, or a stream with both, where the video is left out on purpose.
+        let stream = |flags: u8| {
+            let mut flv = flv_header(flags);
+            push_tag(&mut flv, 18, 0, &metadata.serialize().unwrap());
+            push_tag(&mut flv, 8, 0, &[0xAF, 0, 0x12, 0x10]);
+            if flags & 1 != 0 {
+                push_tag(&mut flv, 9, 0, &[0x17, 0, 0, 0, 0, 1, 0x64, 0, 0x1F, 0xFF, 0xE0, 0]);
+                push_tag(&mut flv, 9, 0, &[0x17, 1, 0, 0, 0, 0, 0, 0, 1, 0x65]);
+            }
+            for timestamp in [0, 23, 46] {
+                push_tag(&mut flv, 8, timestamp, &[0xAF, 1, 0x21, 0x10]);
+            }
+            flv
+        };
+
+        for (flags, disable_video) in [(0b100, false), (0b101, true)] {
+            let mut decoder = Decoder::new(VecDeque::from(stream(flags)));
+            if disable_video {
+                decoder.set_track_enabled(TrackType::Video, false);
+            } else {
+                // no video shows up, so the initialization segment waits for the whole probe.
+                decoder.set_probe_limit(4);
+            }
+            decoder.start().unwrap();
+            decoder.run().unwrap();
+
+            let RemuxedData::Header(header) = decoder.consume().unwrap() else { panic!("no initialization segment") };
+            assert_eq!(header.windows(4).filter(|window| window == b"trak").count(), 1);
+            assert!(header.windows(4).any(|window| window == b"mp4a"));
+            assert!(matches!(decoder.consume().unwrap(), RemuxedData::Audio(_)));
+
+            let codecs = decoder.try_get_codecs().unwrap();
+            assert_eq!((codecs.audio.as_deref(), codecs.video.as_deref()), (Some("mp4a.40.2"), None));
+            assert_eq!(codecs.mime_type(), "audio/mp4; codecs=\"mp4a.40.2\"");
+            // the blocking calls return as well, instead of waiting for a video track forever.
+            assert_eq!(decoder.get_codecs().unwrap(), codecs);
+            assert!(decoder.get_codec_conf().is_err());
+        }
+    }
+
+    #[test]
+    fn tracks_left_out_of_the_flv_header_still_get_a_trak() {
+        use crate::fmpeg::remux_context::{ProbeWarning, TrackType};
+
+        // the header announces audio only, but the stream has video as well, which starts a little later.
+        let mut flv = flv_header(0b100);
+        push_tag(&mut flv, 8, 0, &[0xAF, 0, 0x12, 0x10]);
+        push_tag(&mut flv, 8, 0, &[0xAF, 1, 0x21, 0x10]);
+        push_tag(&mut flv, 8, 23, &[0xAF, 1, 0x21, 0x10]);
+        push_tag(&mut flv, 9, 40, &[0x17, 0, 0, 0, 0, 1, 0x64, 0, 0x1F, 0xFF, 0xE0, 0]);
+        push_tag(&mut flv, 9, 40, &[0x17, 1, 0, 0, 0, 0, 0, 0, 1, 0x65]);
+        push_tag(&mut flv, 9, 73, &[0x27, 1, 0, 0, 0, 0, 0, 0, 1, 0x41]);
+        push_tag(&mut flv, 8, 46, &[0xAF, 1, 0x21, 0x10]);
+
+        let mut decoder = Decoder::new(VecDeque::from(flv));
+        decoder.start().unwrap();
+        decoder.run().unwrap();
+
+        assert_eq!(decoder.probe_warnings(), [ProbeWarning::UnannouncedTrack(TrackType::Video)]);
+        let RemuxedData::Header(header) = decoder.consume().unwrap() else { panic!("no initialization segment") };
+        assert_eq!(header.windows(4).filter(|window| window == b"trak").count(), 2);
+        assert!(header.windows(4).any(|window| window == b"avc1"));
+        // the audio which arrived before the video is held back, not dropped.
+        let output = std::iter::from_fn(|| decoder.consume().ok()).collect::<Vec<_>>();
+        assert!(output.iter().any(|data| matches!(data, RemuxedData::Video(_))));
+        assert_eq!(output.iter().filter(|data| matches!(data, RemuxedData::Audio(_))).count(), 2);
+        assert_eq!(decoder.try_get_codecs().unwrap().video.as_deref(), Some("avc1.64001f"));
+    }
+
+    #[test]
+    fn tracks_and_codecs_are_probed_from_the_tags() {
+        use crate::flv::script::*;
+        use crate::fmpeg::remux_context::{ProbeWarning, TrackType};
+
+        let properties = vec![ScriptDataObjectProp { name: script_string("audiocodecid"), value: ScriptData::Number(2.0) }];
+        let metadata = ScriptTagBody { name: script_string("onMetaData"), value: ScriptDataEcmaArray { length: 1, properties } };
+
+        // the header announces video only and the metadata mp3, but the stream is aac audio.
+        let mut flv = flv_header(0b001);
+        push_tag(&mut flv, 18, 0, &metadata.serialize().unwrap());
+        push_tag(&mut flv, 8, 0, &[0xAF, 0, 0x12, 0x10]);
+        for timestamp in [0, 23, 46, 69, 92] {
+            push_tag(&mut flv, 8, timestamp, &[0xAF, 1, 0x21, 0x10]);
+        }
+
+        let mut decoder = Decoder::new(VecDeque::from(flv));
+        decoder.set_probe_limit(4);
+        decoder.start().unwrap();
+        decoder.run().unwrap();
+
+        assert_eq!(decoder.probe_warnings(), [
+            ProbeWarning::UnannouncedTrack(TrackType::Audio),
+            ProbeWarning::AudioCodecMismatch { declared: AudioCodecType::Mp3, found: AudioCodecType::Aac },
+            ProbeWarning::MissingTrack(TrackType::Video),
+        ]);
+        let RemuxedData::Header(header) = decoder.consume().unwrap() else { panic!("no initialization segment") };
+        assert_eq!(header.windows(4).filter(|window| window == b"trak").count(), 1);
+        // the frames which arrived while probing are held back, not dropped. the last one waits for its duration.
+        for _ in 0..4 {
+            assert!(matches!(decoder.consume().unwrap(), RemuxedData::Audio(_)));
+        }
+        assert!(decoder.consume().is_err());
+    }
+
+    #[test]
+    fn changed_sequence_headers_reinitialise_the_output() {
+        use crate::flv::script::*;
+        use crate::fmpeg::remux_context::ProbeWarning;
+
+        let properties = vec![ScriptDataObjectProp { name: script_string("audiocodecid"), value: ScriptData::Number(10.0) }];
+        let metadata = ScriptTagBody { name: script_string("onMetaData"), value: ScriptDataEcmaArray { length: 1, properties } };
+
+        // aac at 44.1kHz, the same sequence header again, then the encoder restarts at 48kHz.
+        let mut flv = flv_header(0b100);
+        push_tag(&mut flv, 18, 0, &metadata.serialize().unwrap());
+        push_tag(&mut flv, 8, 0, &[0xAF, 0, 0x12, 0x10]);
+        push_tag(&mut flv, 8, 0, &[0xAF, 1, 0x21, 0x10]);
+        push_tag(&mut flv, 8, 23, &[0xAF, 1, 0x21, 0x10]);
+        push_tag(&mut flv, 8, 46, &[0xAF, 0, 0x12, 0x10]);
+        push_tag(&mut flv, 8, 46, &[0xAF, 1, 0x21, 0x10]);
+        push_tag(&mut flv, 8, 69, &[0xAF, 0, 0x11, 0x90]);
+        push_tag(&mut flv, 8, 69, &[0xAF, 1, 0x21, 0x10]);
+        push_tag(&mut flv, 8, 90, &[0xAF, 1, 0x21, 0x10]);
+
+        let mut decoder = Decoder::new(VecDeque::from(flv));
+        decoder.set_probe_limit(2);
+        decoder.start().unwrap();
+        decoder.run().unwrap();
+        let mut output = vec![];
+        while let Ok(data) = decoder.consume() {
+            output.push(data);
+        }
+
+        let kinds = output.iter().map(|data| match data {
+            RemuxedData::Header(_) => 'H',
+            RemuxedData::Audio(_) => 'A',
+            RemuxedData::Reinitialization(_) => 'R',
+            _ => '?',
+        }).collect::<String>();
+        // the three samples before the change are all written out in front of it.
+        assert_eq!(kinds, "HAAARHA");
+
+        let RemuxedData::Reinitialization(ref codecs) = output[4] else { unreachable!() };
+        assert_eq!(codecs.audio.as_deref(), Some("mp4a.40.2"));
+        let sample_rate = |header: &RemuxedData, rate: u16| matches!(header, RemuxedData::Header(data) if data.windows(4).any(|window| window == [(rate >> 8) as u8, rate as u8, 0, 0]));
+        assert!(sample_rate(&output[0], 44100) && sample_rate(&output[5], 48000));
+
+        // a change to opus comes with its own sequence header, so it is no mismatch with the metadata.
+        // mp3 frames on the opus track come without one though.
+        let mut flv = flv_header(0b100);
+        push_tag(&mut flv, 18, 0, &metadata.serialize().unwrap());
+        push_tag(&mut flv, 8, 0, &[0xAF, 0, 0x12, 0x10]);
+        push_tag(&mut flv, 8, 0, &[0xAF, 1, 0x21, 0x10]);
+        let mut body = vec![0x90];
+        body.extend_from_slice(b"Opus");
+        body.extend_from_slice(b"OpusHead");
+        body.extend_from_slice(&[1, 2, 0x38, 0x01, 0x80, 0xBB, 0, 0, 0, 0, 0]);
+        push_tag(&mut flv, 8, 23, &body);
+        let mut body = vec![0x91];
+        body.extend_from_slice(b"Opus");
+        body.extend_from_slice(&[0xF8, 0xFF, 0xFE]);
+        push_tag(&mut flv, 8, 23, &body);
+        push_tag(&mut flv, 8, 43, &[0x2F, 0xFF, 0xFB, 0x90, 0x00]);
+        push_tag(&mut flv, 8, 69, &[0x2F, 0xFF, 0xFB, 0x90, 0x00]);
+
+        let mut decoder = Decoder::new(VecDeque::from(flv));
+        decoder.set_probe_limit(2);
+        decoder.start().unwrap();
+        decoder.run().unwrap();
+        assert!(std::iter::from_fn(|| decoder.consume().ok()).any(|data| matches!(data, RemuxedData::Reinitialization(_))));
+        assert_eq!(decoder.probe_warnings(), [ProbeWarning::AudioCodecMismatch { declared: AudioCodecType::Opus, found: AudioCodecType::Mp3 }]);
+    }
+
+    #[test]
+    fn h264_sps_gives_the_picture_size_and_frame_rate() {
+        use crate::fmpeg::sps::SequenceParameterSet;
+
+        // high profile level 3.1, 1920x1088 cropped to 1080, square samples, 30000/1001 fps.
+        let sps = [
+            0x67, 0x64, 0x00, 0x1F, 0xAC, 0xD9, 0x40, 0x78, 0x02, 0x27, 0xE5, 0xC0,
+            0x44, 0x00, 0x00, 0x0F, 0xA4, 0x00, 0x03, 0xA9, 0x82, 0x10,
+        ];
+        let parsed = SequenceParameterSet::parse(&sps).unwrap();
+        assert_eq!((parsed.profile_idc, parsed.level_idc, parsed.chroma_format_idc), (100, 31, 1));
+        assert_eq!((parsed.width, parsed.height, parsed.sample_aspect_ratio), (1920, 1080, Some((1, 1))));
+        assert!((parsed.frame_rate.unwrap() - 29.97).abs() < 0.01);
+
+        // without any metadata, the initialization segment takes the size from the SPS.
+        let mut record = vec![1, 0x64, 0x00, 0x1F, 0xFF, 0xE1, 0, sps.len() as u8];
+        record.extend_from_slice(&sps);
+        record.extend_from_slice(&[1, 0, 4, 0x68, 0xEB, 0xE3, 0xCB]);
+        assert_eq!(SequenceParameterSet::from_avcc_record(&record).unwrap(), parsed);
+
+        let mut flv = flv_header(0b001);
+        push_tag(&mut flv, 9, 0, &[&[0x17, 0, 0, 0, 0][..], &record].concat());
+        push_tag(&mut flv, 9, 0, &[0x17, 1, 0, 0, 0, 0, 0, 0, 1, 0x65]);
+        let mut decoder = Decoder::new(VecDeque::from(flv));
+        decoder.set_probe_limit(2);
+        decoder.start().unwrap();
+        decoder.run().unwrap();
+
+        let RemuxedData::Header(header) = decoder.consume().unwrap() else { panic!("no initialization segment") };
+        // the track header has the size in 16.16 fixed point.
+        assert!(header.windows(8).any(|window| window == [0x07, 0x80, 0, 0, 0x04, 0x38, 0, 0]));
+    }
+
+    #[test]
+    fn each_video_track_takes_the_size_of_its_own_sps() {
+        use crate::fmpeg::parser::{Avc1ParseResult, VideoParseResult};
+
+        // high profile 1920x1080 on track 0, baseline 640x480 on track 1.
+        let record = |sps: &[u8]| {
+            let mut record = vec![1, sps[1], sps[2], sps[3], 0xFF, 0xE1, 0, sps.len() as u8];
+            record.extend_from_slice(sps);
+            record.extend_from_slice(&[1, 0, 4, 0x68, 0xEB, 0xE3, 0xCB]);
+            VideoParseResult::Avc1(Avc1ParseResult::AvcSequenceHeader(VecDeque::from(record)))
+        };
+        let full_hd = [
+            0x67, 0x64, 0x00, 0x1F, 0xAC, 0xD9, 0x40, 0x78, 0x02, 0x27, 0xE5, 0xC0,
+            0x44, 0x00, 0x00, 0x0F, 0xA4, 0x00, 0x03, 0xA9, 0x82, 0x10,
+        ];
+        let vga = [0x67, 0x42, 0xC0, 0x1E, 0xDA, 0x02, 0x80, 0xF6, 0x40];
+
+        let mut ctx = RemuxContext::new();
+        ctx.configure_video_track(0, &record(&full_hd)).unwrap();
+        ctx.configure_video_track(1, &record(&vga)).unwrap();
+        assert_eq!((ctx.video_size(0), ctx.video_size(1)), ((1920.0, 1080.0), (640.0, 480.0)));
+
+        // the tkhd takes 16.16 fixed point, the avc1 sample entry whole pixels.
+        let moov = Encoder::encode_moov(&ctx).unwrap().serialize();
+        for (width, height) in [(1920u16, 1080u16), (640, 480)] {
+            let [w0, w1] = width.to_be_bytes();
+            let [h0, h1] = height.to_be_bytes();
+            assert!(moov.windows(8).any(|window| window == [w0, w1, 0, 0, h0, h1, 0, 0]));
+            assert!(moov.windows(4).any(|window| window == [w0, w1, h0, h1]));
+        }
+    }
+
+
+    #[test]
+    fn audio_and_video_decode_times_share_the_timeline() {
+        use crate::fmpeg::parser::parse_timescale;
+
+        // joined mid-way, the first tags are stamped 5s.
+        let mut flv = vec![b'F', b'L', b'V', 1, 0b101, 0, 0, 0, 9, 0, 0, 0, 0];
+        push_tag(&mut flv, 9, 5000, &[0x17, 0, 0, 0, 0, 1, 0x64, 0, 0x1F, 0xFF, 0xE0, 0]);
+        push_tag(&mut flv, 8, 5000, &[0xAF, 0, 0x12, 0x10]);
+        push_tag(&mut flv, 9, 5000, &[0x17, 1, 0, 0, 0, 0, 0, 0, 1, 0x65]);
+        push_tag(&mut flv, 8, 5000, &[0xAF, 1, 0x21, 0x10]);
+        push_tag(&mut flv, 8, 5023, &[0xAF, 1, 0x21, 0x10]);
+        push_tag(&mut flv, 9, 5040, &[0x27, 1, 0, 0, 0, 0, 0, 0, 1, 0x41]);
+
+        let mut decoder = Decoder::new(VecDeque::from(flv));
+        decoder.start().unwrap();
+        decoder.run().unwrap();
+
+        let tfdt = |fragment: &[u8]| {
+            let at = fragment.windows(4).position(|window| window == b"tfdt").unwrap() + 8;
+            u32::from_be_bytes(fragment[at..at + 4].try_into().unwrap())
+        };
+        let (mut audio, mut video) = (None, None);
+        while let Ok(data) = decoder.consume() {
+            match data {
+                RemuxedData::Audio(fragment) => { audio.get_or_insert(tfdt(&fragment)); }
+                RemuxedData::Video(fragment) => { video.get_or_insert(tfdt(&fragment)); }
+                _ => {}
+            }
+        }
+        assert_eq!(audio, Some(parse_timescale(5000)));
+        assert_eq!(video, audio);
+    }
+
+    #[test]
+    fn seeking_drops_the_rebase_offset() {
+        use crate::flv::meta::KeyframeIndex;
+        use crate::flv::observer::{ITagObserver, TagAction};
+        use crate::flv::tag::Tag;
+        use crate::flv::timestamp::TimestampPolicy;
+        use std::sync::{Arc, Mutex};
+
+        struct Recorder(Arc<Mutex<Vec<u32>>>);
+        impl ITagObserver for Recorder {
+            fn observe(&mut self, tag: &mut Tag, _: u64) -> TagAction {
+                self.0.lock().unwrap().push(tag.timestamp);
+                TagAction::Pass
+            }
+        }
+
+        // the recording restarts from 0 after 1s.
+        let mut flv = vec![b'F', b'L', b'V', 1, 0b101, 0, 0, 0, 9, 0, 0, 0, 0];
+        let mut positions = vec![];
+        for timestamp in [0, 500, 1000, 0, 500] {
+            positions.push(flv.len() as u64);
+            push_tag(&mut flv, 8, timestamp, &[0x2F, 0xFF]);
+        }
+
+        let seen = Arc::new(Mutex::new(vec![]));
+        let mut decoder = Decoder::from_seekable_reader(std::io::Cursor::new(flv));
+        decoder.set_timestamp_policy(TimestampPolicy::Rebase);
+        decoder.set_keyframes(KeyframeIndex { times: vec![0.0, 0.5], file_positions: positions[..2].to_vec() });
+        decoder.add_tag_observer(Recorder(seen.clone()));
+        decoder.start().unwrap();
+        decoder.run().unwrap();
+        while !decoder.is_source_exhausted() {
+            decoder.continue_decoding().unwrap();
+        }
+        assert_eq!(*seen.lock().unwrap(), [0, 500, 1000, 1500, 2000]);
+
+        // the target is a timestamp in the file, so the tags after it are not shifted by the earlier break.
+        seen.lock().unwrap().clear();
+        assert_eq!(decoder.seek_to(600).unwrap(), 500);
+        while !decoder.is_source_exhausted() {
+            decoder.continue_decoding().unwrap();
+        }
+        assert_eq!(*seen.lock().unwrap(), [500, 1000, 1500, 2000]);
+    }
+
+}